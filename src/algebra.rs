@@ -1,11 +1,21 @@
 use std::ops;
 use std::f64::consts::PI;
 
+#[derive(Clone,Copy,PartialEq,Debug)]
 pub enum Angle {
     Radians(f64),
     Degrees(f64)
 }
 
+impl Angle {
+    pub fn to_radians(self) -> f64 {
+        match self {
+            Angle::Radians(x) => x,
+            Angle::Degrees(x) => x / 180.0 * PI
+        }
+    }
+}
+
 #[derive(Clone,PartialEq,Debug)]
 pub struct Vec4 {
     pub x: f64,
@@ -74,6 +84,58 @@ impl Vec4 {
         obj
     }
 
+    /// Turns a direction into the point it leads to from the origin.
+    pub fn into_point(mut self) -> Self {
+        self.w = 1.0;
+        self
+    }
+
+    /// Turns a point into the direction leading to it from the origin.
+    pub fn to_direction(&self) -> Self {
+        Vec4::new(self.x, self.y, self.z, 0.0)
+    }
+
+    /// Reflects the direction `d` at a surface with the normalized normal `n`.
+    pub fn reflect(d: &Vec4, n: &Vec4) -> Self {
+        d.clone() - (2.0 * Vec4::dot(d, n)) * n.clone()
+    }
+
+    /// Refracts the normalized direction `d` at a surface with the normalized
+    /// normal `n` facing against `d`. `eta` is the ratio of the refractive
+    /// indices of the medium `d` comes from and the medium it enters.
+    /// Returns `None` in case of total internal reflection.
+    pub fn refract(d: &Vec4, n: &Vec4, eta: f64) -> Option<Self> {
+        let cos_i = -Vec4::dot(d, n);
+        let sin2_t = eta * eta * (1.0 - cos_i * cos_i);
+        if sin2_t > 1.0 {
+            return None;
+        }
+
+        let cos_t = (1.0 - sin2_t).sqrt();
+        Some(eta * d.clone() + (eta * cos_i - cos_t) * n.clone())
+    }
+
+    /// Generates two vectors that form an orthonormal basis together with
+    /// the normalized vector `n`, see Duff et al.: "Building an Orthonormal
+    /// Basis, Revisited".
+    pub fn orthonormal_basis(n: &Vec4) -> (Vec4, Vec4) {
+        let sign = 1.0f64.copysign(n.z);
+        let a = -1.0 / (sign + n.z);
+        let b = n.x * n.y * a;
+        (Vec4::new(1.0 + sign * n.x * n.x * a, sign * b, -sign * n.x, 0.0),
+         Vec4::new(b, sign + n.y * n.y * a, -n.y, 0.0))
+    }
+
+    /// Returns the x, y or z component for axis 0, 1 or 2 respectively.
+    pub fn component(&self, axis: usize) -> f64 {
+        match axis {
+            0 => self.x,
+            1 => self.y,
+            2 => self.z,
+            _ => panic!("Axis {} does not exist in 3D space.", axis)
+        }
+    }
+
     /// TODO
     pub fn epsilon_compare(a: Self, b: Self, epsilon: f64) -> bool {
         if     (a.x-b.x).abs() > epsilon
@@ -319,12 +381,7 @@ impl Mat4 {
 
     /// TODO
     pub fn perspective(fovy: Angle, aspect: f64, z_near: f64, z_far: f64) -> Self {
-        let fovy_radians = match fovy {
-            Angle::Radians(x) => x,
-            Angle::Degrees(x) => x / 180.0 * PI
-        };
-
-        let tan_half_fovy = (fovy_radians / 2.0).tan();
+        let tan_half_fovy = (fovy.to_radians() / 2.0).tan();
 
         let mut m = Mat4::zeros();
         m.data[0] = 1.0 / (aspect * tan_half_fovy);
@@ -334,6 +391,19 @@ impl Mat4 {
         m.data[11] = -(2.0 * z_far * z_near) / (z_far - z_near);
         m
     }
+
+    /// Parallel projection of the box between `left`, `right`, `bottom`, `top`,
+    /// `-z_near` and `-z_far` in eye space onto normalized device coordinates.
+    pub fn orthographic(left: f64, right: f64, bottom: f64, top: f64, z_near: f64, z_far: f64) -> Self {
+        let mut m = Mat4::identity();
+        m.data[0] = 2.0 / (right - left);
+        m.data[5] = 2.0 / (top - bottom);
+        m.data[10] = -2.0 / (z_far - z_near);
+        m.data[3] = -(right + left) / (right - left);
+        m.data[7] = -(top + bottom) / (top - bottom);
+        m.data[11] = -(z_far + z_near) / (z_far - z_near);
+        m
+    }
 }
 
 impl ops::Mul<Mat4> for f64 {
@@ -392,6 +462,109 @@ impl ops::Mul<Vec4> for Mat4 {
     }
 }
 
+/// Axis-aligned bounding box spanned by two points.
+#[derive(Clone,PartialEq,Debug)]
+pub struct Aabb {
+    pub min: Vec4,
+    pub max: Vec4
+}
+
+impl Aabb {
+    /// Generates an empty box that contains no point at all.
+    /// Growing it by a point yields a box containing only that point.
+    pub fn empty() -> Self {
+        Aabb {
+            min: Vec4::new(f64::INFINITY, f64::INFINITY, f64::INFINITY, 1.0),
+            max: Vec4::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY, 1.0)
+        }
+    }
+
+    /// Generates the smallest box containing all given points.
+    pub fn from_points(points: &[&Vec4]) -> Self {
+        let mut aabb = Aabb::empty();
+        for point in points {
+            aabb.grow(point);
+        }
+        aabb
+    }
+
+    /// Enlarges the box so that it contains the given point.
+    pub fn grow(&mut self, p: &Vec4) {
+        self.min.x = self.min.x.min(p.x);
+        self.min.y = self.min.y.min(p.y);
+        self.min.z = self.min.z.min(p.z);
+        self.max.x = self.max.x.max(p.x);
+        self.max.y = self.max.y.max(p.y);
+        self.max.z = self.max.z.max(p.z);
+    }
+
+    /// Generates the smallest box containing both boxes.
+    pub fn union(a: &Aabb, b: &Aabb) -> Self {
+        let mut aabb = a.clone();
+        aabb.grow(&b.min);
+        aabb.grow(&b.max);
+        aabb
+    }
+
+    /// Returns true if the box does not contain any point.
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+    }
+
+    pub fn centroid(&self) -> Vec4 {
+        Vec4::new((self.min.x + self.max.x) / 2.0,
+                  (self.min.y + self.max.y) / 2.0,
+                  (self.min.z + self.max.z) / 2.0,
+                  1.0)
+    }
+
+    /// Surface area of the box, empty boxes have none.
+    pub fn surface_area(&self) -> f64 {
+        if self.is_empty() {
+            return 0.0;
+        }
+
+        let dx = self.max.x - self.min.x;
+        let dy = self.max.y - self.min.y;
+        let dz = self.max.z - self.min.z;
+        2.0 * (dx * dy + dy * dz + dz * dx)
+    }
+
+    /// Slab test against a ray given by its origin and the component-wise
+    /// inverse of its direction. Returns the distance at which the ray enters
+    /// the box if that happens within `[0, t_max]`.
+    pub fn intersect(&self, origin: &Vec4, inverse_direction: &Vec4, t_max: f64) -> Option<f64> {
+        // Widen the far distance by a few ulps so that rounding errors can
+        // never make us miss triangles that lie exactly on the box' border.
+        let padding = 1.0 + 4.0 * f64::EPSILON;
+
+        let mut t0 = 0.0;
+        let mut t1 = t_max;
+
+        for axis in 0..3 {
+            let inverse = inverse_direction.component(axis);
+            let mut near = (self.min.component(axis) - origin.component(axis)) * inverse;
+            let mut far = (self.max.component(axis) - origin.component(axis)) * inverse;
+            if near > far {
+                std::mem::swap(&mut near, &mut far);
+            }
+            far *= padding;
+
+            if near > t0 {
+                t0 = near;
+            }
+            if far < t1 {
+                t1 = far;
+            }
+            if t0 > t1 {
+                return None;
+            }
+        }
+
+        Some(t0)
+    }
+}
+
 #[test]
 fn test_vec4_cross() {
     let a = Vec4 { x: 3.0, y: -3.0, z: 1.0, w: 0.0 };
@@ -400,6 +573,56 @@ fn test_vec4_cross() {
     assert_eq!(res, Vec4 { x: -15.0, y: -2.0, z: 39.0, w: 0.0 });
 }
 
+#[test]
+fn test_vec4_reflect() {
+    let d = Vec4::new(1.0, -1.0, 0.0, 0.0);
+    let n = Vec4::new(0.0, 1.0, 0.0, 0.0);
+    assert_eq!(Vec4::reflect(&d, &n), Vec4::new(1.0, 1.0, 0.0, 0.0));
+}
+
+#[test]
+fn test_vec4_refract() {
+    let n = Vec4::new(0.0, 1.0, 0.0, 0.0);
+
+    // Perpendicular rays are not bent at all.
+    let d = Vec4::new(0.0, -1.0, 0.0, 0.0);
+    assert_eq!(Vec4::refract(&d, &n, 1.0 / 1.5), Some(d.clone()));
+
+    // Snell's law: sin(theta_i) * eta == sin(theta_t)
+    let d = Vec4::new(1.0, -1.0, 0.0, 0.0).normalize();
+    let t = Vec4::refract(&d, &n, 1.0 / 1.5).unwrap();
+    assert!((Vec4::dot(&t, &t) - 1.0).abs() < 1e-12);
+    assert!((t.x - d.x / 1.5).abs() < 1e-12);
+
+    // Leaving glass at 45 degrees exceeds the critical angle of ~41.8 degrees.
+    assert!(Vec4::refract(&d, &n, 1.5).is_none());
+}
+
+#[test]
+fn test_vec4_orthonormal_basis() {
+    for n in &[Vec4::new(0.0, 0.0, 1.0, 0.0), Vec4::new(0.0, 0.0, -1.0, 0.0), Vec4::new(1.0, 2.0, -3.0, 0.0).normalize()] {
+        let (t, b) = Vec4::orthonormal_basis(n);
+        assert!((Vec4::dot(&t, &t) - 1.0).abs() < 1e-12);
+        assert!((Vec4::dot(&b, &b) - 1.0).abs() < 1e-12);
+        assert!(Vec4::dot(&t, &b).abs() < 1e-12);
+        assert!(Vec4::dot(&t, n).abs() < 1e-12);
+        assert!(Vec4::dot(&b, n).abs() < 1e-12);
+    }
+}
+
+#[test]
+fn test_vec4_point_direction() {
+    let direction = Vec4::new(1.0, 2.0, 3.0, 0.0);
+    assert_eq!(direction.clone().into_point(), Vec4::new(1.0, 2.0, 3.0, 1.0));
+    assert_eq!(direction.clone().into_point().to_direction(), direction);
+}
+
+#[test]
+fn test_angle_to_radians() {
+    assert_eq!(Angle::Radians(1.5).to_radians(), 1.5);
+    assert!((Angle::Degrees(90.0).to_radians() - PI / 2.0).abs() < 1e-12);
+}
+
 #[test]
 fn test_vec4_normalize() {
     let x = Vec4 { x: 0.0, y: 3.0, z: 0.0, w: 0.0 };
@@ -580,3 +803,59 @@ fn test_mat4_perspective() {
     ]);
     assert!(Mat4::epsilon_compare(&projection_matrix, &reference_projection_matrix, 1e-6f64));
 }
+
+#[test]
+fn test_mat4_orthographic() {
+    let projection_matrix = Mat4::orthographic(-4.0, 4.0, -3.0, 3.0, 1.0, 100.0);
+    let reference_projection_matrix = Mat4::new([
+        0.250000, 0.000000, 0.000000, 0.000000,
+        0.000000, 0.333333, 0.000000, 0.000000,
+        0.000000, 0.000000, -0.020202, -1.020202,
+        0.000000, 0.000000, 0.000000, 1.000000
+    ]);
+    assert!(Mat4::epsilon_compare(&projection_matrix, &reference_projection_matrix, 1e-6f64));
+
+    let projection_matrix = Mat4::orthographic(0.0, 640.0, 0.0, 480.0, -1.0, 1.0);
+    let reference_projection_matrix = Mat4::new([
+        0.003125, 0.000000, 0.000000, -1.000000,
+        0.000000, 0.004167, 0.000000, -1.000000,
+        0.000000, 0.000000, -1.000000, 0.000000,
+        0.000000, 0.000000, 0.000000, 1.000000
+    ]);
+    assert!(Mat4::epsilon_compare(&projection_matrix, &reference_projection_matrix, 1e-6f64));
+}
+
+#[test]
+fn test_aabb_grow_and_union() {
+    let a = Aabb::from_points(&[&Vec4::new(0.0, 1.0, 2.0, 1.0), &Vec4::new(-1.0, 3.0, 0.0, 1.0)]);
+    assert_eq!(a.min, Vec4::new(-1.0, 1.0, 0.0, 1.0));
+    assert_eq!(a.max, Vec4::new(0.0, 3.0, 2.0, 1.0));
+    assert_eq!(a.surface_area(), 2.0 * (2.0 + 4.0 + 2.0));
+
+    let b = Aabb::from_points(&[&Vec4::new(5.0, 0.0, 0.0, 1.0)]);
+    let c = Aabb::union(&a, &b);
+    assert_eq!(c.min, Vec4::new(-1.0, 0.0, 0.0, 1.0));
+    assert_eq!(c.max, Vec4::new(5.0, 3.0, 2.0, 1.0));
+
+    assert!(Aabb::empty().is_empty());
+    assert_eq!(Aabb::empty().surface_area(), 0.0);
+    assert_eq!(Aabb::union(&Aabb::empty(), &a), a);
+}
+
+#[test]
+fn test_aabb_intersect() {
+    let aabb = Aabb::from_points(&[&Vec4::new(-1.0, -1.0, -1.0, 1.0), &Vec4::new(1.0, 1.0, 1.0, 1.0)]);
+    let origin = Vec4::new(0.0, 0.0, 10.0, 1.0);
+
+    // Straight down the negative z-axis, the x and y components are infinite.
+    let inverse_direction = Vec4::new(1.0 / 0.0, 1.0 / 0.0, -1.0, 0.0);
+    let t = aabb.intersect(&origin, &inverse_direction, f64::INFINITY).unwrap();
+    assert!((t - 9.0).abs() < 1e-12);
+
+    // The box lies beyond the maximum distance.
+    assert!(aabb.intersect(&origin, &inverse_direction, 5.0).is_none());
+
+    // Pointing away from the box.
+    let inverse_direction = Vec4::new(1.0 / 0.0, 1.0 / 0.0, 1.0, 0.0);
+    assert!(aabb.intersect(&origin, &inverse_direction, f64::INFINITY).is_none());
+}
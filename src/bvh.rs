@@ -0,0 +1,300 @@
+use algebra::{ Aabb, Vec4 };
+use mesh::Mesh;
+
+/// Leafs with this many faces or less are never split any further.
+const MIN_LEAF_SIZE: usize = 2;
+
+/// Leafs with more faces than this are split even if the SAH advises against it.
+const MAX_LEAF_SIZE: usize = 16;
+
+/// Number of buckets the centroids are sorted into when searching for the best split.
+const SAH_BUCKETS: usize = 12;
+
+/// Cost of visiting an interior node relative to the cost of a single triangle test.
+const TRAVERSAL_COST: f64 = 1.0;
+
+#[derive(Clone,Debug)]
+enum BvhNodeKind {
+    /// References `count` entries in `Bvh::face_indices` starting at `first`.
+    Leaf { first: usize, count: usize },
+    /// Indices of the two child nodes and the axis the faces were split along.
+    Interior { left: usize, right: usize, axis: usize }
+}
+
+#[derive(Clone,Debug)]
+struct BvhNode {
+    bounds: Aabb,
+    kind: BvhNodeKind
+}
+
+/// Bounding volume hierarchy over the faces of a mesh, built using the surface
+/// area heuristic. Positions are stored in the mesh' object space, so rays have
+/// to be transformed into object space before traversing the hierarchy.
+#[derive(Clone,Debug)]
+pub struct Bvh {
+    nodes: Vec<BvhNode>,
+    face_indices: Vec<usize>
+}
+
+#[derive(Clone)]
+struct Bucket {
+    bounds: Aabb,
+    count: usize
+}
+
+impl Bvh {
+    /// Builds the hierarchy for all faces of the given mesh.
+    pub fn build(mesh: &Mesh) -> Self {
+        let face_bounds: Vec<Aabb> = mesh.faces.iter().map(|face| {
+            Aabb::from_points(&[&mesh.vertices[face.a], &mesh.vertices[face.b], &mesh.vertices[face.c]])
+        }).collect();
+        let centroids: Vec<Vec4> = face_bounds.iter().map(|b| b.centroid()).collect();
+
+        let mut bvh = Bvh {
+            nodes: Vec::new(),
+            face_indices: (0..mesh.faces.len()).collect()
+        };
+
+        if !bvh.face_indices.is_empty() {
+            let count = bvh.face_indices.len();
+            bvh.build_recursive(0, count, &face_bounds, &centroids);
+        }
+
+        bvh
+    }
+
+    /// Creates the node for `face_indices[first..first + count]` and its
+    /// children and returns the node's index.
+    fn build_recursive(&mut self, first: usize, count: usize, face_bounds: &[Aabb], centroids: &[Vec4]) -> usize {
+        let node_index = self.nodes.len();
+
+        let mut bounds = Aabb::empty();
+        let mut centroid_bounds = Aabb::empty();
+        for &face_index in &self.face_indices[first..first + count] {
+            bounds = Aabb::union(&bounds, &face_bounds[face_index]);
+            centroid_bounds.grow(&centroids[face_index]);
+        }
+
+        self.nodes.push(BvhNode {
+            bounds: bounds.clone(),
+            kind: BvhNodeKind::Leaf { first, count }
+        });
+
+        if count <= MIN_LEAF_SIZE {
+            return node_index;
+        }
+
+        let (axis, split_bucket, split_cost) = match Self::find_best_split(&self.face_indices[first..first + count],
+                                                                        &bounds, &centroid_bounds,
+                                                                        face_bounds, centroids) {
+            Some(split) => split,
+            // All centroids coincide, there is no way to separate the faces.
+            None => return node_index
+        };
+
+        let leaf_cost = count as f64;
+        if split_cost >= leaf_cost && count <= MAX_LEAF_SIZE {
+            return node_index;
+        }
+
+        // Move all faces left of the split to the front of the range.
+        let mut mid = first;
+        for i in first..first + count {
+            let face_index = self.face_indices[i];
+            if Self::bucket_of(&centroids[face_index], &centroid_bounds, axis) <= split_bucket {
+                self.face_indices.swap(i, mid);
+                mid += 1;
+            }
+        }
+
+        let left = self.build_recursive(first, mid - first, face_bounds, centroids);
+        let right = self.build_recursive(mid, first + count - mid, face_bounds, centroids);
+        self.nodes[node_index].kind = BvhNodeKind::Interior { left, right, axis };
+
+        node_index
+    }
+
+    /// Evaluates the SAH cost for splitting between each pair of adjacent
+    /// buckets along each axis and returns the cheapest `(axis, bucket, cost)`.
+    /// Faces in buckets up to and including `bucket` go to the left child.
+    fn find_best_split(face_indices: &[usize], bounds: &Aabb, centroid_bounds: &Aabb,
+                       face_bounds: &[Aabb], centroids: &[Vec4]) -> Option<(usize, usize, f64)> {
+        let mut best: Option<(usize, usize, f64)> = None;
+        let parent_area = bounds.surface_area();
+
+        for axis in 0..3 {
+            if centroid_bounds.max.component(axis) <= centroid_bounds.min.component(axis) {
+                continue;
+            }
+
+            let mut buckets = vec![Bucket { bounds: Aabb::empty(), count: 0 }; SAH_BUCKETS];
+            for &face_index in face_indices {
+                let bucket = &mut buckets[Self::bucket_of(&centroids[face_index], centroid_bounds, axis)];
+                bucket.bounds = Aabb::union(&bucket.bounds, &face_bounds[face_index]);
+                bucket.count += 1;
+            }
+
+            // Sweep from the right to know the bounds right of every split.
+            let mut right_area = [0.0; SAH_BUCKETS];
+            let mut right_count = [0; SAH_BUCKETS];
+            let mut accumulated = Aabb::empty();
+            let mut count = 0;
+            for i in (1..SAH_BUCKETS).rev() {
+                accumulated = Aabb::union(&accumulated, &buckets[i].bounds);
+                count += buckets[i].count;
+                right_area[i] = accumulated.surface_area();
+                right_count[i] = count;
+            }
+
+            // Then sweep from the left and evaluate every split.
+            let mut accumulated = Aabb::empty();
+            let mut count = 0;
+            for i in 0..SAH_BUCKETS - 1 {
+                accumulated = Aabb::union(&accumulated, &buckets[i].bounds);
+                count += buckets[i].count;
+
+                if count == 0 || right_count[i + 1] == 0 {
+                    continue;
+                }
+
+                let cost = TRAVERSAL_COST
+                         + (accumulated.surface_area() * count as f64
+                            + right_area[i + 1] * right_count[i + 1] as f64) / parent_area;
+
+                let is_better = match best {
+                    Some((_, _, best_cost)) => cost < best_cost,
+                    None => true
+                };
+                if is_better {
+                    best = Some((axis, i, cost));
+                }
+            }
+        }
+
+        best
+    }
+
+    fn bucket_of(centroid: &Vec4, centroid_bounds: &Aabb, axis: usize) -> usize {
+        let min = centroid_bounds.min.component(axis);
+        let extent = centroid_bounds.max.component(axis) - min;
+        let bucket = ((centroid.component(axis) - min) / extent * SAH_BUCKETS as f64) as usize;
+        bucket.min(SAH_BUCKETS - 1)
+    }
+
+    /// Finds the closest face hit by the ray within `max_distance`.
+    /// `intersect` is called for every candidate face and returns the hit
+    /// distance together with arbitrary hit information if the face is hit.
+    pub fn closest_hit<T, F>(&self, origin: &Vec4, direction: &Vec4, max_distance: f64, mut intersect: F) -> Option<(usize, T)>
+        where F: FnMut(usize) -> Option<(f64, T)>
+    {
+        let mut closest: Option<(usize, T)> = None;
+        let mut max_distance = max_distance;
+
+        self.traverse(origin, direction, &mut max_distance, |face_index, max_distance| {
+            if let Some((t, hit)) = intersect(face_index) {
+                if t < *max_distance {
+                    *max_distance = t;
+                    closest = Some((face_index, hit));
+                }
+            }
+            false
+        });
+
+        closest
+    }
+
+    /// Returns true as soon as any face is hit by the ray within `max_distance`.
+    /// `intersect` is called for candidate faces and returns whether the face
+    /// is hit within that distance.
+    pub fn any_hit<F>(&self, origin: &Vec4, direction: &Vec4, max_distance: f64, mut intersect: F) -> bool
+        where F: FnMut(usize) -> bool
+    {
+        let mut max_distance = max_distance;
+        self.traverse(origin, direction, &mut max_distance, |face_index, _| intersect(face_index))
+    }
+
+    /// Visits all leafs whose bounds are hit by the ray in front-to-back order.
+    /// The visitor may shrink the maximum distance to prune the remaining
+    /// nodes and may return true to stop the traversal early.
+    fn traverse<F>(&self, origin: &Vec4, direction: &Vec4, max_distance: &mut f64, mut visit: F) -> bool
+        where F: FnMut(usize, &mut f64) -> bool
+    {
+        if self.nodes.is_empty() {
+            return false;
+        }
+
+        let inverse_direction = Vec4::new(1.0 / direction.x, 1.0 / direction.y, 1.0 / direction.z, 0.0);
+
+        let mut stack: Vec<usize> = Vec::with_capacity(64);
+        stack.push(0);
+
+        while let Some(node_index) = stack.pop() {
+            let node = &self.nodes[node_index];
+            if node.bounds.intersect(origin, &inverse_direction, *max_distance).is_none() {
+                continue;
+            }
+
+            match node.kind {
+                BvhNodeKind::Leaf { first, count } => {
+                    for &face_index in &self.face_indices[first..first + count] {
+                        if visit(face_index, max_distance) {
+                            return true;
+                        }
+                    }
+                }
+                BvhNodeKind::Interior { left, right, axis } => {
+                    // Push the far child first so that the near child gets visited first.
+                    if direction.component(axis) < 0.0 {
+                        stack.push(left);
+                        stack.push(right);
+                    }
+                    else {
+                        stack.push(right);
+                        stack.push(left);
+                    }
+                }
+            }
+        }
+
+        false
+    }
+}
+
+#[test]
+fn test_bvh_structure() {
+    use mesh::PolygonWinding;
+
+    let mesh = Mesh::try_load_from_off("./meshes/teapot.off", PolygonWinding::Clockwise).unwrap();
+    let bvh = Bvh::build(&mesh);
+
+    // Every face has to end up in exactly one leaf.
+    let mut seen = vec![0; mesh.faces.len()];
+    for node in &bvh.nodes {
+        match node.kind {
+            BvhNodeKind::Leaf { first, count } => {
+                for &face_index in &bvh.face_indices[first..first + count] {
+                    seen[face_index] += 1;
+                }
+            }
+            BvhNodeKind::Interior { left, right, .. } => {
+                // Children have to be contained within their parent.
+                for &child in &[left, right] {
+                    let bounds = &bvh.nodes[child].bounds;
+                    assert_eq!(Aabb::union(&node.bounds, bounds), node.bounds);
+                }
+            }
+        }
+    }
+    assert!(seen.iter().all(|&n| n == 1));
+    assert!(bvh.nodes.len() > 1);
+}
+
+#[test]
+fn test_bvh_empty_mesh() {
+    let mesh = Mesh::new(Vec::new(), Vec::new());
+    let bvh = Bvh::build(&mesh);
+    let origin = Vec4::new(0.0, 0.0, 0.0, 1.0);
+    let direction = Vec4::new(0.0, 0.0, -1.0, 0.0);
+    assert!(bvh.closest_hit(&origin, &direction, f64::INFINITY, |_| Some((1.0, ()))).is_none());
+    assert!(!bvh.any_hit(&origin, &direction, f64::INFINITY, |_| true));
+}
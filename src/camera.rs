@@ -1,9 +1,394 @@
-use algebra::Vec4;
+use std::f64::consts::PI;
+use algebra::{ Angle, Vec4, Mat4 };
 
+/// Opening angle of a perspective camera, measured across the whole image
+/// along one of its axes. The angle along the other axis follows from the
+/// aspect ratio.
 #[allow(dead_code)]
+#[derive(Clone,Copy,PartialEq,Debug)]
+pub enum FieldOfView {
+    Vertical(Angle),
+    Horizontal(Angle)
+}
+
+/// Determines how the scene is mapped onto the image.
+#[allow(dead_code)]
+#[derive(Clone,Copy,PartialEq,Debug)]
+pub enum Projection {
+    /// Pinhole camera whose opening angle is given by the field of view.
+    Perspective,
+    /// Parallel rays along the viewing direction, covering a view volume
+    /// of the given height. Its width follows from the aspect ratio.
+    Orthographic { height: f64 },
+    /// Full 360 x 180 degree panorama, mapping the image's horizontal axis
+    /// to the longitude and its vertical axis to the latitude. Images should
+    /// have an aspect ratio of 2:1.
+    Equirectangular,
+    /// Equidistant fisheye whose image circle fills the shorter side of the
+    /// image. The angle to the viewing direction grows linearly with the
+    /// distance to the image's center, up to half of the field of view at
+    /// the circle's border.
+    Fisheye { field_of_view: Angle }
+}
+
+#[allow(dead_code)]
+#[derive(Clone,Debug)]
 pub struct Camera {
     pub position: Vec4,
     pub look_at: Vec4,
-    pub up: Vec4
+    pub up: Vec4,
+    pub projection: Projection,
+    /// Only used by the perspective projection.
+    pub field_of_view: FieldOfView,
+    /// Distance from the camera to the near clipping plane.
+    /// Panoramic projections do not clip.
+    pub near: f64,
+    /// Distance from the camera to the far clipping plane.
+    pub far: f64,
+    /// Width divided by height of the sensor. `None` uses the aspect ratio
+    /// of the rendered image, other values stretch the image.
+    /// Panoramic projections always use the image's aspect ratio.
+    pub aspect_ratio: Option<f64>,
+    /// Radius of the thin lens, 0.0 for a pinhole camera where everything is in focus.
+    /// Out of focus geometry only looks smooth with many samples per pixel.
+    pub aperture_radius: f64,
+    /// Distance from the camera to the plane that is in perfect focus.
+    pub focus_distance: f64,
+    /// Distance by which the rays of an equirectangular projection start
+    /// sideways of the camera's position, tangentially to a circle around it.
+    /// Used for omni-directional stereo, positive values for the right eye.
+    pub stereo_offset: f64,
+    /// Moves the image's center to the right without turning the camera, by
+    /// the given distance at unit distance in front of perspective cameras
+    /// or in world units for orthographic ones. Panoramic projections ignore
+    /// it. Used for the asymmetric frustums of converging stereo eyes.
+    pub horizontal_shift: f64
+}
+
+/// One of the two eyes of a stereo rig.
+#[allow(dead_code)]
+#[derive(Clone,Copy,PartialEq,Debug)]
+pub enum Eye {
+    Left,
+    Right
+}
+
+/// Arrangement of the left and right eye's images within a single image.
+#[allow(dead_code)]
+#[derive(Clone,Copy,PartialEq,Debug)]
+pub enum StereoLayout {
+    /// The left eye's image in the left half of the image.
+    SideBySide,
+    /// The left eye's image in the upper half of the image.
+    TopBottom
+}
+
+/// Pair of cameras for stereoscopic rendering, derived from a central camera.
+#[allow(dead_code)]
+#[derive(Clone,Debug)]
+pub struct StereoRig {
+    /// Distance between the two eyes, 0.064 corresponds to human eyes
+    /// when the scene is modelled in meters.
+    pub interocular_distance: f64,
+    /// Distance from the central camera at which the images of both eyes
+    /// coincide. Infinity puts it at infinity.
+    pub convergence_distance: f64,
+    pub layout: StereoLayout
+}
+
+#[allow(dead_code)]
+impl Camera {
+    /// Creates a perspective camera with a vertical field of view of 45 degrees.
+    pub fn new(position: Vec4, look_at: Vec4, up: Vec4) -> Self {
+        Camera {
+            position,
+            look_at,
+            up,
+            projection: Projection::Perspective,
+            field_of_view: FieldOfView::Vertical(Angle::Degrees(45.0)),
+            near: 0.1,
+            far: 1000.0,
+            aspect_ratio: None,
+            aperture_radius: 0.0,
+            focus_distance: 10.0,
+            stereo_offset: 0.0,
+            horizontal_shift: 0.0
+        }
+    }
+
+    /// Aspect ratio of the sensor when rendering an image of the given size.
+    pub fn aspect_ratio(&self, width: u32, height: u32) -> f64 {
+        self.aspect_ratio.unwrap_or(width as f64 / height as f64)
+    }
+
+    pub fn vertical_field_of_view(&self, aspect_ratio: f64) -> Angle {
+        match self.field_of_view {
+            FieldOfView::Vertical(angle) => angle,
+            FieldOfView::Horizontal(angle) => {
+                Angle::Radians(2.0 * ((angle.to_radians() / 2.0).tan() / aspect_ratio).atan())
+            }
+        }
+    }
+
+    pub fn horizontal_field_of_view(&self, aspect_ratio: f64) -> Angle {
+        match self.field_of_view {
+            FieldOfView::Vertical(angle) => {
+                Angle::Radians(2.0 * ((angle.to_radians() / 2.0).tan() * aspect_ratio).atan())
+            }
+            FieldOfView::Horizontal(angle) => angle
+        }
+    }
+
+    /// Normalized viewing direction followed by the directions of the image's
+    /// horizontal and vertical axes in world space.
+    pub fn basis(&self) -> (Vec4, Vec4, Vec4) {
+        let forward = (self.look_at.clone() - self.position.clone()).normalize();
+        let right = Vec4::cross(&forward, &self.up).normalize();
+        let up = Vec4::cross(&right, &forward);
+        (forward, right, up)
+    }
+
+    pub fn view_matrix(&self) -> Mat4 {
+        Mat4::look_at(&self.position, &self.look_at, &self.up)
+    }
+
+    /// Whether the projection maps the image to directions directly instead
+    /// of using a projection matrix.
+    pub fn is_panoramic(&self) -> bool {
+        match self.projection {
+            Projection::Equirectangular | Projection::Fisheye { .. } => true,
+            Projection::Perspective | Projection::Orthographic { .. } => false
+        }
+    }
+
+    /// Direction of the ray through the image position (x, y) of a panoramic
+    /// projection, given in normalized device coordinates from -1 to 1. Returns
+    /// `None` for positions outside of a fisheye's image circle and for
+    /// projections that are not panoramic.
+    pub fn panoramic_direction(&self, x: f64, y: f64, aspect_ratio: f64) -> Option<Vec4> {
+        let (forward, right, up) = self.basis();
+
+        match self.projection {
+            Projection::Equirectangular => {
+                let longitude = x * PI;
+                let latitude = y * PI / 2.0;
+                let horizontal = longitude.sin() * right + longitude.cos() * forward;
+                Some(latitude.cos() * horizontal + latitude.sin() * up)
+            }
+            Projection::Fisheye { field_of_view } => {
+                // Scale the longer axis so that the image circle is round.
+                let (x, y) = if aspect_ratio >= 1.0 { (x * aspect_ratio, y) } else { (x, y / aspect_ratio) };
+                let radius = (x * x + y * y).sqrt();
+                if radius > 1.0 {
+                    return None;
+                }
+                if radius == 0.0 {
+                    return Some(forward);
+                }
+
+                let theta = radius * field_of_view.to_radians() / 2.0;
+                let sideways = (x / radius) * right + (y / radius) * up;
+                Some(theta.cos() * forward + theta.sin() * sideways)
+            }
+            Projection::Perspective | Projection::Orthographic { .. } => None
+        }
+    }
+
+    /// Origin of the ray through the horizontal image position `x` of a
+    /// panoramic projection, given in normalized device coordinates.
+    pub fn panoramic_origin(&self, x: f64) -> Vec4 {
+        match self.projection {
+            Projection::Equirectangular if self.stereo_offset != 0.0 => {
+                let (forward, right, _) = self.basis();
+                let longitude = x * PI;
+                // Points to the right of the horizontal viewing direction.
+                let tangent = longitude.cos() * right - longitude.sin() * forward;
+                self.position.clone() + self.stereo_offset * tangent
+            }
+            _ => self.position.clone()
+        }
+    }
+
+    /// Projection matrix for rendering an image of the given size.
+    /// Panoramic projections cannot be expressed as a matrix and fall back
+    /// to the perspective one.
+    pub fn projection_matrix(&self, width: u32, height: u32) -> Mat4 {
+        let aspect_ratio = self.aspect_ratio(width, height);
+        match self.projection {
+            Projection::Perspective | Projection::Equirectangular | Projection::Fisheye { .. } => {
+                let mut matrix = Mat4::perspective(self.vertical_field_of_view(aspect_ratio), aspect_ratio, self.near, self.far);
+                // Skews the frustum so that x / -z = horizontal_shift ends up in the center.
+                matrix.data[2] = matrix.data[0] * self.horizontal_shift;
+                matrix
+            }
+            Projection::Orthographic { height } => {
+                let (half_width, half_height) = (height * aspect_ratio / 2.0, height / 2.0);
+                let shift = self.horizontal_shift;
+                Mat4::orthographic(shift - half_width, shift + half_width, -half_height, half_height, self.near, self.far)
+            }
+        }
+    }
 }
 
+#[allow(dead_code)]
+impl StereoRig {
+    /// Creates a rig with human interocular distance and parallel eyes.
+    pub fn new(layout: StereoLayout) -> Self {
+        StereoRig {
+            interocular_distance: 0.064,
+            convergence_distance: f64::INFINITY,
+            layout
+        }
+    }
+
+    /// Camera of a single eye. Equirectangular cameras are turned into
+    /// omni-directional stereo cameras, all others are moved sideways while
+    /// keeping their viewing direction. Convergence shifts the images of
+    /// perspective and orthographic eyes instead of turning them, which
+    /// would cause keystone distortion. Fisheye eyes do not converge.
+    pub fn eye_camera(&self, camera: &Camera, eye: Eye) -> Camera {
+        let offset = match eye {
+            Eye::Left => -self.interocular_distance / 2.0,
+            Eye::Right => self.interocular_distance / 2.0
+        };
+
+        let mut eye_camera = camera.clone();
+        if let Projection::Equirectangular = camera.projection {
+            eye_camera.stereo_offset = offset;
+            return eye_camera;
+        }
+
+        let (_, right, _) = camera.basis();
+        eye_camera.position = camera.position.clone() + offset * right.clone();
+        eye_camera.look_at = camera.look_at.clone() + offset * right;
+        if self.convergence_distance.is_finite() {
+            // Centers the point at the convergence distance in front of the central camera.
+            eye_camera.horizontal_shift -= match camera.projection {
+                Projection::Orthographic { .. } => offset,
+                _ => offset / self.convergence_distance
+            };
+        }
+        eye_camera
+    }
+}
+
+#[test]
+fn test_camera_field_of_view() {
+    let mut camera = Camera::new(Vec4::new(0.0, 0.0, 10.0, 1.0),
+                                 Vec4::new(0.0, 0.0, 0.0, 1.0),
+                                 Vec4::new(0.0, 1.0, 0.0, 0.0));
+    assert_eq!(camera.aspect_ratio(1920, 1080), 1920.0 / 1080.0);
+
+    // A square image has the same opening angle along both axes.
+    let fovx = camera.horizontal_field_of_view(1.0).to_radians();
+    assert!((fovx - (45.0f64).to_radians()).abs() < 1e-12);
+
+    // 90 degrees horizontally at 2:1 correspond to 2 * atan(0.5) vertically.
+    camera.field_of_view = FieldOfView::Horizontal(Angle::Degrees(90.0));
+    let fovy = camera.vertical_field_of_view(2.0).to_radians();
+    assert!((fovy - 2.0 * (0.5f64).atan()).abs() < 1e-12);
+    let fovx = camera.horizontal_field_of_view(2.0).to_radians();
+    assert!((fovx - (90.0f64).to_radians()).abs() < 1e-12);
+
+    camera.aspect_ratio = Some(2.0);
+    assert_eq!(camera.aspect_ratio(640, 480), 2.0);
+}
+
+#[test]
+fn test_camera_basis() {
+    let camera = Camera::new(Vec4::new(0.0, 0.0, 10.0, 1.0),
+                             Vec4::new(0.0, 0.0, 0.0, 1.0),
+                             Vec4::new(0.0, 2.0, 1.0, 0.0));
+    let (forward, right, up) = camera.basis();
+    assert_eq!(forward, Vec4::new(0.0, 0.0, -1.0, 0.0));
+    assert_eq!(right, Vec4::new(1.0, 0.0, 0.0, 0.0));
+    assert_eq!(up, Vec4::new(0.0, 1.0, 0.0, 0.0));
+}
+
+#[test]
+fn test_camera_equirectangular() {
+    let mut camera = Camera::new(Vec4::new(0.0, 0.0, 0.0, 1.0),
+                                 Vec4::new(0.0, 0.0, -1.0, 1.0),
+                                 Vec4::new(0.0, 1.0, 0.0, 0.0));
+    camera.projection = Projection::Equirectangular;
+    assert!(camera.is_panoramic());
+
+    let direction = |x: f64, y: f64| camera.panoramic_direction(x, y, 2.0).unwrap();
+    assert!(Vec4::epsilon_compare(direction(0.0, 0.0), Vec4::new(0.0, 0.0, -1.0, 0.0), 1e-12));
+    assert!(Vec4::epsilon_compare(direction(0.5, 0.0), Vec4::new(1.0, 0.0, 0.0, 0.0), 1e-12));
+    assert!(Vec4::epsilon_compare(direction(-1.0, 0.0), Vec4::new(0.0, 0.0, 1.0, 0.0), 1e-12));
+    assert!(Vec4::epsilon_compare(direction(0.3, 1.0), Vec4::new(0.0, 1.0, 0.0, 0.0), 1e-12));
+    assert!(Vec4::epsilon_compare(direction(0.0, -0.5), Vec4::new(0.0, -0.5f64.sqrt(), -0.5f64.sqrt(), 0.0), 1e-12));
+}
+
+#[test]
+fn test_camera_fisheye() {
+    let mut camera = Camera::new(Vec4::new(0.0, 0.0, 0.0, 1.0),
+                                 Vec4::new(0.0, 0.0, -1.0, 1.0),
+                                 Vec4::new(0.0, 1.0, 0.0, 0.0));
+    camera.projection = Projection::Fisheye { field_of_view: Angle::Degrees(180.0) };
+
+    let direction = |x: f64, y: f64| camera.panoramic_direction(x, y, 2.0);
+    assert_eq!(direction(0.0, 0.0), Some(Vec4::new(0.0, 0.0, -1.0, 0.0)));
+    // The image circle touches the top and bottom but not the sides of a 2:1 image.
+    assert!(Vec4::epsilon_compare(direction(0.0, 1.0).unwrap(), Vec4::new(0.0, 1.0, 0.0, 0.0), 1e-12));
+    assert!(Vec4::epsilon_compare(direction(0.5, 0.0).unwrap(), Vec4::new(1.0, 0.0, 0.0, 0.0), 1e-12));
+    assert_eq!(direction(0.75, 0.0), None);
+
+    // Equidistant: half way to the border is a quarter of the field of view.
+    let d = direction(0.0, 0.5).unwrap();
+    assert!((d.y.atan2(-d.z) - PI / 4.0).abs() < 1e-12);
+
+    camera.projection = Projection::Perspective;
+    assert!(!camera.is_panoramic());
+    assert_eq!(camera.panoramic_direction(0.0, 0.0, 1.0), None);
+}
+
+#[test]
+fn test_stereo_rig() {
+    let camera = Camera::new(Vec4::new(0.0, 0.0, 10.0, 1.0),
+                             Vec4::new(0.0, 0.0, 0.0, 1.0),
+                             Vec4::new(0.0, 1.0, 0.0, 0.0));
+    let mut rig = StereoRig::new(StereoLayout::SideBySide);
+    rig.interocular_distance = 0.5;
+
+    // Parallel eyes keep the viewing direction.
+    let left = rig.eye_camera(&camera, Eye::Left);
+    let right = rig.eye_camera(&camera, Eye::Right);
+    assert_eq!(left.position, Vec4::new(-0.25, 0.0, 10.0, 1.0));
+    assert_eq!(right.position, Vec4::new(0.25, 0.0, 10.0, 1.0));
+    assert_eq!(left.basis().0, camera.basis().0);
+
+    // Converging eyes stay parallel, but both see the convergence point in
+    // the center of their image.
+    rig.convergence_distance = 4.0;
+    let convergence_point = Vec4::new(0.0, 0.0, 6.0, 1.0);
+    for &projection in &[Projection::Perspective, Projection::Orthographic { height: 2.0 }] {
+        let mut camera = camera.clone();
+        camera.projection = projection;
+        for &eye in &[Eye::Left, Eye::Right] {
+            let eye_camera = rig.eye_camera(&camera, eye);
+            assert_eq!(eye_camera.basis().0, camera.basis().0);
+            let clip = eye_camera.projection_matrix(64, 48) * (eye_camera.view_matrix() * convergence_point.clone());
+            assert!((clip.x / clip.w).abs() < 1e-12 && (clip.y / clip.w).abs() < 1e-12);
+        }
+    }
+}
+
+#[test]
+fn test_omni_directional_stereo() {
+    let mut camera = Camera::new(Vec4::new(0.0, 0.0, 0.0, 1.0),
+                                 Vec4::new(0.0, 0.0, -1.0, 1.0),
+                                 Vec4::new(0.0, 1.0, 0.0, 0.0));
+    camera.projection = Projection::Equirectangular;
+    let rig = StereoRig::new(StereoLayout::TopBottom);
+
+    let left = rig.eye_camera(&camera, Eye::Left);
+    assert_eq!(left.position, camera.position);
+    assert_eq!(left.stereo_offset, -0.032);
+
+    // Looking forward the left eye is on the left, looking to the right it is in front.
+    assert!(Vec4::epsilon_compare(left.panoramic_origin(0.0), Vec4::new(-0.032, 0.0, 0.0, 1.0), 1e-12));
+    assert!(Vec4::epsilon_compare(left.panoramic_origin(0.5), Vec4::new(0.0, 0.0, -0.032, 1.0), 1e-12));
+    assert_eq!(camera.panoramic_origin(0.5), camera.position);
+}
@@ -1,3 +1,5 @@
+use std::ops;
+
 #[derive(Clone,PartialEq,Debug)]
 pub struct Color {
     pub r: u8,
@@ -5,3 +7,95 @@ pub struct Color {
     pub b: u8
 }
 
+/// Linear RGB color with floating point channels that is used for light
+/// calculations. Channels are not limited to [0, 1] until converted to a `Color`.
+#[derive(Clone,Copy,PartialEq,Debug)]
+pub struct Rgb {
+    pub r: f64,
+    pub g: f64,
+    pub b: f64
+}
+
+impl Rgb {
+    pub fn new(r: f64, g: f64, b: f64) -> Self {
+        Rgb { r, g, b }
+    }
+
+    pub fn black() -> Self {
+        Rgb::new(0.0, 0.0, 0.0)
+    }
+
+    pub fn white() -> Self {
+        Rgb::new(1.0, 1.0, 1.0)
+    }
+
+    /// Perceived brightness according to ITU-R BT.709.
+    pub fn luminance(self) -> f64 {
+        0.2126 * self.r + 0.7152 * self.g + 0.0722 * self.b
+    }
+
+    pub fn max_component(self) -> f64 {
+        self.r.max(self.g).max(self.b)
+    }
+
+    /// Converts a 8-bit color into the [0, 1] range.
+    pub fn from_color(color: &Color) -> Self {
+        Rgb::new(color.r as f64 / 255.0, color.g as f64 / 255.0, color.b as f64 / 255.0)
+    }
+
+    /// Clamps the channels to [0, 1] and quantizes them to 8 bits.
+    pub fn to_color(self) -> Color {
+        let quantize = |x: f64| (x.clamp(0.0, 1.0) * 255.0).round() as u8;
+        Color {
+            r: quantize(self.r),
+            g: quantize(self.g),
+            b: quantize(self.b)
+        }
+    }
+}
+
+impl ops::Add<Rgb> for Rgb {
+    type Output = Rgb;
+
+    fn add(self, rhs: Rgb) -> Rgb {
+        Rgb::new(self.r + rhs.r, self.g + rhs.g, self.b + rhs.b)
+    }
+}
+
+impl ops::AddAssign<Rgb> for Rgb {
+    fn add_assign(&mut self, rhs: Rgb) {
+        *self = *self + rhs;
+    }
+}
+
+impl ops::Sub<Rgb> for Rgb {
+    type Output = Rgb;
+
+    fn sub(self, rhs: Rgb) -> Rgb {
+        Rgb::new(self.r - rhs.r, self.g - rhs.g, self.b - rhs.b)
+    }
+}
+
+/// Component-wise product, e.g. for filtering light by a surface color.
+impl ops::Mul<Rgb> for Rgb {
+    type Output = Rgb;
+
+    fn mul(self, rhs: Rgb) -> Rgb {
+        Rgb::new(self.r * rhs.r, self.g * rhs.g, self.b * rhs.b)
+    }
+}
+
+impl ops::Mul<Rgb> for f64 {
+    type Output = Rgb;
+
+    fn mul(self, rhs: Rgb) -> Rgb {
+        Rgb::new(self * rhs.r, self * rhs.g, self * rhs.b)
+    }
+}
+
+#[test]
+fn test_rgb_to_color() {
+    assert_eq!(Rgb::new(1.0, 0.5, 0.0).to_color(), Color { r: 255, g: 128, b: 0 });
+    assert_eq!(Rgb::new(2.0, -1.0, 0.2).to_color(), Color { r: 255, g: 0, b: 51 });
+    assert_eq!(Rgb::from_color(&Color { r: 255, g: 0, b: 51 }), Rgb::new(1.0, 0.0, 0.2));
+}
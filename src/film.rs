@@ -0,0 +1,115 @@
+use color::Rgb;
+use pixmap::Pixmap;
+
+#[derive(Clone,Debug)]
+struct FilmPixel {
+    sum: Rgb,
+    weight: f64
+}
+
+/// Floating point image that accumulates weighted radiance samples.
+/// A film may only cover a rectangular part of the final image, starting at
+/// pixel (x, y), so that tiles can be rendered into films of their own
+/// before they are merged.
+#[derive(Clone,Debug)]
+pub struct Film {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    pixels: Vec<FilmPixel>
+}
+
+#[allow(dead_code)]
+impl Film {
+    pub fn new(x: u32, y: u32, width: u32, height: u32) -> Self {
+        Film {
+            x,
+            y,
+            width,
+            height,
+            pixels: vec![FilmPixel { sum: Rgb::black(), weight: 0.0 }; (width * height) as usize]
+        }
+    }
+
+    /// Maps image coordinates onto an index into the pixels vector,
+    /// `None` if the film does not cover the pixel.
+    fn index(&self, x: u32, y: u32) -> Option<usize> {
+        if x < self.x || y < self.y || x >= self.x + self.width || y >= self.y + self.height {
+            return None;
+        }
+        Some(((y - self.y) * self.width + (x - self.x)) as usize)
+    }
+
+    /// Adds a radiance sample to a pixel given in image coordinates.
+    /// Samples for pixels outside of the film are ignored.
+    pub fn add_sample(&mut self, x: u32, y: u32, radiance: Rgb, weight: f64) {
+        if let Some(index) = self.index(x, y) {
+            let pixel = &mut self.pixels[index];
+            pixel.sum += weight * radiance;
+            pixel.weight += weight;
+        }
+    }
+
+    /// Adds all samples of another film to the pixels covered by both films.
+    pub fn merge(&mut self, other: &Film) {
+        for y in other.y..other.y + other.height {
+            for x in other.x..other.x + other.width {
+                let source = &other.pixels[other.index(x, y).unwrap()];
+                if let Some(index) = self.index(x, y) {
+                    let pixel = &mut self.pixels[index];
+                    pixel.sum += source.sum;
+                    pixel.weight += source.weight;
+                }
+            }
+        }
+    }
+
+    /// Weighted average of a pixel's samples, black if it has none.
+    pub fn radiance(&self, x: u32, y: u32) -> Rgb {
+        match self.index(x, y) {
+            Some(index) if self.pixels[index].weight != 0.0 => {
+                let pixel = &self.pixels[index];
+                (1.0 / pixel.weight) * pixel.sum
+            }
+            _ => Rgb::black()
+        }
+    }
+
+    /// Draws all pixels covered by the film onto the pixmap.
+    pub fn develop(&self, pixmap: &mut Pixmap) {
+        for y in self.y..(self.y + self.height).min(pixmap.height) {
+            for x in self.x..(self.x + self.width).min(pixmap.width) {
+                pixmap.draw(x, y, self.radiance(x, y).to_color());
+            }
+        }
+    }
+}
+
+#[test]
+fn test_film_weighted_average() {
+    let mut film = Film::new(2, 3, 4, 4);
+    film.add_sample(2, 3, Rgb::new(1.0, 0.0, 0.0), 1.0);
+    film.add_sample(2, 3, Rgb::new(0.0, 1.0, 0.0), 3.0);
+    film.add_sample(0, 0, Rgb::white(), 1.0);
+
+    assert_eq!(film.radiance(2, 3), Rgb::new(0.25, 0.75, 0.0));
+    assert_eq!(film.radiance(3, 3), Rgb::black());
+    assert_eq!(film.radiance(0, 0), Rgb::black());
+}
+
+#[test]
+fn test_film_merge() {
+    let mut image = Film::new(0, 0, 4, 4);
+    let mut tile = Film::new(2, 2, 4, 4);
+    tile.add_sample(3, 3, Rgb::white(), 2.0);
+    tile.add_sample(5, 5, Rgb::white(), 1.0);
+    image.add_sample(3, 3, Rgb::black(), 2.0);
+
+    image.merge(&tile);
+    assert_eq!(image.radiance(3, 3), Rgb::new(0.5, 0.5, 0.5));
+
+    let mut pixmap = Pixmap::new(4, 4);
+    image.develop(&mut pixmap);
+    assert_eq!(pixmap.get_color(3, 3), Rgb::new(0.5, 0.5, 0.5).to_color());
+}
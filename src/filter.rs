@@ -0,0 +1,122 @@
+/// Reconstruction filter that determines how much a sample contributes to the
+/// pixels around it, depending on its offset to their centers measured in
+/// pixels. All filters are separable, i.e. the weight is the product of the
+/// filter evaluated for the horizontal and the vertical offset.
+#[allow(dead_code)]
+#[derive(Clone,Copy,PartialEq,Debug)]
+pub enum Filter {
+    /// Every sample within the radius is weighted equally. A radius of 0.5
+    /// averages exactly the samples within the pixel.
+    Box { radius: f64 },
+    /// Weights decrease linearly with the distance to the pixel's center.
+    Tent { radius: f64 },
+    /// Gaussian bell curve `exp(-alpha * x^2)`, shifted down so that it
+    /// reaches zero at the radius.
+    Gaussian { radius: f64, alpha: f64 },
+    /// Cubic filter by Mitchell and Netravali with the parameters B and C,
+    /// stretched to the radius. It sharpens due to its negative lobes.
+    MitchellNetravali { radius: f64, b: f64, c: f64 }
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Filter::Box { radius: 0.5 }
+    }
+}
+
+#[allow(dead_code)]
+impl Filter {
+    /// Mitchell-Netravali filter with the parameters recommended by its authors.
+    pub fn mitchell_netravali() -> Self {
+        Filter::MitchellNetravali { radius: 2.0, b: 1.0 / 3.0, c: 1.0 / 3.0 }
+    }
+
+    /// Samples further away from a pixel's center than this do not contribute to it.
+    pub fn radius(&self) -> f64 {
+        match *self {
+            Filter::Box { radius } => radius,
+            Filter::Tent { radius } => radius,
+            Filter::Gaussian { radius, .. } => radius,
+            Filter::MitchellNetravali { radius, .. } => radius
+        }
+    }
+
+    /// Weight of a sample with the offset (dx, dy) to a pixel's center.
+    pub fn evaluate(&self, dx: f64, dy: f64) -> f64 {
+        self.evaluate_1d(dx) * self.evaluate_1d(dy)
+    }
+
+    fn evaluate_1d(&self, x: f64) -> f64 {
+        match *self {
+            // Half-open so that samples on the border between two pixels
+            // only count for one of them.
+            Filter::Box { radius } => if -radius <= x && x < radius { 1.0 } else { 0.0 },
+            Filter::Tent { radius } => (1.0 - x.abs() / radius).max(0.0),
+            Filter::Gaussian { radius, alpha } => {
+                ((-alpha * x * x).exp() - (-alpha * radius * radius).exp()).max(0.0)
+            }
+            Filter::MitchellNetravali { radius, b, c } => {
+                // The cubic polynomials are defined for |x| in [0, 2].
+                let x = (2.0 * x / radius).abs();
+                if x >= 2.0 {
+                    0.0
+                }
+                else if x >= 1.0 {
+                    ((-b - 6.0 * c) * x * x * x + (6.0 * b + 30.0 * c) * x * x
+                     + (-12.0 * b - 48.0 * c) * x + (8.0 * b + 24.0 * c)) / 6.0
+                }
+                else {
+                    ((12.0 - 9.0 * b - 6.0 * c) * x * x * x + (-18.0 + 12.0 * b + 6.0 * c) * x * x
+                     + (6.0 - 2.0 * b)) / 6.0
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_box_filter() {
+    let filter = Filter::default();
+    assert_eq!(filter.evaluate(0.0, 0.0), 1.0);
+    assert_eq!(filter.evaluate(-0.5, 0.49), 1.0);
+    assert_eq!(filter.evaluate(0.5, 0.0), 0.0);
+    assert_eq!(filter.evaluate(0.0, -0.6), 0.0);
+}
+
+#[test]
+fn test_tent_filter() {
+    let filter = Filter::Tent { radius: 2.0 };
+    assert_eq!(filter.evaluate(0.0, 0.0), 1.0);
+    assert_eq!(filter.evaluate(1.0, 0.0), 0.5);
+    assert_eq!(filter.evaluate(1.0, -1.0), 0.25);
+    assert_eq!(filter.evaluate(2.0, 0.0), 0.0);
+}
+
+#[test]
+fn test_gaussian_filter() {
+    let filter = Filter::Gaussian { radius: 1.5, alpha: 2.0 };
+    assert!(filter.evaluate(0.0, 0.0) > filter.evaluate(0.5, 0.0));
+    assert!(filter.evaluate(0.5, 0.0) > filter.evaluate(1.0, 0.0));
+    assert_eq!(filter.evaluate(1.5, 0.0), 0.0);
+    assert_eq!(filter.evaluate(0.0, 3.0), 0.0);
+}
+
+#[test]
+fn test_mitchell_netravali_filter() {
+    let filter = Filter::mitchell_netravali();
+    assert_eq!(filter.radius(), 2.0);
+
+    // The 1D filter integrates to one and has negative lobes.
+    let steps = 4000;
+    let mut integral = 0.0;
+    let mut minimum = f64::INFINITY;
+    for i in 0..steps {
+        let x = -2.0 + 4.0 * (i as f64 + 0.5) / steps as f64;
+        let weight = filter.evaluate_1d(x);
+        integral += weight * 4.0 / steps as f64;
+        minimum = minimum.min(weight);
+    }
+    assert!((integral - 1.0).abs() < 1e-6);
+    assert!(minimum < 0.0);
+    assert_eq!(filter.evaluate(2.0, 0.0), 0.0);
+}
@@ -0,0 +1,236 @@
+use algebra::{ Angle, Vec4 };
+use color::Rgb;
+use random::Random;
+
+/// Light source illuminating the scene.
+/// The intensity of point and spot lights falls off with the squared distance.
+#[allow(dead_code)]
+#[derive(Clone,Debug)]
+pub enum Light {
+    /// Emits light uniformly into all directions from a single position.
+    Point {
+        position: Vec4,
+        color: Rgb,
+        intensity: f64
+    },
+    /// Infinitely far away light source like the sun.
+    /// `direction` points from the light into the scene.
+    Directional {
+        direction: Vec4,
+        color: Rgb,
+        intensity: f64
+    },
+    /// Point light that only emits light into a cone around `direction`.
+    /// The light fades out between `inner_angle` and `outer_angle`,
+    /// both measured from the cone's axis.
+    Spot {
+        position: Vec4,
+        direction: Vec4,
+        inner_angle: Angle,
+        outer_angle: Angle,
+        color: Rgb,
+        intensity: f64
+    },
+    /// Rectangle centered at `position` and spanned by the edges `u` and `v`
+    /// that only emits light into the direction of its normal `u x v`.
+    /// It is sampled at `samples * samples` stratified points, which results
+    /// in soft shadows. Facing it head-on, it is as bright as a point light.
+    Area {
+        position: Vec4,
+        u: Vec4,
+        v: Vec4,
+        color: Rgb,
+        intensity: f64,
+        samples: u32
+    }
+}
+
+/// Light arriving at a point from a single point on a light source.
+#[derive(Clone,Debug)]
+pub struct LightSample {
+    /// Normalized direction from the illuminated point towards the light.
+    pub direction: Vec4,
+    /// Distance to the light, infinite for directional lights.
+    pub distance: f64,
+    pub radiance: Rgb
+}
+
+impl Light {
+    /// Calculates the light arriving at `point` from this light source.
+    /// Area lights yield one sample per point on their surface, all other
+    /// lights yield a single sample unless the point does not receive any
+    /// light at all. The radiance of all samples adds up to the total light.
+    pub fn illuminate(&self, point: &Vec4, random: &mut Random) -> Vec<LightSample> {
+        match *self {
+            Light::Point { ref position, color, intensity } => {
+                direction_and_distance(point, position).map(|(direction, distance)| LightSample {
+                    direction,
+                    distance,
+                    radiance: (intensity / (distance * distance)) * color
+                }).into_iter().collect()
+            }
+            Light::Directional { ref direction, color, intensity } => {
+                vec![LightSample {
+                    direction: (-1.0 * direction.clone()).normalize(),
+                    distance: f64::INFINITY,
+                    radiance: intensity * color
+                }]
+            }
+            Light::Spot { ref position, ref direction, inner_angle, outer_angle, color, intensity } => {
+                let (to_light, distance) = match direction_and_distance(point, position) {
+                    Some(x) => x,
+                    None => return Vec::new()
+                };
+
+                let cos_theta = -Vec4::dot(&to_light, &direction.clone().normalize());
+                let cos_inner = inner_angle.to_radians().cos();
+                let cos_outer = outer_angle.to_radians().cos();
+
+                let falloff = if cos_theta >= cos_inner {
+                    1.0
+                }
+                else if cos_theta <= cos_outer {
+                    return Vec::new();
+                }
+                else {
+                    smoothstep((cos_theta - cos_outer) / (cos_inner - cos_outer))
+                };
+
+                vec![LightSample {
+                    direction: to_light,
+                    distance,
+                    radiance: (falloff * intensity / (distance * distance)) * color
+                }]
+            }
+            Light::Area { ref position, ref u, ref v, color, intensity, samples } => {
+                let normal = Vec4::cross(u, v).normalize();
+                let weight = intensity / (samples * samples) as f64;
+                let mut light_samples = Vec::with_capacity((samples * samples) as usize);
+
+                for i in 0..samples {
+                    for j in 0..samples {
+                        // Pick a random point within each cell of a samples x samples grid.
+                        let s = (i as f64 + random.next_f64()) / samples as f64 - 0.5;
+                        let t = (j as f64 + random.next_f64()) / samples as f64 - 0.5;
+                        let light_point = position.clone() + s * u.clone() + t * v.clone();
+
+                        let (direction, distance) = match direction_and_distance(point, &light_point) {
+                            Some(x) => x,
+                            None => continue
+                        };
+
+                        let cos_light = -Vec4::dot(&normal, &direction);
+                        if cos_light <= 0.0 {
+                            continue;
+                        }
+
+                        light_samples.push(LightSample {
+                            direction,
+                            distance,
+                            radiance: (weight * cos_light / (distance * distance)) * color
+                        });
+                    }
+                }
+
+                light_samples
+            }
+        }
+    }
+}
+
+/// Normalized direction and distance from `from` to `to`, `None` if both coincide.
+fn direction_and_distance(from: &Vec4, to: &Vec4) -> Option<(Vec4, f64)> {
+    let difference = to.clone() - from.clone();
+    let distance = Vec4::dot(&difference, &difference).sqrt();
+    if distance == 0.0 {
+        return None;
+    }
+    Some((difference / distance, distance))
+}
+
+fn smoothstep(x: f64) -> f64 {
+    x * x * (3.0 - 2.0 * x)
+}
+
+#[test]
+fn test_point_light() {
+    let light = Light::Point {
+        position: Vec4::new(0.0, 2.0, 0.0, 1.0),
+        color: Rgb::white(),
+        intensity: 8.0
+    };
+
+    let mut random = Random::new(0);
+    let samples = light.illuminate(&Vec4::new(0.0, 0.0, 0.0, 1.0), &mut random);
+    assert_eq!(samples.len(), 1);
+    assert_eq!(samples[0].direction, Vec4::new(0.0, 1.0, 0.0, 0.0));
+    assert_eq!(samples[0].distance, 2.0);
+    assert_eq!(samples[0].radiance, Rgb::new(2.0, 2.0, 2.0));
+
+    assert!(light.illuminate(&Vec4::new(0.0, 2.0, 0.0, 1.0), &mut random).is_empty());
+}
+
+#[test]
+fn test_directional_light() {
+    let light = Light::Directional {
+        direction: Vec4::new(0.0, -3.0, 0.0, 0.0),
+        color: Rgb::new(1.0, 0.5, 0.0),
+        intensity: 2.0
+    };
+
+    let samples = light.illuminate(&Vec4::new(5.0, 0.0, 1.0, 1.0), &mut Random::new(0));
+    let sample = &samples[0];
+    assert_eq!(sample.direction, Vec4::new(0.0, 1.0, 0.0, 0.0));
+    assert!(sample.distance.is_infinite());
+    assert_eq!(sample.radiance, Rgb::new(2.0, 1.0, 0.0));
+}
+
+#[test]
+fn test_spot_light() {
+    let light = Light::Spot {
+        position: Vec4::new(0.0, 1.0, 0.0, 1.0),
+        direction: Vec4::new(0.0, -1.0, 0.0, 0.0),
+        inner_angle: Angle::Degrees(10.0),
+        outer_angle: Angle::Degrees(30.0),
+        color: Rgb::white(),
+        intensity: 1.0
+    };
+
+    let mut random = Random::new(0);
+
+    // Right beneath the light, within the inner cone.
+    let samples = light.illuminate(&Vec4::new(0.0, 0.0, 0.0, 1.0), &mut random);
+    assert_eq!(samples[0].radiance, Rgb::white());
+
+    // 20 degrees off the axis, partially lit.
+    let x = (20.0f64).to_radians().tan();
+    let samples = light.illuminate(&Vec4::new(x, 0.0, 0.0, 1.0), &mut random);
+    let full = 1.0 / (samples[0].distance * samples[0].distance);
+    assert!(samples[0].radiance.r > 0.0 && samples[0].radiance.r < full);
+
+    // 45 degrees off the axis, outside of the cone.
+    assert!(light.illuminate(&Vec4::new(1.0, 0.0, 0.0, 1.0), &mut random).is_empty());
+}
+
+#[test]
+fn test_area_light() {
+    let light = Light::Area {
+        position: Vec4::new(0.0, 0.0, 4.0, 1.0),
+        u: Vec4::new(0.0, 0.1, 0.0, 0.0),
+        v: Vec4::new(0.1, 0.0, 0.0, 0.0),
+        color: Rgb::white(),
+        intensity: 16.0,
+        samples: 3
+    };
+    let mut random = Random::new(0);
+
+    // A small light far away behaves almost like a point light.
+    let samples = light.illuminate(&Vec4::new(0.0, 0.0, 0.0, 1.0), &mut random);
+    assert_eq!(samples.len(), 9);
+    let total = samples.iter().fold(0.0, |sum, sample| sum + sample.radiance.r);
+    assert!((total - 1.0).abs() < 0.01);
+    assert!(samples.iter().all(|sample| sample.direction.z > 0.99));
+
+    // Nothing is emitted towards the back side.
+    assert!(light.illuminate(&Vec4::new(0.0, 0.0, 8.0, 1.0), &mut random).is_empty());
+}
@@ -1,35 +1,54 @@
 mod mesh;
+mod obj;
+mod ply;
+mod stl;
+mod primitives;
+mod repair;
+mod bvh;
+mod shape;
 mod scene;
 mod algebra;
 mod camera;
 mod model;
+mod material;
 mod color;
+mod light;
 mod pixmap;
 mod raytrace;
+mod film;
+mod filter;
+mod sampling;
+mod random;
+mod texture;
 
-use algebra::Vec4;
+use algebra::{ Angle, Vec4 };
+use color::Rgb;
 
 fn main() {
-    let mesh = mesh::Mesh::try_load_from_off("./meshes/teapot.off", mesh::PolygonWinding::Clockwise).unwrap();
-
-    let model = model::Model {
-        mesh: mesh,
-        position: Vec4 {
-            x: 0.0,
-            y: -1.0,
-            z: 0.0,
-            w: 1.0
-        }
-    };
-
-    let camera = camera::Camera {
-        position: Vec4 { x: 0.0, y: 0.0, z: 10.0, w: 1.0 },
-        look_at: Vec4 { x: 0.0, y: 0.0, z: 0.0, w: 1.0 },
-        up: Vec4 { x: 0.0, y: 1.0, z: 0.0, w: 0.0 },
+    let mut mesh = mesh::Mesh::try_load_from_off("./meshes/teapot.off", mesh::PolygonWinding::Clockwise).unwrap();
+    mesh.compute_vertex_normals(Angle::Degrees(60.0));
+
+    let mut model = model::Model::new(mesh, Vec4 {
+        x: 0.0,
+        y: -1.0,
+        z: 0.0,
+        w: 1.0
+    });
+    model.material.base_color = Rgb::new(0.8, 0.1, 0.1);
+
+    let camera = camera::Camera::new(Vec4 { x: 0.0, y: 0.0, z: 10.0, w: 1.0 },
+                                     Vec4 { x: 0.0, y: 0.0, z: 0.0, w: 1.0 },
+                                     Vec4 { x: 0.0, y: 1.0, z: 0.0, w: 0.0 });
+
+    let light = light::Light::Point {
+        position: Vec4 { x: 5.0, y: 5.0, z: 10.0, w: 1.0 },
+        color: Rgb::white(),
+        intensity: 150.0
     };
 
     let scene = scene::Scene {
         models: vec![model],
+        lights: vec![light],
         camera: camera
     };
 
@@ -0,0 +1,180 @@
+use std::sync::Arc;
+use color::Rgb;
+use texture::Texture;
+
+/// Describes the appearance of a surface using the parameters of the
+/// metallic/roughness workflow.
+#[derive(Clone,PartialEq,Debug)]
+pub struct Material {
+    /// Diffuse color of dielectrics and specular color of metals.
+    pub base_color: Rgb,
+    /// 0.0 for perfectly smooth surfaces up to 1.0 for completely rough ones.
+    pub roughness: f64,
+    /// 0.0 for dielectrics like plastic or glass, 1.0 for metals.
+    pub metallic: f64,
+    /// Light emitted by the surface itself.
+    pub emission: Rgb,
+    /// Determines the strength of specular reflections of dielectrics
+    /// and how much light is bent when passing through them.
+    pub index_of_refraction: f64,
+    /// 1.0 for opaque surfaces, 0.0 for completely transparent ones.
+    pub opacity: f64,
+    /// Textures whose values at the surface's texture coordinates multiply
+    /// the base color, the roughness using the texture's luminance, and the
+    /// emission. They are shared, as many materials may use the same image.
+    pub base_color_texture: Option<Arc<Texture>>,
+    pub roughness_texture: Option<Arc<Texture>>,
+    pub emission_texture: Option<Arc<Texture>>
+}
+
+impl Default for Material {
+    /// White-ish plastic.
+    fn default() -> Self {
+        Material {
+            base_color: Rgb::new(0.8, 0.8, 0.8),
+            roughness: 0.5,
+            metallic: 0.0,
+            emission: Rgb::black(),
+            index_of_refraction: 1.5,
+            opacity: 1.0,
+            base_color_texture: None,
+            roughness_texture: None,
+            emission_texture: None
+        }
+    }
+}
+
+impl Material {
+    /// Whether any of the parameters is textured.
+    pub fn has_textures(&self) -> bool {
+        self.base_color_texture.is_some() || self.roughness_texture.is_some() || self.emission_texture.is_some()
+    }
+
+    /// Material at the point of the surface with the given texture
+    /// coordinates, whose parameters are multiplied by the textures.
+    pub fn at(&self, u: f64, v: f64) -> Material {
+        let mut material = self.clone();
+        if let Some(ref texture) = self.base_color_texture {
+            material.base_color = material.base_color * texture.sample(u, v);
+        }
+        if let Some(ref texture) = self.roughness_texture {
+            material.roughness *= texture.sample(u, v).luminance();
+        }
+        if let Some(ref texture) = self.emission_texture {
+            material.emission = material.emission * texture.sample(u, v);
+        }
+        material
+    }
+
+    /// Color of the diffusely reflected light. Metals do not have any.
+    pub fn diffuse_color(&self) -> Rgb {
+        (1.0 - self.metallic) * self.base_color
+    }
+
+    /// Color of the specularly reflected light at normal incidence. For
+    /// dielectrics it is derived from the index of refraction using the
+    /// Fresnel equations, metals use their base color instead.
+    pub fn specular_color(&self) -> Rgb {
+        let f0 = ((self.index_of_refraction - 1.0) / (self.index_of_refraction + 1.0)).powi(2);
+        (1.0 - self.metallic) * Rgb::new(f0, f0, f0) + self.metallic * self.base_color
+    }
+
+    /// Fraction of light that is specularly reflected when viewing the surface
+    /// at the given angle, using Schlick's approximation.
+    pub fn reflectance(&self, cos_theta: f64) -> Rgb {
+        let f0 = self.specular_color();
+        let weight = (1.0 - cos_theta.clamp(0.0, 1.0)).powi(5);
+        f0 + weight * (Rgb::white() - f0)
+    }
+
+    /// Phong exponent corresponding to the roughness.
+    pub fn shininess(&self) -> f64 {
+        // Same mapping as between Beckmann roughness and Phong exponents,
+        // with the squared roughness for a perceptually linear parameter.
+        let alpha = (self.roughness * self.roughness).max(1e-4);
+        2.0 / (alpha * alpha) - 2.0
+    }
+
+    /// Roughness corresponding to a Phong exponent, the inverse of `shininess`.
+    pub fn roughness_from_shininess(shininess: f64) -> f64 {
+        let alpha = (2.0 / (shininess.max(0.0) + 2.0)).sqrt();
+        alpha.sqrt()
+    }
+}
+
+/// Fraction of unpolarized light that is reflected at the boundary between
+/// two dielectrics with the refractive indices `eta_i` on the side of the
+/// incoming light and `eta_t` on the other side. Returns 1.0 in case of
+/// total internal reflection.
+pub fn fresnel_dielectric(cos_theta_i: f64, eta_i: f64, eta_t: f64) -> f64 {
+    let cos_i = cos_theta_i.clamp(0.0, 1.0);
+    let sin_t = eta_i / eta_t * (1.0 - cos_i * cos_i).sqrt();
+    if sin_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin_t * sin_t).sqrt();
+
+    let r_s = (eta_i * cos_i - eta_t * cos_t) / (eta_i * cos_i + eta_t * cos_t);
+    let r_p = (eta_t * cos_i - eta_i * cos_t) / (eta_t * cos_i + eta_i * cos_t);
+    (r_s * r_s + r_p * r_p) / 2.0
+}
+
+#[test]
+fn test_material_specular_color() {
+    let plastic = Material::default();
+    let f0 = plastic.specular_color();
+    assert!((f0.r - 0.04).abs() < 1e-12);
+    assert_eq!(plastic.diffuse_color(), plastic.base_color);
+
+    let gold = Material {
+        base_color: Rgb::new(1.0, 0.8, 0.3),
+        metallic: 1.0,
+        ..Material::default()
+    };
+    assert_eq!(gold.specular_color(), gold.base_color);
+    assert_eq!(gold.diffuse_color(), Rgb::black());
+}
+
+#[test]
+fn test_material_shininess() {
+    let rough = Material { roughness: 1.0, ..Material::default() };
+    let smooth = Material { roughness: 0.1, ..Material::default() };
+    assert_eq!(rough.shininess(), 0.0);
+    assert!(smooth.shininess() > 1000.0);
+
+    assert_eq!(Material::roughness_from_shininess(0.0), 1.0);
+    assert!((Material::roughness_from_shininess(smooth.shininess()) - 0.1).abs() < 1e-12);
+}
+
+#[test]
+fn test_material_textures() {
+    let texture = Arc::new(Texture::try_load("testdata/meshes/red.ppm").unwrap());
+    let material = Material {
+        base_color: Rgb::new(0.5, 0.5, 0.5),
+        emission: Rgb::white(),
+        base_color_texture: Some(texture.clone()),
+        emission_texture: Some(texture),
+        ..Material::default()
+    };
+    assert!(material.has_textures());
+    assert!(!Material::default().has_textures());
+
+    let textured = material.at(0.3, 0.6);
+    assert_eq!(textured.base_color, Rgb::new(0.5, 0.0, 0.0));
+    assert_eq!(textured.emission, Rgb::new(1.0, 0.0, 0.0));
+    assert_eq!(textured.roughness, material.roughness);
+}
+
+#[test]
+fn test_fresnel() {
+    // Perpendicular incidence from air into glass.
+    assert!((fresnel_dielectric(1.0, 1.0, 1.5) - 0.04).abs() < 1e-12);
+    // Grazing incidence reflects everything.
+    assert!((fresnel_dielectric(0.0, 1.0, 1.5) - 1.0).abs() < 1e-12);
+    // Total internal reflection when leaving glass at 45 degrees.
+    assert_eq!(fresnel_dielectric((45.0f64).to_radians().cos(), 1.5, 1.0), 1.0);
+
+    let plastic = Material::default();
+    assert!((plastic.reflectance(1.0).r - 0.04).abs() < 1e-12);
+    assert!((plastic.reflectance(0.0).r - 1.0).abs() < 1e-12);
+}
@@ -1,9 +1,15 @@
 // https://m4rw3r.github.io/rust-questionmark-operator
 
-use std::io::BufReader;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::io::{ BufReader, BufWriter };
 use std::io::prelude::*;
 use std::fs::File;
-use algebra::Vec4;
+use std::str::FromStr;
+use algebra::{ Angle, Vec4 };
+use color::Rgb;
 
 /// Faces consist of exactly three vertices.
 /// a, b and c contain indices for our vertices vector.
@@ -21,124 +27,665 @@ pub enum PolygonWinding {
     CounterClockwise
 }
 
+/// Named subset of a mesh' faces, e.g. to assign them a different material.
+#[allow(dead_code)]
+#[derive(Clone)]
+pub struct FaceGroup {
+    pub name: String,
+    /// Indices for our faces vector.
+    pub faces: Vec<usize>
+}
+
 #[allow(dead_code)]
 #[derive(Clone)]
 pub struct Mesh {
     pub vertices: Vec<Vec4>,
-    pub faces: Vec<Face>
+    pub faces: Vec<Face>,
+    pub groups: Vec<FaceGroup>,
+    /// Optional per vertex attributes, either empty or with one entry per vertex.
+    pub normals: Vec<Vec4>,
+    pub colors: Vec<Rgb>,
+    pub texture_coordinates: Vec<(f64, f64)>,
+    /// Optional per face colors, either empty or with one entry per face.
+    pub face_colors: Vec<Rgb>
+}
+
+/// Error that occurs while loading or saving a mesh. Lines and columns start
+/// at 1. Binary data has no lines, there `line` is 0 and `column` is the
+/// offset in bytes from the start of the file.
+#[allow(dead_code)]
+#[derive(Debug)]
+pub enum MeshError {
+    Io(io::Error),
+    Parse { line: usize, column: usize, message: String },
+    /// The file ends before all announced vertices, faces or values were read.
+    UnexpectedEof,
+    /// A face refers to a vertex, texture coordinate or normal that does not exist.
+    IndexOutOfRange { line: usize, column: usize, index: i64 },
+    /// The file is valid, but uses a feature that the loader cannot handle.
+    UnsupportedFeature(String)
+}
+
+/// Whitespace separated word of a text file together with its position.
+#[derive(Clone,Copy,Debug)]
+pub struct Token<'a> {
+    pub text: &'a str,
+    pub line: usize,
+    pub column: usize
+}
+
+/// Optional vertex attributes of an OFF file, given by the prefixes of its keyword.
+struct OffKeyword {
+    texture_coordinates: bool,
+    colors: bool,
+    normals: bool,
+    homogeneous: bool
 }
 
 #[allow(dead_code)]
 impl Mesh {
+    /// Creates a mesh without any face groups or optional attributes.
+    pub fn new(vertices: Vec<Vec4>, faces: Vec<Face>) -> Self {
+        Mesh {
+            vertices,
+            faces,
+            groups: Vec::new(),
+            normals: Vec::new(),
+            colors: Vec::new(),
+            texture_coordinates: Vec::new(),
+            face_colors: Vec::new()
+        }
+    }
+
     /// Loads a mesh from an OFF file.
     /// See: http://shape.cs.princeton.edu/benchmark/documentation/off_format.html
-    pub fn try_load_from_off(path: &str, polygon_winding: PolygonWinding) -> Result<Self, String> {
+    ///
+    /// The keyword may be prefixed by `ST`, `C`, `N` and `4` in this order,
+    /// which add texture coordinates, colors and normals to each vertex or
+    /// give its position in homogeneous coordinates. Faces may be followed by
+    /// a color as well. Colors consist of 3 or 4 integers from 0 to 255 or
+    /// floats from 0.0 to 1.0, the alpha channel is ignored. Polygons with
+    /// more than three vertices are split into triangles.
+    pub fn try_load_from_off(path: &str, polygon_winding: PolygonWinding) -> Result<Self, MeshError> {
         // TODO: use String instead of &str for consistency with pixmap?
-        // TODO: do not use unwrap() statements, use try!() instead, NOPE: use ? question mark
-        // operator instead.
-        // TODO: do not use panic!() statements, see above
-       
-        let f = File::open(path).map_err(|e| e.to_string())?;
+        let f = File::open(path)?;
         let f = BufReader::new(f);
 
-        let mut num_vertices: u32 = 0;
-        let mut num_faces: u32 = 0;
+        let mut lines: Vec<String> = Vec::new();
+        for line in f.lines() {
+            lines.push(line?);
+        }
+
+        // Strip comments and skip blank lines.
+        let mut lines = lines.iter().enumerate()
+            .map(|(index, line)| match line.find('#') {
+                Some(end) => tokenize(&line[..end], index + 1),
+                None => tokenize(line, index + 1)
+            })
+            .filter(|tokens| !tokens.is_empty());
+
+        let mut tokens = lines.next().ok_or(MeshError::UnexpectedEof)?;
+        let keyword = OffKeyword::parse(&tokens[0])?;
+
+        // The header may follow the keyword on the same line.
+        tokens.remove(0);
+        if tokens.is_empty() {
+            tokens = lines.next().ok_or(MeshError::UnexpectedEof)?;
+        }
+        if tokens.len() != 3 {
+            return Err(tokens[0].error("Header has to consist of 3 elements"));
+        }
+        let num_vertices = tokens[0].parse::<usize>()?;
+        let num_faces = tokens[1].parse::<usize>()?;
+        // The number of edges is irrelevant as OFF files do not list edges.
+        tokens[2].parse::<usize>()?;
 
-        let mut vertices: Vec<Vec4> = Vec::new();
-        let mut faces: Vec<Face> = Vec::new();
+        // The counts are not trusted for preallocation, as they may be arbitrarily large.
+        let mut mesh = Mesh::new(Vec::new(), Vec::new());
 
-        enum FSM { OffKeyword, Header, Vertices, Faces, Accepted };
-        let mut current_state = FSM::OffKeyword;
+        for _ in 0..num_vertices {
+            let tokens = lines.next().ok_or(MeshError::UnexpectedEof)?;
+            keyword.parse_vertex(&tokens, &mut mesh)?;
+        }
 
-        for line in f.lines() {
-            let line = line.unwrap();
+        let mut face_colors: Vec<Option<Rgb>> = Vec::new();
+        for _ in 0..num_faces {
+            let tokens = lines.next().ok_or(MeshError::UnexpectedEof)?;
 
-            match current_state {
-                FSM::OffKeyword => {
-                    if line != "OFF" {
-                        panic!("Cannot find OFF keyword");
-                    }
-                    current_state = FSM::Header;
+            let n = tokens[0].parse::<usize>()?;
+            if n < 3 {
+                return Err(tokens[0].error("Faces need to have at least 3 vertices"));
+            }
+            if tokens.len() <= n {
+                return Err(tokens[0].error("Face has less vertices than specified"));
+            }
+
+            let mut polygon: Vec<usize> = Vec::with_capacity(n);
+            for token in &tokens[1..n + 1] {
+                let index = token.parse::<usize>()?;
+                if index >= mesh.vertices.len() {
+                    return Err(token.index_out_of_range(index as i64));
                 }
-                FSM::Header => {
-                    let splits: Vec<&str> = line.split_whitespace().collect();
+                polygon.push(index);
+            }
 
-                    if splits.len() != 3 {
-                        return Err("Header has to consist of 3 elements".to_string())
-                    }
+            let color = match tokens.len() - n - 1 {
+                0 => None,
+                // Indices into a color map that is not part of the file.
+                1 => None,
+                3 | 4 => Some(parse_off_color(&tokens[n + 1..])?),
+                _ => return Err(tokens[n + 1].error("Face colors need to have 3 or 4 components"))
+            };
+
+            for triangle in triangulate(&mesh.vertices, &polygon) {
+                let (a, b, c) = (triangle[0], triangle[1], triangle[2]);
+                mesh.faces.push(match polygon_winding {
+                    PolygonWinding::Clockwise => Face { a, b, c },
+                    PolygonWinding::CounterClockwise => Face { a, b: c, c: b },
+                });
+                face_colors.push(color);
+            }
+        }
 
-                    num_vertices = splits[0].parse::<u32>().map_err(|e| e.to_string())?;
-                    num_faces = splits[1].parse::<u32>().map_err(|e| e.to_string())?;
-                    let num_edges: u32 = splits[2].parse::<u32>().map_err(|e| e.to_string())?;
+        if num_faces == 0 {
+            return Err(MeshError::UnsupportedFeature("Meshes without faces are not supported".to_string()));
+        }
 
-                    if num_edges != 0 {
-                        return Err("The OFF loader only supports numEdges == 0".to_string())
-                    }
+        if let Some(tokens) = lines.next() {
+            return Err(tokens[0].error("Unexpected content after the last face"));
+        }
 
-                    current_state = FSM::Vertices;
-                }
-                FSM::Vertices => {
-                    let splits: Vec<&str> = line.split_whitespace().collect();
+        if face_colors.iter().any(|color| color.is_some()) {
+            mesh.face_colors = face_colors.iter().map(|color| color.unwrap_or(Rgb::white())).collect();
+        }
 
-                    if splits.len() != 3 {
-                        return Err("Vertices need to have exactly 3 coordinates".to_string())
-                    }
-                    
-                    let vertex = Vec4 {
-                        x: splits[0].parse::<f64>().map_err(|e| e.to_string())?,
-                        y: splits[1].parse::<f64>().map_err(|e| e.to_string())?,
-                        z: splits[2].parse::<f64>().map_err(|e| e.to_string())?,
-                        w: 1.0
-                    };
-
-                    vertices.push(vertex);
-
-                    if vertices.len() as u32 == num_vertices {
-                        current_state = FSM::Faces;
-                    }
-                }
-                FSM::Faces => {
-                    let splits: Vec<&str> = line.split_whitespace().collect();
+        Ok(mesh)
+    }
+
+    /// Saves the mesh as OFF file that `try_load_from_off` reads back with
+    /// clockwise winding. The keyword's prefixes are chosen according to the
+    /// vertex attributes, face colors are appended to the faces. Face groups
+    /// are not part of the format and get lost.
+    pub fn save_as_off(&self, path: &str) -> Result<(), MeshError> {
+        let f = File::create(path)?;
+        let mut f = BufWriter::new(f);
+
+        let mut keyword = String::new();
+        if !self.texture_coordinates.is_empty() {
+            keyword.push_str("ST");
+        }
+        if !self.colors.is_empty() {
+            keyword.push('C');
+        }
+        if !self.normals.is_empty() {
+            keyword.push('N');
+        }
+        keyword.push_str("OFF");
+
+        // Colors are written with a decimal point, as integers would be read
+        // as values from 0 to 255.
+        writeln!(f, "{}", keyword)?;
+        writeln!(f, "{} {} 0", self.vertices.len(), self.faces.len())?;
+        for (i, vertex) in self.vertices.iter().enumerate() {
+            let mut line = format!("{} {} {}", vertex.x, vertex.y, vertex.z);
+            if let Some(normal) = self.normals.get(i) {
+                line += &format!(" {} {} {}", normal.x, normal.y, normal.z);
+            }
+            if let Some(color) = self.colors.get(i) {
+                line += &format!(" {:?} {:?} {:?}", color.r, color.g, color.b);
+            }
+            if let Some(&(u, v)) = self.texture_coordinates.get(i) {
+                line += &format!(" {} {}", u, v);
+            }
+            writeln!(f, "{}", line)?;
+        }
+        for (i, face) in self.faces.iter().enumerate() {
+            let mut line = format!("3 {} {} {}", face.a, face.b, face.c);
+            if let Some(color) = self.face_colors.get(i) {
+                line += &format!(" {:?} {:?} {:?}", color.r, color.g, color.b);
+            }
+            writeln!(f, "{}", line)?;
+        }
 
-                    // TODO: check splits length
+        f.flush()?;
+        Ok(())
+    }
+
+    /// Looks up a face group by its name.
+    pub fn group(&self, name: &str) -> Option<&FaceGroup> {
+        self.groups.iter().find(|group| group.name == name)
+    }
+
+    /// Normalized normal of a face. It points towards the side from which the
+    /// face's vertices a, b and c appear in counter-clockwise order.
+    pub fn face_normal(&self, face: &Face) -> Vec4 {
+        let a = &self.vertices[face.a];
+        let b = &self.vertices[face.b];
+        let c = &self.vertices[face.c];
+        Vec4::cross(&(b.clone() - a.clone()), &(c.clone() - a.clone())).normalize()
+    }
+
+    /// Volume of the tetrahedron between the face and the origin, positive
+    /// if the face points away from the origin. Summed up over all faces of
+    /// a closed mesh, this is the enclosed volume.
+    pub fn signed_volume(&self, face: &Face) -> f64 {
+        let a = self.vertices[face.a].to_direction();
+        let b = self.vertices[face.b].to_direction();
+        let c = self.vertices[face.c].to_direction();
+        Vec4::dot(&a, &Vec4::cross(&b, &c)) / 6.0
+    }
+
+    /// Normal at the point of the face with the barycentric coordinates `u`
+    /// and `v` with respect to its second and third vertex, interpolated
+    /// between the vertex normals. Meshes without vertex normals are flat.
+    pub fn shading_normal(&self, face: &Face, u: f64, v: f64) -> Vec4 {
+        if self.normals.is_empty() {
+            return self.face_normal(face);
+        }
+
+        let normal = (1.0 - u - v) * self.normals[face.a].clone()
+            + u * self.normals[face.b].clone()
+            + v * self.normals[face.c].clone();
+        if Vec4::dot(&normal, &normal) > 0.0 {
+            normal.normalize()
+        }
+        else {
+            self.face_normal(face)
+        }
+    }
+
+    /// Texture coordinates at the point of the face with the barycentric
+    /// coordinates `u` and `v`, `None` if the mesh does not have any.
+    pub fn texture_coordinate(&self, face: &Face, u: f64, v: f64) -> Option<(f64, f64)> {
+        if self.texture_coordinates.is_empty() {
+            return None;
+        }
+
+        let (a, b, c) = (self.texture_coordinates[face.a], self.texture_coordinates[face.b], self.texture_coordinates[face.c]);
+        let w = 1.0 - u - v;
+        Some((w * a.0 + u * b.0 + v * c.0, w * a.1 + u * b.1 + v * c.1))
+    }
+
+    /// Replaces the vertex normals by the average of the adjacent faces'
+    /// normals, each weighted by the face's angle at the vertex. Faces only
+    /// share a vertex normal if their normals differ by at most
+    /// `crease_angle`, vertices on sharper edges are split to keep them hard.
+    pub fn compute_vertex_normals(&mut self, crease_angle: Angle) {
+        let cos_crease = crease_angle.to_radians().cos();
+
+        // Degenerate faces do not have a normal and do not contribute.
+        let face_normals: Vec<Option<Vec4>> = self.faces.iter()
+            .map(|face| Some(self.face_normal(face)).filter(|normal| normal.x.is_finite() && normal.y.is_finite() && normal.z.is_finite()))
+            .collect();
+
+        // Faces around every vertex together with their angle at the vertex.
+        let mut adjacent_faces: Vec<Vec<(usize, f64)>> = vec![Vec::new(); self.vertices.len()];
+        for (face_index, face) in self.faces.iter().enumerate() {
+            if face_normals[face_index].is_none() {
+                continue;
+            }
+            for &(vertex, previous, next) in &[(face.a, face.c, face.b), (face.b, face.a, face.c), (face.c, face.b, face.a)] {
+                let to_previous = (self.vertices[previous].clone() - self.vertices[vertex].clone()).normalize();
+                let to_next = (self.vertices[next].clone() - self.vertices[vertex].clone()).normalize();
+                let angle = Vec4::dot(&to_previous, &to_next).clamp(-1.0, 1.0).acos();
+                adjacent_faces[vertex].push((face_index, angle));
+            }
+        }
 
-                    let n = splits[0].parse::<u32>().unwrap();
-                    if n != (splits.len() as u32) - 1 {
-                        panic!("something is wrong");
+        let mut normals: Vec<Vec4> = vec![Vec4::new(0.0, 0.0, 0.0, 0.0); self.vertices.len()];
+        let mut has_normal: Vec<bool> = vec![false; self.vertices.len()];
+        // Vertex for every original vertex and distinct normal.
+        let mut splits: HashMap<(usize, [u64; 3]), usize> = HashMap::new();
+        // Original vertex of every vertex that is added by splitting.
+        let mut sources: Vec<usize> = Vec::new();
+
+        for face_index in 0..self.faces.len() {
+            let own_normal = match face_normals[face_index] {
+                Some(ref normal) => normal,
+                None => continue
+            };
+
+            let face = &self.faces[face_index];
+            let mut corners = [face.a, face.b, face.c];
+            for vertex in corners.iter_mut() {
+                let mut normal = Vec4::new(0.0, 0.0, 0.0, 0.0);
+                for &(other_index, angle) in &adjacent_faces[*vertex] {
+                    if let Some(ref other_normal) = face_normals[other_index] {
+                        if Vec4::dot(own_normal, other_normal) >= cos_crease - 1e-9 {
+                            normal = normal + angle * other_normal.clone();
+                        }
                     }
-                    if n != 3 {
-                        // TODO
-                        panic!("Our OFF loader can only handle 3 foo bar");
+                }
+                // Opposing faces, e.g. of a sheet without thickness, may cancel out.
+                let normal = if Vec4::dot(&normal, &normal) > 1e-24 { normal.normalize() } else { own_normal.clone() };
+
+                let key = (*vertex, [normal.x.to_bits(), normal.y.to_bits(), normal.z.to_bits()]);
+                *vertex = match splits.get(&key) {
+                    Some(&index) => index,
+                    None => {
+                        let index = if has_normal[*vertex] {
+                            sources.push(*vertex);
+                            normals.push(normal.clone());
+                            self.vertices.len() + sources.len() - 1
+                        }
+                        else {
+                            has_normal[*vertex] = true;
+                            normals[*vertex] = normal.clone();
+                            *vertex
+                        };
+                        splits.insert(key, index);
+                        index
                     }
+                };
+            }
+
+            self.faces[face_index] = Face { a: corners[0], b: corners[1], c: corners[2] };
+        }
+
+        for &source in &sources {
+            self.vertices.push(self.vertices[source].clone());
+            if !self.colors.is_empty() {
+                self.colors.push(self.colors[source]);
+            }
+            if !self.texture_coordinates.is_empty() {
+                self.texture_coordinates.push(self.texture_coordinates[source]);
+            }
+        }
+        self.normals = normals;
+    }
+}
+
+impl fmt::Display for MeshError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MeshError::Io(ref error) => write!(f, "{}", error),
+            MeshError::Parse { line: 0, column, ref message } => write!(f, "Byte {}: {}", column, message),
+            MeshError::Parse { line, column, ref message } => write!(f, "Line {}, column {}: {}", line, column, message),
+            MeshError::UnexpectedEof => write!(f, "Unexpected end of file"),
+            MeshError::IndexOutOfRange { line: 0, column, index } => write!(f, "Byte {}: Index {} does not exist", column, index),
+            MeshError::IndexOutOfRange { line, column, index } => write!(f, "Line {}, column {}: Index {} does not exist", line, column, index),
+            MeshError::UnsupportedFeature(ref message) => write!(f, "{}", message)
+        }
+    }
+}
+
+impl Error for MeshError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            MeshError::Io(ref error) => Some(error),
+            _ => None
+        }
+    }
+}
+
+impl From<io::Error> for MeshError {
+    fn from(error: io::Error) -> Self {
+        MeshError::Io(error)
+    }
+}
 
-                    let a: usize = splits[1].parse::<usize>().unwrap();
-                    let b: usize = splits[2].parse::<usize>().unwrap();
-                    let c: usize = splits[3].parse::<usize>().unwrap();
+impl<'a> Token<'a> {
+    pub fn parse<T>(&self) -> Result<T, MeshError> where T: FromStr, T::Err: fmt::Display {
+        self.text.parse::<T>().map_err(|e| self.error(&format!("{}: {}", self.text, e)))
+    }
 
-                    // TODO: check if vertex ids exists
+    /// Parse error located at this token.
+    pub fn error(&self, message: &str) -> MeshError {
+        MeshError::Parse { line: self.line, column: self.column, message: message.to_string() }
+    }
 
-                    let face = match polygon_winding {
-                        PolygonWinding::Clockwise => Face { a: a, b: b, c: c },
-                        PolygonWinding::CounterClockwise => Face { a: a, b: c, c: b },
-                    };
-                    faces.push(face);
+    pub fn index_out_of_range(&self, index: i64) -> MeshError {
+        MeshError::IndexOutOfRange { line: self.line, column: self.column, index }
+    }
+}
 
-                    if faces.len() as u32 == num_faces {
-                        current_state = FSM::Accepted;
-                    }
+/// Splits the line with the given number into whitespace separated tokens.
+pub fn tokenize<'a>(line: &'a str, line_number: usize) -> Vec<Token<'a>> {
+    let mut tokens: Vec<Token> = Vec::new();
+    let mut start: Option<usize> = None;
+    let mut column = 0;
+    let mut start_column = 0;
+    for (offset, c) in line.char_indices() {
+        column += 1;
+        match (c.is_whitespace(), start) {
+            (false, None) => {
+                start = Some(offset);
+                start_column = column;
+            }
+            (true, Some(token_start)) => {
+                tokens.push(Token { text: &line[token_start..offset], line: line_number, column: start_column });
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(token_start) = start {
+        tokens.push(Token { text: &line[token_start..], line: line_number, column: start_column });
+    }
+    tokens
+}
+
+impl OffKeyword {
+    fn parse(keyword: &Token) -> Result<Self, MeshError> {
+        let mut rest = match keyword.text.strip_suffix("OFF") {
+            Some(rest) => rest,
+            None => return Err(keyword.error("Cannot find OFF keyword"))
+        };
+
+        let off_keyword = OffKeyword {
+            texture_coordinates: strip_flag(&mut rest, "ST"),
+            colors: strip_flag(&mut rest, "C"),
+            normals: strip_flag(&mut rest, "N"),
+            homogeneous: strip_flag(&mut rest, "4")
+        };
+
+        match rest {
+            "" => Ok(off_keyword),
+            "n" => Err(MeshError::UnsupportedFeature("OFF files with a custom vertex dimension are not supported".to_string())),
+            _ => Err(keyword.error(&format!("Unknown keyword {}", keyword.text)))
+        }
+    }
+
+    /// Parses a vertex line, which consists of the position followed by the
+    /// normal, color and texture coordinates if present.
+    fn parse_vertex(&self, tokens: &[Token], mesh: &mut Mesh) -> Result<(), MeshError> {
+        let dimension = if self.homogeneous { 4 } else { 3 };
+        let normal_length = if self.normals { 3 } else { 0 };
+        let texture_length = if self.texture_coordinates { 2 } else { 0 };
+        let color_length = match tokens.len().checked_sub(dimension + normal_length + texture_length) {
+            Some(0) if !self.colors => 0,
+            Some(length @ 3..=4) if self.colors => length,
+            _ => return Err(tokens[0].error("Vertex has the wrong number of elements"))
+        };
+
+        let mut values: Vec<f64> = Vec::with_capacity(tokens.len());
+        for token in tokens {
+            values.push(token.parse::<f64>()?);
+        }
+
+        let w = if self.homogeneous { values[3] } else { 1.0 };
+        mesh.vertices.push(Vec4::new(values[0] / w, values[1] / w, values[2] / w, 1.0));
+
+        let mut offset = dimension;
+        if self.normals {
+            mesh.normals.push(Vec4::new(values[offset], values[offset + 1], values[offset + 2], 0.0));
+            offset += normal_length;
+        }
+        if self.colors {
+            mesh.colors.push(parse_off_color(&tokens[offset..offset + color_length])?);
+            offset += color_length;
+        }
+        if self.texture_coordinates {
+            mesh.texture_coordinates.push((values[offset], values[offset + 1]));
+        }
+
+        Ok(())
+    }
+}
+
+/// Removes `prefix` from the start of `text` and tells whether it was there.
+fn strip_flag(text: &mut &str, prefix: &str) -> bool {
+    match text.strip_prefix(prefix) {
+        Some(rest) => {
+            *text = rest;
+            true
+        }
+        None => false
+    }
+}
+
+/// Parses 3 or 4 color components, which are either integers from 0 to 255
+/// or floats from 0.0 to 1.0. The alpha channel is ignored.
+fn parse_off_color(tokens: &[Token]) -> Result<Rgb, MeshError> {
+    let scale = if tokens.iter().all(|token| token.text.parse::<u32>().is_ok()) { 255.0 } else { 1.0 };
+    let mut components = [0.0; 3];
+    for (component, token) in components.iter_mut().zip(tokens) {
+        *component = token.parse::<f64>()? / scale;
+    }
+    Ok(Rgb::new(components[0], components[1], components[2]))
+}
+
+/// Bits of the position's coordinates, for finding vertices at exactly the
+/// same position. Adding 0.0 turns -0.0 into 0.0, which would be considered
+/// different otherwise.
+pub fn position_key(position: &Vec4) -> [u64; 3] {
+    [(position.x + 0.0).to_bits(), (position.y + 0.0).to_bits(), (position.z + 0.0).to_bits()]
+}
+
+/// Splits a simple polygon given by indices into `vertices` into triangles
+/// by ear clipping. The triangles keep the polygon's orientation.
+pub fn triangulate(vertices: &[Vec4], polygon: &[usize]) -> Vec<[usize; 3]> {
+    if polygon.len() < 3 {
+        return Vec::new();
+    }
+
+    // Project onto the coordinate plane in which the polygon appears largest,
+    // using Newell's method to find its normal.
+    let mut normal = [0.0; 3];
+    for (i, &index) in polygon.iter().enumerate() {
+        let current = &vertices[index];
+        let next = &vertices[polygon[(i + 1) % polygon.len()]];
+        normal[0] += (current.y - next.y) * (current.z + next.z);
+        normal[1] += (current.z - next.z) * (current.x + next.x);
+        normal[2] += (current.x - next.x) * (current.y + next.y);
+    }
+    let axis = (0..3).max_by(|&a, &b| normal[a].abs().total_cmp(&normal[b].abs())).unwrap_or(2);
+    let orientation = if normal[axis] < 0.0 { -1.0 } else { 1.0 };
+    let project = |index: usize| {
+        let v = &vertices[index];
+        match axis {
+            0 => (v.y, v.z),
+            1 => (v.z, v.x),
+            _ => (v.x, v.y)
+        }
+    };
+    let cross = |a: (f64, f64), b: (f64, f64), c: (f64, f64)| {
+        orientation * ((b.0 - a.0) * (c.1 - a.1) - (b.1 - a.1) * (c.0 - a.0))
+    };
+
+    let mut remaining: Vec<usize> = polygon.to_vec();
+    let mut triangles: Vec<[usize; 3]> = Vec::with_capacity(polygon.len() - 2);
+
+    while remaining.len() > 3 {
+        let n = remaining.len();
+        let ear = (0..n).find(|&i| {
+            let (a, b, c) = (remaining[(i + n - 1) % n], remaining[i], remaining[(i + 1) % n]);
+            let (pa, pb, pc) = (project(a), project(b), project(c));
+            if cross(pa, pb, pc) <= 0.0 {
+                return false;
+            }
+            // No other vertex may lie within the ear.
+            remaining.iter().all(|&other| {
+                if other == a || other == b || other == c {
+                    return true;
                 }
-                _ => return Err("Something bad happened.".to_string())
+                let p = project(other);
+                cross(pa, pb, p) < 0.0 || cross(pb, pc, p) < 0.0 || cross(pc, pa, p) < 0.0
+            })
+        });
+
+        match ear {
+            Some(i) => {
+                triangles.push([remaining[(i + n - 1) % n], remaining[i], remaining[(i + 1) % n]]);
+                remaining.remove(i);
+            }
+            None => {
+                // Degenerate polygon, fall back to a triangle fan.
+                for i in 1..n - 1 {
+                    triangles.push([remaining[0], remaining[i], remaining[i + 1]]);
+                }
+                return triangles;
             }
         }
+    }
 
-        match current_state {
-            FSM::Accepted => Ok(Mesh {
-                vertices: vertices,
-                faces: faces
-            }),
-            _ => return Err("Something bad happened.".to_string())
+    triangles.push([remaining[0], remaining[1], remaining[2]]);
+    triangles
+}
+
+#[test]
+fn test_face_normal() {
+    let mesh = Mesh::try_load_from_off("testdata/meshes/good.off", PolygonWinding::Clockwise).unwrap();
+    assert_eq!(mesh.face_normal(&mesh.faces[0]), Vec4::new(0.0, 0.0, 1.0, 0.0));
+    assert_eq!(mesh.face_normal(&mesh.faces[1]), Vec4::new(0.0, 0.0, -1.0, 0.0));
+}
+
+#[test]
+fn test_vertex_normals() {
+    // A ridge along the x axis with a right angle between its two slopes.
+    let vertices = vec![Vec4::new(0.0, 0.0, 1.0, 1.0), Vec4::new(1.0, 0.0, 1.0, 1.0),
+                        Vec4::new(0.0, -1.0, 0.0, 1.0), Vec4::new(1.0, -1.0, 0.0, 1.0),
+                        Vec4::new(0.0, 1.0, 0.0, 1.0), Vec4::new(1.0, 1.0, 0.0, 1.0)];
+    let faces = vec![Face { a: 2, b: 3, c: 1 }, Face { a: 2, b: 1, c: 0 },
+                     Face { a: 4, b: 0, c: 1 }, Face { a: 4, b: 1, c: 5 }];
+
+    let mut smooth = Mesh::new(vertices.clone(), faces.clone());
+    smooth.compute_vertex_normals(Angle::Degrees(100.0));
+    assert_eq!(smooth.vertices.len(), 6);
+    assert!(Vec4::epsilon_compare(smooth.normals[0].clone(), Vec4::new(0.0, 0.0, 1.0, 0.0), 1e-12));
+    assert!(Vec4::epsilon_compare(smooth.normals[1].clone(), Vec4::new(0.0, 0.0, 1.0, 0.0), 1e-12));
+
+    // The ridge's vertices are split, so every face is flat.
+    let mut hard = Mesh::new(vertices, faces);
+    hard.compute_vertex_normals(Angle::Degrees(45.0));
+    assert_eq!(hard.vertices.len(), 8);
+    for face in &hard.faces {
+        for &index in &[face.a, face.b, face.c] {
+            assert!(Vec4::epsilon_compare(hard.normals[index].clone(), hard.face_normal(face), 1e-12));
         }
     }
+
+    // The vertex at the origin has a right angle in each plane, even though
+    // one of them is split into two faces.
+    let vertices = vec![Vec4::new(0.0, 0.0, 0.0, 1.0), Vec4::new(1.0, 0.0, 0.0, 1.0), Vec4::new(0.0, 1.0, 0.0, 1.0),
+                        Vec4::new(0.0, 1.0, 1.0, 1.0), Vec4::new(0.0, 0.0, 1.0, 1.0)];
+    let faces = vec![Face { a: 0, b: 1, c: 2 }, Face { a: 0, b: 2, c: 3 }, Face { a: 0, b: 3, c: 4 }];
+    let mut mesh = Mesh::new(vertices, faces);
+    mesh.compute_vertex_normals(Angle::Degrees(100.0));
+    assert!(Vec4::epsilon_compare(mesh.normals[0].clone(), Vec4::new(1.0, 0.0, 1.0, 0.0).normalize(), 1e-12));
+}
+
+#[test]
+fn test_shading_normal() {
+    let mut mesh = Mesh::try_load_from_off("testdata/meshes/good.off", PolygonWinding::Clockwise).unwrap();
+    let face = mesh.faces[0].clone();
+    assert_eq!(mesh.shading_normal(&face, 0.25, 0.25), mesh.face_normal(&face));
+
+    mesh.normals = vec![Vec4::new(0.0, 0.0, 1.0, 0.0); mesh.vertices.len()];
+    mesh.normals[face.b] = Vec4::new(1.0, 0.0, 0.0, 0.0);
+    assert!(Vec4::epsilon_compare(mesh.shading_normal(&face, 0.5, 0.0), Vec4::new(1.0, 0.0, 1.0, 0.0).normalize(), 1e-12));
+    assert!(Vec4::epsilon_compare(mesh.shading_normal(&face, 0.0, 0.5), Vec4::new(0.0, 0.0, 1.0, 0.0), 1e-12));
+}
+
+#[test]
+fn test_texture_coordinate() {
+    let mut mesh = Mesh::try_load_from_off("testdata/meshes/attributes.off", PolygonWinding::Clockwise).unwrap();
+    let face = mesh.faces[0].clone();
+    assert_eq!(mesh.texture_coordinate(&face, 0.0, 0.0), Some((0.0, 0.0)));
+    assert_eq!(mesh.texture_coordinate(&face, 0.25, 0.5), Some((0.25, 0.5)));
+
+    mesh.texture_coordinates.clear();
+    assert_eq!(mesh.texture_coordinate(&face, 0.25, 0.5), None);
 }
 
 #[test]
@@ -163,6 +710,145 @@ fn test_bad_mesh() {
 
     let mesh = Mesh::try_load_from_off("testdata/meshes/bad_5.off", PolygonWinding::Clockwise);
     assert!(mesh.is_err());
+
+    let mesh = Mesh::try_load_from_off("testdata/meshes/bad_6.off", PolygonWinding::Clockwise);
+    assert!(mesh.is_err());
+
+    let mesh = Mesh::try_load_from_off("testdata/meshes/bad_7.off", PolygonWinding::Clockwise);
+    assert!(mesh.is_err());
+
+    let mesh = Mesh::try_load_from_off("testdata/meshes/bad_8.off", PolygonWinding::Clockwise);
+    assert!(mesh.is_err());
+}
+
+#[test]
+fn test_mesh_errors() {
+    match Mesh::try_load_from_off("testdata/meshes/bad_1.off", PolygonWinding::Clockwise) {
+        Err(MeshError::UnexpectedEof) => {}
+        result => panic!("Unexpected result {:?}", result.map(|_| ()))
+    }
+
+    let error = Mesh::try_load_from_off("testdata/meshes/bad_5.off", PolygonWinding::Clockwise).err().unwrap();
+    match error {
+        MeshError::Parse { line: 3, column: 5, .. } => {}
+        _ => panic!("Unexpected error {:?}", error)
+    }
+    assert_eq!(error.to_string(), "Line 3, column 5: a: invalid float literal");
+
+    match Mesh::try_load_from_off("testdata/meshes/bad_6.off", PolygonWinding::Clockwise) {
+        Err(MeshError::IndexOutOfRange { line: 6, column: 7, index: 3 }) => {}
+        result => panic!("Unexpected result {:?}", result.map(|_| ()))
+    }
+
+    match Mesh::try_load_from_off("testdata/meshes/bad_7.off", PolygonWinding::Clockwise) {
+        Err(MeshError::UnsupportedFeature(_)) => {}
+        result => panic!("Unexpected result {:?}", result.map(|_| ()))
+    }
+
+    match Mesh::try_load_from_off("testdata/non_existing_mesh.off", PolygonWinding::Clockwise) {
+        Err(ref error @ MeshError::Io(_)) => assert!(error.source().is_some()),
+        result => panic!("Unexpected result {:?}", result.map(|_| ()))
+    }
+}
+
+#[test]
+fn test_malformed_meshes_do_not_panic() {
+    use std::fs;
+
+    fn load(path: &str) -> Result<Mesh, MeshError> {
+        match path.rsplit('.').next() {
+            Some("off") => Mesh::try_load_from_off(path, PolygonWinding::Clockwise),
+            Some("obj") => Mesh::try_load_from_obj(path, PolygonWinding::Clockwise),
+            Some("ply") => Mesh::try_load_from_ply(path, PolygonWinding::Clockwise),
+            Some("stl") => Mesh::try_load_from_stl(path, PolygonWinding::Clockwise),
+            _ => Err(MeshError::UnsupportedFeature(path.to_string()))
+        }
+    }
+
+    let truncated = ::std::env::temp_dir().join("raytracer_truncated_mesh");
+    for entry in fs::read_dir("testdata/meshes").unwrap() {
+        let path = entry.unwrap().path();
+        let name = path.file_name().unwrap().to_str().unwrap().to_string();
+        let extension = match path.extension() {
+            Some(extension) if extension != "mtl" => extension.to_str().unwrap().to_string(),
+            _ => continue
+        };
+        if name.starts_with("bad_") {
+            assert!(load(path.to_str().unwrap()).is_err(), "{} was loaded", name);
+        }
+
+        // Every truncation of a file either loads or fails gracefully.
+        let data = fs::read(&path).unwrap();
+        let truncated = truncated.with_extension(&extension);
+        for length in 0..data.len() {
+            fs::write(&truncated, &data[..length]).unwrap();
+            let _ = load(truncated.to_str().unwrap());
+        }
+    }
+}
+
+#[test]
+fn test_off_polygons() {
+    let mesh = Mesh::try_load_from_off("testdata/meshes/polygons.off", PolygonWinding::Clockwise).unwrap();
+    assert_eq!(mesh.vertices.len(), 10);
+    assert_eq!(mesh.faces.len(), 6);
+    assert!(mesh.face_colors.is_empty());
+
+    // The concave hexagon is covered exactly by its triangles.
+    let mut area = 0.0;
+    for face in &mesh.faces[..4] {
+        let (a, b, c) = (&mesh.vertices[face.a], &mesh.vertices[face.b], &mesh.vertices[face.c]);
+        let cross = Vec4::cross(&(b.clone() - a.clone()), &(c.clone() - a.clone()));
+        assert!(cross.z > 0.0);
+        area += cross.z / 2.0;
+    }
+    assert!((area - 3.0).abs() < 1e-12);
+
+    for face in &mesh.faces[4..] {
+        assert_eq!(mesh.face_normal(face), Vec4::new(0.0, -1.0, 0.0, 0.0));
+    }
+
+    let mesh = Mesh::try_load_from_off("testdata/meshes/polygons.off", PolygonWinding::CounterClockwise).unwrap();
+    assert_eq!(mesh.face_normal(&mesh.faces[5]), Vec4::new(0.0, 1.0, 0.0, 0.0));
+}
+
+#[test]
+fn test_off_colors() {
+    let mesh = Mesh::try_load_from_off("testdata/meshes/colors.off", PolygonWinding::Clockwise).unwrap();
+    assert_eq!(mesh.colors, vec![Rgb::new(1.0, 0.0, 0.0), Rgb::new(0.0, 1.0, 0.0), Rgb::new(0.0, 0.0, 1.0)]);
+    assert_eq!(mesh.face_colors, vec![Rgb::new(0.5, 0.25, 1.0), Rgb::white()]);
+    assert!(mesh.normals.is_empty() && mesh.texture_coordinates.is_empty());
+}
+
+#[test]
+fn test_off_vertex_attributes() {
+    let mesh = Mesh::try_load_from_off("testdata/meshes/attributes.off", PolygonWinding::Clockwise).unwrap();
+    assert_eq!(mesh.vertices[1], Vec4::new(1.0, 0.0, 0.0, 1.0));
+    assert_eq!(mesh.vertices[2], Vec4::new(0.0, 1.0, 0.0, 1.0));
+    assert_eq!(mesh.normals, vec![Vec4::new(0.0, 0.0, 1.0, 0.0); 3]);
+    assert_eq!(mesh.colors[1], Rgb::new(0.0, 1.0, 0.0));
+    assert_eq!(mesh.texture_coordinates, vec![(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)]);
+}
+
+#[test]
+fn test_off_round_trip() {
+    for name in &["polygons.off", "colors.off", "attributes.off"] {
+        let mesh = Mesh::try_load_from_off(&format!("testdata/meshes/{}", name), PolygonWinding::Clockwise).unwrap();
+        let path = ::std::env::temp_dir().join(format!("raytracer_round_trip_{}", name));
+        let path = path.to_str().unwrap();
+        mesh.save_as_off(path).unwrap();
+
+        let loaded = Mesh::try_load_from_off(path, PolygonWinding::Clockwise).unwrap();
+        assert_eq!(loaded.vertices, mesh.vertices);
+        assert_eq!(loaded.faces.len(), mesh.faces.len());
+        for (a, b) in loaded.faces.iter().zip(&mesh.faces) {
+            assert_eq!((a.a, a.b, a.c), (b.a, b.b, b.c));
+        }
+        assert_eq!(loaded.normals, mesh.normals);
+        assert_eq!(loaded.colors, mesh.colors);
+        assert_eq!(loaded.texture_coordinates, mesh.texture_coordinates);
+        assert_eq!(loaded.face_colors, mesh.face_colors);
+    }
 }
 
 #[test]
@@ -1,9 +1,116 @@
+use std::sync::Arc;
 use mesh::Mesh;
+use material::Material;
+use shape::{ Shape, TriangleMesh };
 use algebra::*;
 
 #[allow(dead_code)]
 #[derive(Clone)]
 pub struct Model {
-    pub mesh: Mesh,
+    /// Geometry in object space, shared as it may be instanced many times.
+    pub shape: Arc<dyn Shape>,
     pub position: Vec4,
+    /// Material of all parts that have not been assigned their own material.
+    pub material: Material,
+    group_materials: Vec<Material>,
+    /// Index into `group_materials` for every part that has its own material.
+    part_materials: Vec<Option<usize>>
+}
+
+#[allow(dead_code)]
+impl Model {
+    /// Creates a model and builds the acceleration structure for its mesh.
+    pub fn new(mesh: Mesh, position: Vec4) -> Self {
+        Model::with_shape(TriangleMesh::new(mesh), position)
+    }
+
+    /// Creates a model from any shape.
+    pub fn with_shape<S: Shape + 'static>(shape: S, position: Vec4) -> Self {
+        Model {
+            shape: Arc::new(shape),
+            position,
+            material: Material::default(),
+            group_materials: Vec::new(),
+            part_materials: Vec::new()
+        }
+    }
+
+    /// Moves a point from world space into the model's object space. Models
+    /// are only translated, so directions are the same in both spaces.
+    pub fn to_object_space(&self, point: &Vec4) -> Vec4 {
+        Vec4::new(point.x - self.position.x, point.y - self.position.y, point.z - self.position.z, point.w)
+    }
+
+    /// The model's triangle mesh, if its shape is one.
+    pub fn mesh(&self) -> Option<&Mesh> {
+        self.shape.as_mesh()
+    }
+
+    /// Overrides the material of all faces in the mesh' face group with the given name.
+    /// Faces that are part of multiple groups use the material that was assigned last.
+    pub fn set_group_material(&mut self, group_name: &str, material: Material) -> Result<(), String> {
+        let faces = match self.mesh().and_then(|mesh| mesh.group(group_name)) {
+            Some(group) => group.faces.clone(),
+            None => return Err(format!("The mesh has no face group named '{}'", group_name))
+        };
+
+        self.set_faces_material(&faces, material);
+        Ok(())
+    }
+
+    /// Overrides the material of the faces of a mesh or the sides of an
+    /// analytic shape with the given indices.
+    pub fn set_faces_material(&mut self, faces: &[usize], material: Material) {
+        self.group_materials.push(material);
+        let material_index = self.group_materials.len() - 1;
+        for &face_index in faces {
+            if face_index >= self.part_materials.len() {
+                self.part_materials.resize(face_index + 1, None);
+            }
+            self.part_materials[face_index] = Some(material_index);
+        }
+    }
+
+    /// Material of the face or side with the given index.
+    pub fn material(&self, part: usize) -> &Material {
+        match self.part_materials.get(part) {
+            Some(&Some(material_index)) => &self.group_materials[material_index],
+            _ => &self.material
+        }
+    }
+}
+
+#[test]
+fn test_group_material() {
+    use mesh::{ FaceGroup, PolygonWinding };
+    use color::Rgb;
+
+    let mut mesh = Mesh::try_load_from_off("testdata/meshes/good.off", PolygonWinding::Clockwise).unwrap();
+    mesh.groups.push(FaceGroup { name: "top".to_string(), faces: vec![1] });
+
+    let mut model = Model::new(mesh, Vec4::new(0.0, 0.0, 0.0, 1.0));
+    model.material.base_color = Rgb::new(1.0, 0.0, 0.0);
+
+    let green = Material { base_color: Rgb::new(0.0, 1.0, 0.0), ..Material::default() };
+    assert!(model.set_group_material("top", green.clone()).is_ok());
+    assert!(model.set_group_material("bottom", green.clone()).is_err());
+
+    assert_eq!(model.material(0).base_color, Rgb::new(1.0, 0.0, 0.0));
+    assert_eq!(model.material(1), &green);
+}
+
+#[test]
+fn test_shape_part_material() {
+    use shape::Cuboid;
+    use color::Rgb;
+
+    let mut model = Model::with_shape(Cuboid { size: Vec4::new(1.0, 1.0, 1.0, 0.0) }, Vec4::new(0.0, 0.0, 0.0, 1.0));
+    assert!(model.mesh().is_none());
+    assert!(model.set_group_material("top", Material::default()).is_err());
+
+    let green = Material { base_color: Rgb::new(0.0, 1.0, 0.0), ..Material::default() };
+    model.set_faces_material(&[2], green.clone());
+    assert_eq!(model.material(2), &green);
+    assert_eq!(model.material(0), &model.material);
+    assert_eq!(model.material(5), &model.material);
 }
@@ -0,0 +1,598 @@
+use std::collections::HashMap;
+use std::io;
+use std::io::{ BufReader, BufWriter };
+use std::io::prelude::*;
+use std::fs::File;
+use std::path::Path;
+use std::sync::Arc;
+use algebra::Vec4;
+use color::Rgb;
+use material::Material;
+use texture::Texture;
+use mesh::{ Face, FaceGroup, Mesh, MeshError, PolygonWinding, Token, tokenize, triangulate };
+use model::Model;
+
+/// Contents of a Wavefront OBJ file together with the materials defined in
+/// the MTL files it references.
+/// See: http://paulbourke.net/dataformats/obj/
+#[allow(dead_code)]
+pub struct ObjFile {
+    /// Contains a face group for every object and group name.
+    pub mesh: Mesh,
+    /// Faces per material name as assigned by `usemtl` statements.
+    pub material_groups: Vec<FaceGroup>,
+    pub materials: HashMap<String, Material>,
+    /// Problems with referenced files that did not prevent loading the mesh,
+    /// like missing or malformed MTL files.
+    pub warnings: Vec<MeshError>
+}
+
+/// Combination of indices a face refers to a vertex with. Every unique
+/// combination becomes a separate vertex of the mesh.
+#[derive(Clone,Copy,PartialEq,Eq,Hash)]
+struct Corner {
+    position: usize,
+    texture_coordinate: Option<usize>,
+    normal: Option<usize>
+}
+
+/// Values of a material in an MTL file that are only mapped to the
+/// material's parameters once all of them are known.
+struct MtlEntry {
+    material: Material,
+    specular: Option<Rgb>,
+    exponent: Option<f64>,
+    roughness: Option<f64>
+}
+
+#[allow(dead_code)]
+impl ObjFile {
+    /// Loads an OBJ file including the materials of its MTL files, whose
+    /// paths are relative to the OBJ file. Polygons are split into triangles.
+    /// Lines, points, curves and smoothing groups are ignored. MTL files and
+    /// textures that cannot be loaded are skipped and end up in the warnings,
+    /// their materials fall back to the default one or go without the texture.
+    pub fn try_load(path: &str, polygon_winding: PolygonWinding) -> Result<Self, MeshError> {
+        let f = File::open(path)?;
+        let f = BufReader::new(f);
+        let directory = Path::new(path).parent().unwrap_or(Path::new(""));
+
+        let mut positions: Vec<Vec4> = Vec::new();
+        let mut colors: Vec<Rgb> = Vec::new();
+        let mut texture_coordinates: Vec<(f64, f64)> = Vec::new();
+        let mut normals: Vec<Vec4> = Vec::new();
+
+        let mut corners: Vec<Corner> = Vec::new();
+        let mut corner_indices: HashMap<Corner, usize> = HashMap::new();
+        let mut vertices: Vec<Vec4> = Vec::new();
+        let mut faces: Vec<Face> = Vec::new();
+
+        let mut groups: Vec<FaceGroup> = Vec::new();
+        let mut active_groups: Vec<usize> = Vec::new();
+        let mut material_groups: Vec<FaceGroup> = Vec::new();
+        let mut active_material: Option<usize> = None;
+        let mut materials: HashMap<String, Material> = HashMap::new();
+        let mut warnings: Vec<MeshError> = Vec::new();
+
+        for (line_index, line) in f.lines().enumerate() {
+            let line = line?;
+            let content = match line.find('#') {
+                Some(index) => &line[..index],
+                None => &line[..]
+            };
+            let tokens = tokenize(content, line_index + 1);
+            if tokens.is_empty() {
+                continue;
+            }
+            let keyword = &tokens[0];
+
+            match keyword.text {
+                "v" => {
+                    let values = parse_floats(&tokens[1..])?;
+                    match values.len() {
+                        3 => positions.push(Vec4::new(values[0], values[1], values[2], 1.0)),
+                        4 => positions.push(Vec4::new(values[0] / values[3], values[1] / values[3], values[2] / values[3], 1.0)),
+                        // Common extension that appends a color to the position.
+                        6 => {
+                            positions.push(Vec4::new(values[0], values[1], values[2], 1.0));
+                            colors.push(Rgb::new(values[3], values[4], values[5]));
+                        }
+                        _ => return Err(keyword.error("Vertices need to have 3 or 4 coordinates"))
+                    }
+                }
+                "vt" => {
+                    let values = parse_floats(&tokens[1..])?;
+                    match values.len() {
+                        1 => texture_coordinates.push((values[0], 0.0)),
+                        2 | 3 => texture_coordinates.push((values[0], values[1])),
+                        _ => return Err(keyword.error("Texture coordinates need to have 1 to 3 components"))
+                    }
+                }
+                "vn" => {
+                    let values = parse_floats(&tokens[1..])?;
+                    if values.len() != 3 {
+                        return Err(keyword.error("Normals need to have exactly 3 coordinates"));
+                    }
+                    normals.push(Vec4::new(values[0], values[1], values[2], 0.0));
+                }
+                "f" => {
+                    if tokens.len() < 4 {
+                        return Err(keyword.error("Faces need to have at least 3 vertices"));
+                    }
+
+                    let mut polygon: Vec<usize> = Vec::with_capacity(tokens.len() - 1);
+                    for token in &tokens[1..] {
+                        let corner = parse_corner(token, positions.len(), texture_coordinates.len(), normals.len())?;
+                        let index = *corner_indices.entry(corner).or_insert_with(|| {
+                            corners.push(corner);
+                            vertices.push(positions[corner.position].clone());
+                            corners.len() - 1
+                        });
+                        polygon.push(index);
+                    }
+
+                    for triangle in triangulate(&vertices, &polygon) {
+                        let (a, b, c) = (triangle[0], triangle[1], triangle[2]);
+                        faces.push(match polygon_winding {
+                            PolygonWinding::Clockwise => Face { a, b, c },
+                            PolygonWinding::CounterClockwise => Face { a, b: c, c: b },
+                        });
+                        let face_index = faces.len() - 1;
+                        for &group_index in &active_groups {
+                            groups[group_index].faces.push(face_index);
+                        }
+                        if let Some(material_index) = active_material {
+                            material_groups[material_index].faces.push(face_index);
+                        }
+                    }
+                }
+                "o" | "g" => {
+                    active_groups = tokens[1..].iter().map(|name| find_or_add_group(&mut groups, name.text)).collect();
+                }
+                "usemtl" => {
+                    let name = tokens.get(1).ok_or_else(|| keyword.error("Missing material name"))?;
+                    active_material = Some(find_or_add_group(&mut material_groups, name.text));
+                }
+                "mtllib" => {
+                    for name in &tokens[1..] {
+                        match try_load_mtl(&directory.join(name.text), &mut warnings) {
+                            Ok(mtl_materials) => materials.extend(mtl_materials),
+                            Err(error) => warnings.push(error)
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if faces.is_empty() {
+            return Err(MeshError::UnsupportedFeature("Meshes without faces are not supported".to_string()));
+        }
+
+        let mut mesh = Mesh::new(vertices, faces);
+        mesh.groups = groups;
+        if colors.len() == positions.len() {
+            mesh.colors = corners.iter().map(|corner| colors[corner.position]).collect();
+        }
+        if corners.iter().any(|corner| corner.texture_coordinate.is_some()) {
+            mesh.texture_coordinates = corners.iter()
+                .map(|corner| corner.texture_coordinate.map_or((0.0, 0.0), |index| texture_coordinates[index]))
+                .collect();
+        }
+        if corners.iter().any(|corner| corner.normal.is_some()) {
+            mesh.normals = corners.iter()
+                .map(|corner| corner.normal.map_or(Vec4::new(0.0, 0.0, 0.0, 0.0), |index| normals[index].clone()))
+                .collect();
+
+            // Vertices without a normal use the one of the first face they belong to.
+            for face in &mesh.faces {
+                let face_normal = mesh.face_normal(face);
+                for &index in &[face.a, face.b, face.c] {
+                    if corners[index].normal.is_none() && mesh.normals[index] == Vec4::new(0.0, 0.0, 0.0, 0.0) {
+                        mesh.normals[index] = face_normal.clone();
+                    }
+                }
+            }
+        }
+
+        Ok(ObjFile {
+            mesh,
+            material_groups,
+            materials,
+            warnings
+        })
+    }
+
+    /// Creates a model whose faces use the materials assigned by `usemtl`.
+    /// Faces without a material or with an undefined one use the default material.
+    pub fn into_model(self, position: Vec4) -> Model {
+        let mut model = Model::new(self.mesh, position);
+        for group in &self.material_groups {
+            if let Some(material) = self.materials.get(&group.name) {
+                model.set_faces_material(&group.faces, material.clone());
+            }
+        }
+        model
+    }
+}
+
+#[allow(dead_code)]
+impl Mesh {
+    /// Loads the mesh of an OBJ file without its materials, see `ObjFile::try_load`.
+    pub fn try_load_from_obj(path: &str, polygon_winding: PolygonWinding) -> Result<Self, MeshError> {
+        ObjFile::try_load(path, polygon_winding).map(|obj| obj.mesh)
+    }
+
+    /// Saves the mesh as OBJ file that `try_load_from_obj` reads back with
+    /// clockwise winding. Vertex colors are appended to the positions and
+    /// face groups become `g` statements, face colors are not written.
+    pub fn save_as_obj(&self, path: &str) -> Result<(), MeshError> {
+        let f = File::create(path)?;
+        let mut f = BufWriter::new(f);
+
+        for (i, vertex) in self.vertices.iter().enumerate() {
+            match self.colors.get(i) {
+                Some(color) => writeln!(f, "v {} {} {} {} {} {}", vertex.x, vertex.y, vertex.z, color.r, color.g, color.b),
+                None => writeln!(f, "v {} {} {}", vertex.x, vertex.y, vertex.z)
+            }?;
+        }
+        for &(u, v) in &self.texture_coordinates {
+            writeln!(f, "vt {} {}", u, v)?;
+        }
+        for normal in &self.normals {
+            writeln!(f, "vn {} {} {}", normal.x, normal.y, normal.z)?;
+        }
+
+        // Every vertex has its own texture coordinate and normal, so all
+        // three indices of a face's vertex are the same.
+        let corner = |index: usize| match (self.texture_coordinates.is_empty(), self.normals.is_empty()) {
+            (true, true) => format!("{}", index + 1),
+            (false, true) => format!("{}/{}", index + 1, index + 1),
+            (true, false) => format!("{}//{}", index + 1, index + 1),
+            (false, false) => format!("{}/{}/{}", index + 1, index + 1, index + 1)
+        };
+
+        // A `g` statement lists all groups of the following faces, which
+        // keeps the face order even if groups overlap.
+        let mut face_groups: Vec<Vec<&str>> = vec![Vec::new(); self.faces.len()];
+        for group in &self.groups {
+            for &face_index in &group.faces {
+                face_groups[face_index].push(&group.name);
+            }
+        }
+
+        let mut active_groups: &[&str] = &[];
+        for (face, groups) in self.faces.iter().zip(&face_groups) {
+            if groups[..] != *active_groups {
+                writeln!(f, "g {}", groups.join(" "))?;
+                active_groups = groups;
+            }
+            writeln!(f, "f {} {} {}", corner(face.a), corner(face.b), corner(face.c))?;
+        }
+
+        f.flush()?;
+        Ok(())
+    }
+}
+
+/// Loads the materials of an MTL file by their names. Paths of textures are
+/// relative to the MTL file. Errors and warnings name the MTL file, as they
+/// would be confused with the ones of the OBJ file otherwise.
+///
+/// `Kd` is used as base color, `Ns` as Phong exponent to derive the roughness,
+/// `d` or `Tr` as opacity, `Ni` as index of refraction, `Ke` as emission and
+/// `map_Kd` and `map_Ke` as base color and emission texture. Textures that
+/// are used multiple times are only loaded once, textures that cannot be
+/// loaded are added to `warnings` and left out. The luminance of `Ks` scales
+/// the peak of the highlight, which lowers the Phong exponent accordingly, so a
+/// black `Ks` makes the material completely rough. The PBR extensions `Pr`
+/// and `Pm` set roughness and metallic directly.
+pub fn try_load_mtl(path: &Path, warnings: &mut Vec<MeshError>) -> Result<HashMap<String, Material>, MeshError> {
+    let mut mtl_warnings: Vec<MeshError> = Vec::new();
+    let result = parse_mtl(path, &mut mtl_warnings);
+    warnings.extend(mtl_warnings.into_iter().map(|warning| in_file(path, warning)));
+    result.map_err(|error| in_file(path, error))
+}
+
+fn parse_mtl(path: &Path, warnings: &mut Vec<MeshError>) -> Result<HashMap<String, Material>, MeshError> {
+    let f = File::open(path)?;
+    let f = BufReader::new(f);
+    let directory = path.parent().unwrap_or(Path::new(""));
+
+    let mut entries: Vec<(String, MtlEntry)> = Vec::new();
+    let mut textures: HashMap<String, Arc<Texture>> = HashMap::new();
+
+    for (line_index, line) in f.lines().enumerate() {
+        let line = line?;
+        let content = match line.find('#') {
+            Some(index) => &line[..index],
+            None => &line[..]
+        };
+        let tokens = tokenize(content, line_index + 1);
+        if tokens.is_empty() {
+            continue;
+        }
+        let keyword = &tokens[0];
+
+        if keyword.text == "newmtl" {
+            let name = tokens.get(1).ok_or_else(|| keyword.error("Missing material name"))?;
+            entries.push((name.text.to_string(), MtlEntry {
+                material: Material::default(),
+                specular: None,
+                exponent: None,
+                roughness: None
+            }));
+            continue;
+        }
+
+        let entry = match entries.last_mut() {
+            Some(&mut (_, ref mut entry)) => entry,
+            None => return Err(keyword.error("Material properties before the first newmtl"))
+        };
+        let material = &mut entry.material;
+
+        match keyword.text {
+            "Kd" => material.base_color = parse_color(&tokens)?,
+            "Ks" => entry.specular = Some(parse_color(&tokens)?),
+            "Ke" => material.emission = parse_color(&tokens)?,
+            "Ns" => entry.exponent = Some(parse_float(&tokens)?),
+            "d" => material.opacity = parse_float(&tokens)?,
+            "Tr" => material.opacity = 1.0 - parse_float(&tokens)?,
+            "Ni" => material.index_of_refraction = parse_float(&tokens)?,
+            "Pr" => entry.roughness = Some(parse_float(&tokens)?),
+            "Pm" => material.metallic = parse_float(&tokens)?,
+            "map_Kd" => material.base_color_texture = load_texture(&tokens, directory, &mut textures, warnings),
+            "map_Ke" => material.emission_texture = load_texture(&tokens, directory, &mut textures, warnings),
+            _ => {}
+        }
+    }
+
+    Ok(entries.into_iter().map(|(name, entry)| {
+        let mut material = entry.material;
+        material.roughness = match (entry.roughness, entry.specular, entry.exponent) {
+            (Some(roughness), _, _) => roughness,
+            (None, None, None) => material.roughness,
+            (None, specular, exponent) => {
+                // The peak of a normalized Phong lobe is proportional to the
+                // exponent plus 2, a weaker highlight thus means a wider lobe.
+                let exponent = exponent.unwrap_or_else(|| material.shininess());
+                let strength = specular.map_or(1.0, |specular| specular.luminance());
+                Material::roughness_from_shininess(strength * (exponent + 2.0) - 2.0)
+            }
+        };
+        (name, material)
+    }).collect())
+}
+
+/// Prefixes the error's message with the path of the file it occurred in.
+fn in_file(path: &Path, error: MeshError) -> MeshError {
+    match error {
+        MeshError::Io(e) => MeshError::Io(io::Error::new(e.kind(), format!("{}: {}", path.display(), e))),
+        MeshError::Parse { line, column, message } => {
+            MeshError::Parse { line, column, message: format!("{}: {}", path.display(), message) }
+        }
+        error => error
+    }
+}
+
+/// Loads the texture whose file name is the last of the tokens, as options
+/// like `-s 1 1 1` precede it, unless it has been loaded before. Failures
+/// are added to the warnings.
+fn load_texture(tokens: &[Token], directory: &Path, textures: &mut HashMap<String, Arc<Texture>>,
+                warnings: &mut Vec<MeshError>) -> Option<Arc<Texture>> {
+    let name = match tokens.last().filter(|_| tokens.len() > 1) {
+        Some(name) => name,
+        None => {
+            warnings.push(tokens[0].error("Missing texture file"));
+            return None;
+        }
+    };
+    let path = directory.join(name.text).to_string_lossy().into_owned();
+    if let Some(texture) = textures.get(&path) {
+        return Some(texture.clone());
+    }
+
+    match Texture::try_load(&path) {
+        Ok(texture) => {
+            let texture = Arc::new(texture);
+            textures.insert(path, texture.clone());
+            Some(texture)
+        }
+        Err(e) => {
+            warnings.push(name.error(&format!("Cannot load texture {}: {}", path, e)));
+            None
+        }
+    }
+}
+
+fn find_or_add_group(groups: &mut Vec<FaceGroup>, name: &str) -> usize {
+    match groups.iter().position(|group| group.name == name) {
+        Some(index) => index,
+        None => {
+            groups.push(FaceGroup { name: name.to_string(), faces: Vec::new() });
+            groups.len() - 1
+        }
+    }
+}
+
+/// Parses a face's vertex of the form `v`, `v/vt`, `v//vn` or `v/vt/vn`.
+fn parse_corner(token: &Token, positions: usize, texture_coordinates: usize, normals: usize) -> Result<Corner, MeshError> {
+    let mut parts = token.text.split('/');
+    let position = parse_index(token, parts.next().unwrap_or(""), positions)?;
+    let texture_coordinate = match parts.next() {
+        Some("") | None => None,
+        Some(part) => Some(parse_index(token, part, texture_coordinates)?)
+    };
+    let normal = match parts.next() {
+        Some("") | None => None,
+        Some(part) => Some(parse_index(token, part, normals)?)
+    };
+    if parts.next().is_some() {
+        return Err(token.error(&format!("Invalid face vertex {}", token.text)));
+    }
+
+    Ok(Corner {
+        position,
+        texture_coordinate,
+        normal
+    })
+}
+
+/// Converts a one-based index, or a negative one relative to the end of the
+/// `count` elements defined so far, into a zero-based index.
+fn parse_index(token: &Token, part: &str, count: usize) -> Result<usize, MeshError> {
+    let index = part.parse::<i64>().map_err(|e| token.error(&format!("{}: {}", token.text, e)))?;
+    let resolved = if index < 0 { count as i64 + index } else { index - 1 };
+    if index == 0 || resolved < 0 || resolved >= count as i64 {
+        return Err(token.index_out_of_range(index));
+    }
+    Ok(resolved as usize)
+}
+
+fn parse_floats(tokens: &[Token]) -> Result<Vec<f64>, MeshError> {
+    tokens.iter().map(|token| token.parse::<f64>()).collect()
+}
+
+/// Parses the single number following the keyword.
+fn parse_float(tokens: &[Token]) -> Result<f64, MeshError> {
+    match parse_floats(&tokens[1..])?[..] {
+        [value] => Ok(value),
+        _ => Err(tokens[0].error("Expected a single number"))
+    }
+}
+
+/// Parses the RGB color following the keyword. A single value is used for
+/// all three channels.
+fn parse_color(tokens: &[Token]) -> Result<Rgb, MeshError> {
+    match parse_floats(&tokens[1..])?[..] {
+        [value] => Ok(Rgb::new(value, value, value)),
+        [r, g, b] => Ok(Rgb::new(r, g, b)),
+        _ => Err(tokens[0].error("Colors need to have 1 or 3 components"))
+    }
+}
+
+#[test]
+fn test_obj() {
+    let obj = ObjFile::try_load("testdata/meshes/shapes.obj", PolygonWinding::Clockwise).unwrap();
+    let mesh = &obj.mesh;
+
+    // A quad, a pentagon and a triangle using negative indices.
+    assert_eq!(mesh.faces.len(), 2 + 3 + 1);
+    // The quad's corners all differ in their normal, the pentagon shares
+    // two corners with the triangle.
+    assert_eq!(mesh.vertices.len(), 4 + 5 + 1);
+    assert_eq!(mesh.texture_coordinates.len(), mesh.vertices.len());
+    assert_eq!(mesh.normals.len(), mesh.vertices.len());
+    assert_eq!(mesh.texture_coordinates[2], (1.0, 1.0));
+
+    for face in &mesh.faces[..2] {
+        assert_eq!(mesh.face_normal(face), Vec4::new(0.0, 0.0, 1.0, 0.0));
+    }
+    let triangle = &mesh.faces[5];
+    assert_eq!(mesh.vertices[triangle.b], Vec4::new(2.5, -1.0, 0.0, 1.0));
+    assert_eq!(mesh.vertices[triangle.c], Vec4::new(3.0, 0.0, 0.0, 1.0));
+    // Corners without a normal use the face's normal.
+    assert_eq!(mesh.normals[triangle.b], mesh.face_normal(triangle));
+
+    assert_eq!(mesh.group("quad").unwrap().faces, vec![0, 1]);
+    assert_eq!(mesh.group("polygons").unwrap().faces, vec![0, 1, 2, 3, 4]);
+    assert_eq!(mesh.group("pentagon").unwrap().faces, vec![2, 3, 4]);
+    assert_eq!(obj.material_groups.len(), 2);
+    assert_eq!(obj.material_groups[0].faces, vec![0, 1, 5]);
+}
+
+#[test]
+fn test_obj_materials() {
+    let obj = ObjFile::try_load("testdata/meshes/shapes.obj", PolygonWinding::Clockwise).unwrap();
+
+    let red = &obj.materials["red"];
+    assert_eq!(red.base_color, Rgb::new(1.0, 0.0, 0.0));
+    assert_eq!(red.opacity, 0.5);
+    assert_eq!(red.index_of_refraction, 1.33);
+    // Ks = 0.5 halves the highlight of Ns = 100.
+    assert!((red.shininess() - 49.0).abs() < 1e-9);
+    assert_eq!(red.base_color_texture.as_ref().unwrap().sample(0.5, 0.5), Rgb::new(1.0, 0.0, 0.0));
+
+    let matte = &obj.materials["matte"];
+    assert_eq!(matte.roughness, 1.0);
+    assert_eq!(matte.opacity, 0.75);
+    assert_eq!(matte.emission, Rgb::new(0.5, 0.5, 0.5));
+
+    let model = obj.into_model(Vec4::new(0.0, 0.0, 0.0, 1.0));
+    assert_eq!(model.material(0).base_color, Rgb::new(1.0, 0.0, 0.0));
+    assert_eq!(model.material(2).roughness, 1.0);
+    assert_eq!(model.material(5).base_color, Rgb::new(1.0, 0.0, 0.0));
+}
+
+#[test]
+fn test_obj_missing_mtl() {
+    let obj = ObjFile::try_load("testdata/meshes/missing_mtl.obj", PolygonWinding::Clockwise).unwrap();
+    assert_eq!(obj.mesh.faces.len(), 1);
+    assert!(obj.materials.is_empty());
+    match obj.warnings[..] {
+        [MeshError::Io(_)] => {}
+        _ => panic!("Unexpected warnings {:?}", obj.warnings)
+    }
+    assert!(obj.warnings[0].to_string().starts_with("testdata/meshes/missing.mtl: "), "{}", obj.warnings[0]);
+
+    let model = obj.into_model(Vec4::new(0.0, 0.0, 0.0, 1.0));
+    assert_eq!(*model.material(0), Material::default());
+}
+
+#[test]
+fn test_obj_missing_texture() {
+    let obj = ObjFile::try_load("testdata/meshes/missing_texture.obj", PolygonWinding::Clockwise).unwrap();
+    let broken = &obj.materials["broken"];
+    assert_eq!(broken.base_color, Rgb::new(1.0, 0.0, 0.0));
+    assert!(!broken.has_textures());
+    match obj.warnings[..] {
+        [MeshError::Parse { line: 4, column: 8, .. }, MeshError::Parse { line: 5, column: 8, .. }] => {}
+        _ => panic!("Unexpected warnings {:?}", obj.warnings)
+    }
+    for warning in &obj.warnings {
+        assert!(warning.to_string().contains("missing_texture.mtl: Cannot load texture"), "{}", warning);
+    }
+}
+
+#[test]
+fn test_obj_round_trip() {
+    let mut mesh = Mesh::try_load_from_off("testdata/meshes/attributes.off", PolygonWinding::Clockwise).unwrap();
+    mesh.groups.push(FaceGroup { name: "triangle".to_string(), faces: vec![0] });
+    let path = ::std::env::temp_dir().join("raytracer_obj_round_trip.obj");
+    let path = path.to_str().unwrap();
+    mesh.save_as_obj(path).unwrap();
+
+    let loaded = Mesh::try_load_from_obj(path, PolygonWinding::Clockwise).unwrap();
+    assert_eq!(loaded.vertices, mesh.vertices);
+    assert_eq!((loaded.faces[0].a, loaded.faces[0].b, loaded.faces[0].c), (0, 1, 2));
+    assert_eq!(loaded.normals, mesh.normals);
+    assert_eq!(loaded.colors, mesh.colors);
+    assert_eq!(loaded.texture_coordinates, mesh.texture_coordinates);
+    assert_eq!(loaded.group("triangle").unwrap().faces, vec![0]);
+
+    // Overlapping groups keep their faces.
+    let mut mesh = Mesh::try_load_from_off("testdata/meshes/polygons.off", PolygonWinding::Clockwise).unwrap();
+    mesh.groups.push(FaceGroup { name: "hexagon".to_string(), faces: vec![0, 1, 2, 3] });
+    mesh.groups.push(FaceGroup { name: "odd".to_string(), faces: vec![1, 3, 5] });
+    mesh.save_as_obj(path).unwrap();
+
+    let loaded = Mesh::try_load_from_obj(path, PolygonWinding::Clockwise).unwrap();
+    assert_eq!(loaded.faces.len(), mesh.faces.len());
+    for (a, b) in loaded.faces.iter().zip(&mesh.faces) {
+        assert_eq!(loaded.vertices[a.a], mesh.vertices[b.a]);
+        assert_eq!(loaded.vertices[a.b], mesh.vertices[b.b]);
+        assert_eq!(loaded.vertices[a.c], mesh.vertices[b.c]);
+    }
+    assert_eq!(loaded.group("hexagon").unwrap().faces, vec![0, 1, 2, 3]);
+    assert_eq!(loaded.group("odd").unwrap().faces, vec![1, 3, 5]);
+}
+
+#[test]
+fn test_bad_obj() {
+    match Mesh::try_load_from_obj("testdata/meshes/bad_1.obj", PolygonWinding::Clockwise) {
+        Err(MeshError::IndexOutOfRange { line: 4, column: 7, index: 4 }) => {}
+        result => panic!("Unexpected result {:?}", result.map(|_| ()))
+    }
+    assert!(Mesh::try_load_from_obj("testdata/meshes/bad_2.obj", PolygonWinding::Clockwise).is_err());
+    assert!(Mesh::try_load_from_obj("testdata/non_existing_mesh.obj", PolygonWinding::Clockwise).is_err());
+}
@@ -64,7 +64,7 @@ impl Pixmap {
         let mut current_state = FSM::MagicHeader;
 
         for line in f.lines() {
-            let line = line.unwrap();
+            let line = line.map_err(|e| e.to_string())?;
 
             match current_state {
                 FSM::MagicHeader => {
@@ -192,6 +192,7 @@ fn test_draw() {
 
 #[test]
 fn test_save_load_ppm() {
+    ::std::fs::create_dir_all("./testdata/output").unwrap();
     let mut pixmap = Pixmap::new(4, 4);
     pixmap.pixels[0].color.r = 5;
     pixmap.save_as_ppm("./testdata/output/pixmap_save_test.ppm".to_string()).unwrap();
@@ -0,0 +1,517 @@
+use std::io::prelude::*;
+use std::fs::File;
+use algebra::Vec4;
+use color::Rgb;
+use mesh::{ Face, Mesh, MeshError, PolygonWinding, Token, tokenize, triangulate };
+
+#[derive(Clone,Copy,PartialEq,Debug)]
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian
+}
+
+#[derive(Clone,Copy,PartialEq,Debug)]
+enum ScalarType {
+    Int8,
+    UInt8,
+    Int16,
+    UInt16,
+    Int32,
+    UInt32,
+    Float32,
+    Float64
+}
+
+#[derive(Clone,Copy,PartialEq,Debug)]
+enum PropertyType {
+    Scalar(ScalarType),
+    /// Variable number of items preceded by their count.
+    List { count: ScalarType, item: ScalarType }
+}
+
+#[derive(Clone,Debug)]
+struct Property {
+    name: String,
+    property_type: PropertyType
+}
+
+#[derive(Clone,Debug)]
+struct Element {
+    name: String,
+    /// Line of the element's declaration in the header.
+    line: usize,
+    count: usize,
+    properties: Vec<Property>
+}
+
+/// Value of a single property of an element, all numbers are converted to f64.
+#[derive(Clone,Debug)]
+enum Value {
+    Scalar(f64),
+    List(Vec<f64>)
+}
+
+/// Value of every property of an element and the offset where it starts.
+/// The offset is only turned into a line and column for errors, as that
+/// takes a pass over the file up to it.
+struct Record {
+    offset: usize,
+    values: Vec<Value>
+}
+
+/// Reads the numbers of a PLY file's body in the file's format.
+struct Reader<'a> {
+    format: Format,
+    /// The whole file, so that positions are relative to its start.
+    data: &'a [u8],
+    position: usize
+}
+
+impl ScalarType {
+    fn parse(token: &Token) -> Result<Self, MeshError> {
+        match token.text {
+            "char" | "int8" => Ok(ScalarType::Int8),
+            "uchar" | "uint8" => Ok(ScalarType::UInt8),
+            "short" | "int16" => Ok(ScalarType::Int16),
+            "ushort" | "uint16" => Ok(ScalarType::UInt16),
+            "int" | "int32" => Ok(ScalarType::Int32),
+            "uint" | "uint32" => Ok(ScalarType::UInt32),
+            "float" | "float32" => Ok(ScalarType::Float32),
+            "double" | "float64" => Ok(ScalarType::Float64),
+            _ => Err(token.error(&format!("Unknown property type {}", token.text)))
+        }
+    }
+
+    fn size(self) -> usize {
+        match self {
+            ScalarType::Int8 | ScalarType::UInt8 => 1,
+            ScalarType::Int16 | ScalarType::UInt16 => 2,
+            ScalarType::Int32 | ScalarType::UInt32 | ScalarType::Float32 => 4,
+            ScalarType::Float64 => 8
+        }
+    }
+
+    fn is_integer(self) -> bool {
+        !matches!(self, ScalarType::Float32 | ScalarType::Float64)
+    }
+}
+
+impl<'a> Reader<'a> {
+    /// Line and column of the given offset in ASCII files. Binary files
+    /// have no lines, so their line is 0 and their column is the offset.
+    fn location(&self, offset: usize) -> (usize, usize) {
+        if self.format != Format::Ascii {
+            return (0, offset);
+        }
+        let before = &self.data[..offset];
+        let line_start = before.iter().rposition(|&byte| byte == b'\n').map_or(0, |index| index + 1);
+        (before.iter().filter(|&&byte| byte == b'\n').count() + 1, offset - line_start + 1)
+    }
+
+    fn error(&self, offset: usize, message: &str) -> MeshError {
+        let (line, column) = self.location(offset);
+        MeshError::Parse { line, column, message: message.to_string() }
+    }
+
+    /// Skips the whitespace in front of the next value of ASCII files.
+    fn skip_whitespace(&mut self) {
+        if self.format == Format::Ascii {
+            while self.position < self.data.len() && self.data[self.position].is_ascii_whitespace() {
+                self.position += 1;
+            }
+        }
+    }
+
+    fn read(&mut self, scalar_type: ScalarType) -> Result<f64, MeshError> {
+        if self.format == Format::Ascii {
+            return self.read_ascii();
+        }
+
+        let size = scalar_type.size();
+        if self.position + size > self.data.len() {
+            return Err(MeshError::UnexpectedEof);
+        }
+        let mut bytes = [0u8; 8];
+        bytes[..size].copy_from_slice(&self.data[self.position..self.position + size]);
+        self.position += size;
+        if self.format == Format::BinaryBigEndian {
+            bytes[..size].reverse();
+        }
+
+        Ok(match scalar_type {
+            ScalarType::Int8 => bytes[0] as i8 as f64,
+            ScalarType::UInt8 => bytes[0] as f64,
+            ScalarType::Int16 => i16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+            ScalarType::UInt16 => u16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+            ScalarType::Int32 => i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+            ScalarType::UInt32 => u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+            ScalarType::Float32 => f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+            ScalarType::Float64 => f64::from_le_bytes(bytes)
+        })
+    }
+
+    fn read_ascii(&mut self) -> Result<f64, MeshError> {
+        self.skip_whitespace();
+        let start = self.position;
+        while self.position < self.data.len() && !self.data[self.position].is_ascii_whitespace() {
+            self.position += 1;
+        }
+        if start == self.position {
+            return Err(MeshError::UnexpectedEof);
+        }
+
+        let token = String::from_utf8_lossy(&self.data[start..self.position]);
+        token.parse::<f64>().map_err(|e| self.error(start, &format!("{}: {}", token, e)))
+    }
+
+    fn read_property(&mut self, property_type: PropertyType) -> Result<Value, MeshError> {
+        match property_type {
+            PropertyType::Scalar(scalar_type) => Ok(Value::Scalar(self.read(scalar_type)?)),
+            PropertyType::List { count, item } => {
+                self.skip_whitespace();
+                let start = self.position;
+                let count = self.read(count)?;
+                if count < 0.0 || count.fract() != 0.0 {
+                    return Err(self.error(start, &format!("Invalid list length {}", count)));
+                }
+                // The count is not trusted for preallocation, as it may be arbitrarily large.
+                let mut items = Vec::new();
+                for _ in 0..count as usize {
+                    items.push(self.read(item)?);
+                }
+                Ok(Value::List(items))
+            }
+        }
+    }
+}
+
+impl Element {
+    fn property_index(&self, names: &[&str]) -> Option<usize> {
+        self.properties.iter().position(|property| names.contains(&&property.name[..]))
+    }
+
+    /// Indices of the red, green and blue properties and the factor that
+    /// scales their values to the range from 0.0 to 1.0.
+    fn color_properties(&self) -> Option<([usize; 3], f64)> {
+        let red = self.property_index(&["red", "r", "diffuse_red"])?;
+        let green = self.property_index(&["green", "g", "diffuse_green"])?;
+        let blue = self.property_index(&["blue", "b", "diffuse_blue"])?;
+        let scale = match self.properties[red].property_type {
+            PropertyType::Scalar(scalar_type) if scalar_type.is_integer() => 1.0 / 255.0,
+            _ => 1.0
+        };
+        Some(([red, green, blue], scale))
+    }
+}
+
+#[allow(dead_code)]
+impl Mesh {
+    /// Loads a mesh from a PLY file in ASCII or binary format.
+    /// See: http://paulbourke.net/dataformats/ply/
+    ///
+    /// Vertices need `x`, `y` and `z` properties and may have normals,
+    /// colors and texture coordinates. Faces need a list of vertex indices
+    /// and may have colors. Polygons are split into triangles, all other
+    /// elements and properties are ignored.
+    pub fn try_load_from_ply(path: &str, polygon_winding: PolygonWinding) -> Result<Self, MeshError> {
+        let mut f = File::open(path)?;
+        let mut data: Vec<u8> = Vec::new();
+        f.read_to_end(&mut data)?;
+
+        let (format, elements, body_start) = parse_header(&data)?;
+        let mut reader = Reader {
+            format,
+            data: &data,
+            position: body_start
+        };
+
+        let mut mesh = Mesh::new(Vec::new(), Vec::new());
+        let mut found_vertices = false;
+
+        for element in &elements {
+            let mut records: Vec<Record> = Vec::new();
+            for _ in 0..element.count {
+                reader.skip_whitespace();
+                // Stops at the end of the file, as the count may be arbitrarily large.
+                if reader.position >= reader.data.len() {
+                    return Err(MeshError::UnexpectedEof);
+                }
+                let offset = reader.position;
+                let mut values = Vec::with_capacity(element.properties.len());
+                for property in &element.properties {
+                    values.push(reader.read_property(property.property_type)?);
+                }
+                records.push(Record { offset, values });
+            }
+
+            match &element.name[..] {
+                "vertex" => {
+                    read_vertices(element, &records, &mut mesh)?;
+                    found_vertices = true;
+                }
+                "face" => {
+                    if !found_vertices {
+                        return Err(MeshError::Parse {
+                            line: element.line,
+                            column: 1,
+                            message: "Faces have to follow the vertices".to_string()
+                        });
+                    }
+                    read_faces(element, &records, &reader, &polygon_winding, &mut mesh)?;
+                }
+                _ => {}
+            }
+        }
+
+        if mesh.faces.is_empty() {
+            return Err(MeshError::UnsupportedFeature("Meshes without faces are not supported".to_string()));
+        }
+
+        Ok(mesh)
+    }
+}
+
+/// Parses the header and returns the format, the elements and the offset of the body.
+fn parse_header(data: &[u8]) -> Result<(Format, Vec<Element>, usize), MeshError> {
+    let mut format: Option<Format> = None;
+    let mut elements: Vec<Element> = Vec::new();
+    let mut position = 0;
+    let mut line_number = 0;
+
+    loop {
+        let end = match data[position..].iter().position(|&byte| byte == b'\n') {
+            Some(end) => position + end,
+            None => return Err(MeshError::UnexpectedEof)
+        };
+        let line = String::from_utf8_lossy(&data[position..end]).into_owned();
+        position = end + 1;
+        line_number += 1;
+
+        let tokens = tokenize(&line, line_number);
+        let texts: Vec<&str> = tokens.iter().map(|token| token.text).collect();
+        let error = |message: &str| MeshError::Parse { line: line_number, column: 1, message: message.to_string() };
+
+        if line_number == 1 {
+            if texts != ["ply"] {
+                return Err(error("Cannot find ply keyword"));
+            }
+            continue;
+        }
+
+        match texts.first() {
+            Some(&"format") => {
+                format = Some(match texts.get(1) {
+                    Some(&"ascii") => Format::Ascii,
+                    Some(&"binary_little_endian") => Format::BinaryLittleEndian,
+                    Some(&"binary_big_endian") => Format::BinaryBigEndian,
+                    _ => return Err(error(&format!("Unknown format {}", line)))
+                });
+            }
+            Some(&"element") => {
+                if tokens.len() != 3 {
+                    return Err(error("Elements need a name and a count"));
+                }
+                elements.push(Element {
+                    name: tokens[1].text.to_string(),
+                    line: line_number,
+                    count: tokens[2].parse::<usize>()?,
+                    properties: Vec::new()
+                });
+            }
+            Some(&"property") => {
+                let property = match texts[..] {
+                    [_, "list", _, _, name] => Property {
+                        name: name.to_string(),
+                        property_type: PropertyType::List {
+                            count: ScalarType::parse(&tokens[2])?,
+                            item: ScalarType::parse(&tokens[3])?
+                        }
+                    },
+                    [_, _, name] => Property {
+                        name: name.to_string(),
+                        property_type: PropertyType::Scalar(ScalarType::parse(&tokens[1])?)
+                    },
+                    _ => return Err(error("Invalid property"))
+                };
+                match elements.last_mut() {
+                    Some(element) => element.properties.push(property),
+                    None => return Err(error("Properties need to belong to an element"))
+                }
+            }
+            Some(&"end_header") => {
+                // Records without properties take up no space, so nothing would limit their count.
+                if let Some(element) = elements.iter().find(|element| element.properties.is_empty() && element.count != 0) {
+                    return Err(element.error("Elements need at least one property"));
+                }
+                match format {
+                    Some(format) => return Ok((format, elements, position)),
+                    None => return Err(error("Missing format"))
+                }
+            }
+            Some(&"comment") | Some(&"obj_info") | None => {}
+            Some(_) => return Err(tokens[0].error(&format!("Unknown keyword {}", tokens[0].text)))
+        }
+    }
+}
+
+impl Element {
+    /// Parse error located at the element's declaration.
+    fn error(&self, message: &str) -> MeshError {
+        MeshError::Parse { line: self.line, column: 1, message: message.to_string() }
+    }
+}
+
+fn read_vertices(element: &Element, records: &[Record], mesh: &mut Mesh) -> Result<(), MeshError> {
+    let position = [element.property_index(&["x"]), element.property_index(&["y"]), element.property_index(&["z"])];
+    let position = match position {
+        [Some(x), Some(y), Some(z)] => [x, y, z],
+        _ => return Err(element.error("Vertices need x, y and z properties"))
+    };
+    let normal = match [element.property_index(&["nx"]), element.property_index(&["ny"]), element.property_index(&["nz"])] {
+        [Some(x), Some(y), Some(z)] => Some([x, y, z]),
+        _ => None
+    };
+    let texture_coordinate = match [element.property_index(&["u", "s", "texture_u", "texture_s"]),
+                                    element.property_index(&["v", "t", "texture_v", "texture_t"])] {
+        [Some(u), Some(v)] => Some([u, v]),
+        _ => None
+    };
+    let color = element.color_properties();
+
+    for record in records {
+        let value = |index: usize| match record.values[index] {
+            Value::Scalar(value) => Ok(value),
+            Value::List(_) => Err(element.error(&format!("Property {} must not be a list", element.properties[index].name)))
+        };
+
+        mesh.vertices.push(Vec4::new(value(position[0])?, value(position[1])?, value(position[2])?, 1.0));
+        if let Some(normal) = normal {
+            mesh.normals.push(Vec4::new(value(normal[0])?, value(normal[1])?, value(normal[2])?, 0.0));
+        }
+        if let Some(texture_coordinate) = texture_coordinate {
+            mesh.texture_coordinates.push((value(texture_coordinate[0])?, value(texture_coordinate[1])?));
+        }
+        if let Some((color, scale)) = color {
+            mesh.colors.push(Rgb::new(scale * value(color[0])?, scale * value(color[1])?, scale * value(color[2])?));
+        }
+    }
+
+    Ok(())
+}
+
+fn read_faces(element: &Element, records: &[Record], reader: &Reader, polygon_winding: &PolygonWinding,
+              mesh: &mut Mesh) -> Result<(), MeshError> {
+    let indices = match element.property_index(&["vertex_indices", "vertex_index"]) {
+        Some(index) => index,
+        None => return Err(element.error("Faces need a vertex_indices property"))
+    };
+    let color = element.color_properties();
+
+    for record in records {
+        let polygon = match record.values[indices] {
+            Value::List(ref items) => items,
+            Value::Scalar(_) => return Err(element.error("vertex_indices has to be a list"))
+        };
+        if polygon.len() < 3 {
+            return Err(reader.error(record.offset, "Faces need to have at least 3 vertices"));
+        }
+        let mut indices: Vec<usize> = Vec::with_capacity(polygon.len());
+        for &index in polygon {
+            // Also rejects NaN and infinity, whose fractional part is NaN.
+            if index.fract() != 0.0 {
+                return Err(reader.error(record.offset, &format!("Invalid vertex index {}", index)));
+            }
+            if index < 0.0 || index >= mesh.vertices.len() as f64 {
+                let (line, column) = reader.location(record.offset);
+                return Err(MeshError::IndexOutOfRange { line, column, index: index as i64 });
+            }
+            indices.push(index as usize);
+        }
+
+        let face_color = match color {
+            Some((color, scale)) => {
+                let mut components = [0.0; 3];
+                for (component, &index) in components.iter_mut().zip(&color) {
+                    *component = match record.values[index] {
+                        Value::Scalar(value) => scale * value,
+                        Value::List(_) => return Err(element.error("Face colors must not be lists"))
+                    };
+                }
+                Some(Rgb::new(components[0], components[1], components[2]))
+            }
+            None => None
+        };
+
+        for triangle in triangulate(&mesh.vertices, &indices) {
+            let (a, b, c) = (triangle[0], triangle[1], triangle[2]);
+            mesh.faces.push(match *polygon_winding {
+                PolygonWinding::Clockwise => Face { a, b, c },
+                PolygonWinding::CounterClockwise => Face { a, b: c, c: b },
+            });
+            if let Some(face_color) = face_color {
+                mesh.face_colors.push(face_color);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_ply_formats() {
+    let ascii = Mesh::try_load_from_ply("testdata/meshes/quads_ascii.ply", PolygonWinding::Clockwise).unwrap();
+    assert_eq!(ascii.vertices.len(), 6);
+    assert_eq!(ascii.faces.len(), 4);
+    assert_eq!(ascii.vertices[4], Vec4::new(2.0, 0.0, 0.5, 1.0));
+    assert_eq!(ascii.normals[0], Vec4::new(0.0, 0.0, 1.0, 0.0));
+    assert_eq!(ascii.colors[1], Rgb::new(1.0, 0.0, 0.0));
+    assert_eq!(ascii.face_colors, vec![Rgb::white(), Rgb::white(), Rgb::black(), Rgb::black()]);
+    assert_eq!(ascii.face_normal(&ascii.faces[0]), Vec4::new(0.0, 0.0, 1.0, 0.0));
+
+    for path in &["testdata/meshes/quads_binary_little_endian.ply", "testdata/meshes/quads_binary_big_endian.ply"] {
+        let binary = Mesh::try_load_from_ply(path, PolygonWinding::Clockwise).unwrap();
+        assert_eq!(binary.vertices, ascii.vertices);
+        assert_eq!(binary.normals, ascii.normals);
+        assert_eq!(binary.colors, ascii.colors);
+        assert_eq!(binary.face_colors, ascii.face_colors);
+        for (a, b) in binary.faces.iter().zip(&ascii.faces) {
+            assert_eq!((a.a, a.b, a.c), (b.a, b.b, b.c));
+        }
+    }
+}
+
+#[test]
+fn test_bad_ply() {
+    // Truncated binary body.
+    match Mesh::try_load_from_ply("testdata/meshes/bad_1.ply", PolygonWinding::Clockwise) {
+        Err(MeshError::UnexpectedEof) => {}
+        result => panic!("Unexpected result {:?}", result.map(|_| ()))
+    }
+    // Face referencing a vertex that does not exist.
+    match Mesh::try_load_from_ply("testdata/meshes/bad_2.ply", PolygonWinding::Clockwise) {
+        Err(MeshError::IndexOutOfRange { line: 31, column: 1, index: 6 }) => {}
+        result => panic!("Unexpected result {:?}", result.map(|_| ()))
+    }
+    // Unknown property type.
+    match Mesh::try_load_from_ply("testdata/meshes/bad_3.ply", PolygonWinding::Clockwise) {
+        Err(MeshError::Parse { line: 14, column: 10, .. }) => {}
+        result => panic!("Unexpected result {:?}", result.map(|_| ()))
+    }
+    // Vertex index with a fractional part.
+    match Mesh::try_load_from_ply("testdata/meshes/bad_4.ply", PolygonWinding::Clockwise) {
+        Err(MeshError::Parse { line: 13, column: 1, .. }) => {}
+        result => panic!("Unexpected result {:?}", result.map(|_| ()))
+    }
+    // Element without properties and a huge count.
+    match Mesh::try_load_from_ply("testdata/meshes/bad_5.ply", PolygonWinding::Clockwise) {
+        Err(MeshError::Parse { line: 3, column: 1, .. }) => {}
+        result => panic!("Unexpected result {:?}", result.map(|_| ()))
+    }
+    // Far fewer vertices than declared.
+    match Mesh::try_load_from_ply("testdata/meshes/bad_6.ply", PolygonWinding::Clockwise) {
+        Err(MeshError::UnexpectedEof) => {}
+        result => panic!("Unexpected result {:?}", result.map(|_| ()))
+    }
+    assert!(Mesh::try_load_from_ply("testdata/meshes/good.off", PolygonWinding::Clockwise).is_err());
+}
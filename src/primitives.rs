@@ -0,0 +1,359 @@
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use algebra::Vec4;
+use mesh::{ Face, Mesh };
+
+/// Constructors for simple shapes centered at the origin. Their faces are
+/// wound so that `face_normal` points outwards, the same as meshes loaded
+/// with the `PolygonWinding` matching their file. All shapes have vertex
+/// normals and all but the icosphere have texture coordinates. Shapes with
+/// an axis are aligned with the y axis, flat ones face upwards.
+#[allow(dead_code)]
+impl Mesh {
+    /// Sphere made of `segments` slices around the y axis and `rings`
+    /// stacks from pole to pole, the texture wraps around it once.
+    pub fn uv_sphere(radius: f64, segments: usize, rings: usize) -> Self {
+        let (segments, rings) = (segments.max(3), rings.max(2));
+        let mut mesh = Mesh::new(Vec::new(), Vec::new());
+
+        // The poles get one vertex per segment, all other rings need another
+        // one as the texture's seam runs through them.
+        let mut ring_starts: Vec<usize> = Vec::with_capacity(rings + 1);
+        for i in 0..rings + 1 {
+            ring_starts.push(mesh.vertices.len());
+            let theta = PI * i as f64 / rings as f64;
+            let is_pole = i == 0 || i == rings;
+            let count = if is_pole { segments } else { segments + 1 };
+            for j in 0..count {
+                let turns = if is_pole { (j as f64 + 0.5) / segments as f64 } else { j as f64 / segments as f64 };
+                let phi = 2.0 * PI * turns;
+                let normal = Vec4::new(theta.sin() * phi.cos(), theta.cos(), -theta.sin() * phi.sin(), 0.0);
+                let position = (radius * normal.clone()).into_point();
+                add_vertex(&mut mesh, position, normal, (turns, 1.0 - i as f64 / rings as f64));
+            }
+        }
+
+        let index = |i: usize, j: usize| ring_starts[i] + j;
+        for i in 0..rings {
+            for j in 0..segments {
+                // The triangles touching the poles would be degenerate.
+                if i != 0 {
+                    let below = if i == rings - 1 { index(i + 1, j) } else { index(i + 1, j + 1) };
+                    add_triangle(&mut mesh, index(i, j), below, index(i, j + 1));
+                }
+                if i != rings - 1 {
+                    add_triangle(&mut mesh, index(i, j), index(i + 1, j), index(i + 1, j + 1));
+                }
+            }
+        }
+
+        mesh
+    }
+
+    /// Sphere made of nearly equally sized triangles by subdividing every
+    /// face of an icosahedron into four triangles `subdivisions` times.
+    pub fn icosphere(radius: f64, subdivisions: u32) -> Self {
+        let t = (1.0 + 5.0f64.sqrt()) / 2.0;
+        let mut directions: Vec<Vec4> = [
+            (-1.0, t, 0.0), (1.0, t, 0.0), (-1.0, -t, 0.0), (1.0, -t, 0.0),
+            (0.0, -1.0, t), (0.0, 1.0, t), (0.0, -1.0, -t), (0.0, 1.0, -t),
+            (t, 0.0, -1.0), (t, 0.0, 1.0), (-t, 0.0, -1.0), (-t, 0.0, 1.0)
+        ].iter().map(|&(x, y, z)| Vec4::new(x, y, z, 0.0).normalize()).collect();
+        let mut triangles: Vec<[usize; 3]> = vec![
+            [0, 11, 5], [0, 5, 1], [0, 1, 7], [0, 7, 10], [0, 10, 11],
+            [1, 5, 9], [5, 11, 4], [11, 10, 2], [10, 7, 6], [7, 1, 8],
+            [3, 9, 4], [3, 4, 2], [3, 2, 6], [3, 6, 8], [3, 8, 9],
+            [4, 9, 5], [2, 4, 11], [6, 2, 10], [8, 6, 7], [9, 8, 1]
+        ];
+
+        for _ in 0..subdivisions {
+            // Neighbouring triangles share the vertices in the middle of their edges.
+            let mut midpoints: HashMap<(usize, usize), usize> = HashMap::new();
+            let mut midpoint = |a: usize, b: usize, directions: &mut Vec<Vec4>| {
+                *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                    directions.push((directions[a].clone() + directions[b].clone()).normalize());
+                    directions.len() - 1
+                })
+            };
+
+            let mut subdivided: Vec<[usize; 3]> = Vec::with_capacity(4 * triangles.len());
+            for &[a, b, c] in &triangles {
+                let ab = midpoint(a, b, &mut directions);
+                let bc = midpoint(b, c, &mut directions);
+                let ca = midpoint(c, a, &mut directions);
+                subdivided.extend_from_slice(&[[a, ab, ca], [b, bc, ab], [c, ca, bc], [ab, bc, ca]]);
+            }
+            triangles = subdivided;
+        }
+
+        let vertices = directions.iter().map(|direction| (radius * direction.clone()).into_point()).collect();
+        let faces = triangles.iter().map(|&[a, b, c]| Face { a, b, c }).collect();
+        let mut mesh = Mesh::new(vertices, faces);
+        mesh.normals = directions;
+        mesh
+    }
+
+    /// Cube with the given edge length. Every side has its own vertices and
+    /// is covered by the whole texture.
+    pub fn cube(size: f64) -> Self {
+        let h = size / 2.0;
+        let mut mesh = Mesh::new(Vec::new(), Vec::new());
+
+        // Normal and the directions of increasing texture coordinates for every side.
+        let sides = [
+            ((1.0, 0.0, 0.0), (0.0, 0.0, -1.0), (0.0, 1.0, 0.0)),
+            ((-1.0, 0.0, 0.0), (0.0, 0.0, 1.0), (0.0, 1.0, 0.0)),
+            ((0.0, 1.0, 0.0), (1.0, 0.0, 0.0), (0.0, 0.0, -1.0)),
+            ((0.0, -1.0, 0.0), (1.0, 0.0, 0.0), (0.0, 0.0, 1.0)),
+            ((0.0, 0.0, 1.0), (1.0, 0.0, 0.0), (0.0, 1.0, 0.0)),
+            ((0.0, 0.0, -1.0), (-1.0, 0.0, 0.0), (0.0, 1.0, 0.0))
+        ];
+        for &(n, u, v) in &sides {
+            let normal = Vec4::new(n.0, n.1, n.2, 0.0);
+            let first = mesh.vertices.len();
+            for &(s, t) in &[(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)] {
+                let (a, b) = (2.0 * s - 1.0, 2.0 * t - 1.0);
+                let position = Vec4::new(n.0 + a * u.0 + b * v.0, n.1 + a * u.1 + b * v.1, n.2 + a * u.2 + b * v.2, 0.0);
+                add_vertex(&mut mesh, (h * position).into_point(), normal.clone(), (s, t));
+            }
+            add_quad(&mut mesh, first, first + 1, first + 2, first + 3);
+        }
+
+        mesh
+    }
+
+    /// Closed cylinder of the given height around the y axis, the texture
+    /// wraps around its side once.
+    pub fn cylinder(radius: f64, height: f64, segments: usize) -> Self {
+        let segments = segments.max(3);
+        let h = height / 2.0;
+        let mut mesh = Mesh::new(Vec::new(), Vec::new());
+
+        for j in 0..segments + 1 {
+            let normal = radial(j as f64 / segments as f64);
+            let u = j as f64 / segments as f64;
+            add_vertex(&mut mesh, (radius * normal.clone()).into_point() + Vec4::new(0.0, -h, 0.0, 0.0), normal.clone(), (u, 0.0));
+            add_vertex(&mut mesh, (radius * normal.clone()).into_point() + Vec4::new(0.0, h, 0.0, 0.0), normal, (u, 1.0));
+        }
+        for j in 0..segments {
+            add_quad(&mut mesh, 2 * j, 2 * j + 2, 2 * j + 3, 2 * j + 1);
+        }
+
+        add_cap(&mut mesh, radius, h, segments, true);
+        add_cap(&mut mesh, radius, -h, segments, false);
+        mesh
+    }
+
+    /// Closed cone of the given height around the y axis with its apex at
+    /// the top, the texture wraps around its side once.
+    pub fn cone(radius: f64, height: f64, segments: usize) -> Self {
+        let segments = segments.max(3);
+        let h = height / 2.0;
+        let mut mesh = Mesh::new(Vec::new(), Vec::new());
+
+        // The side's normals tilt upwards by the cone's opening angle.
+        let slope_normal = |turns: f64| {
+            let direction = radial(turns);
+            Vec4::new(height * direction.x, radius, height * direction.z, 0.0).normalize()
+        };
+
+        // Every segment has its own apex, so that its normal points along
+        // the middle of the segment.
+        for j in 0..segments + 1 {
+            let turns = j as f64 / segments as f64;
+            let position = (radius * radial(turns)).into_point() + Vec4::new(0.0, -h, 0.0, 0.0);
+            add_vertex(&mut mesh, position, slope_normal(turns), (turns, 0.0));
+        }
+        for j in 0..segments {
+            let turns = (j as f64 + 0.5) / segments as f64;
+            let apex = mesh.vertices.len();
+            add_vertex(&mut mesh, Vec4::new(0.0, h, 0.0, 1.0), slope_normal(turns), (turns, 1.0));
+            add_triangle(&mut mesh, j, j + 1, apex);
+        }
+
+        add_cap(&mut mesh, radius, -h, segments, false);
+        mesh
+    }
+
+    /// Torus around the y axis. `major_radius` is the distance from its
+    /// center to the center of the tube, `minor_radius` the tube's radius.
+    /// The texture wraps around it once in both directions.
+    pub fn torus(major_radius: f64, minor_radius: f64, major_segments: usize, minor_segments: usize) -> Self {
+        let (major_segments, minor_segments) = (major_segments.max(3), minor_segments.max(3));
+        let mut mesh = Mesh::new(Vec::new(), Vec::new());
+
+        for i in 0..minor_segments + 1 {
+            let theta = 2.0 * PI * i as f64 / minor_segments as f64;
+            for j in 0..major_segments + 1 {
+                let direction = radial(j as f64 / major_segments as f64);
+                let normal = theta.cos() * direction.clone() + Vec4::new(0.0, theta.sin(), 0.0, 0.0);
+                let position = (major_radius * direction + minor_radius * normal.clone()).into_point();
+                add_vertex(&mut mesh, position, normal, (j as f64 / major_segments as f64, i as f64 / minor_segments as f64));
+            }
+        }
+
+        let index = |i: usize, j: usize| i * (major_segments + 1) + j;
+        for i in 0..minor_segments {
+            for j in 0..major_segments {
+                add_quad(&mut mesh, index(i, j), index(i, j + 1), index(i + 1, j + 1), index(i + 1, j));
+            }
+        }
+
+        mesh
+    }
+
+    /// Rectangle in the xz plane facing upwards, divided into a grid of
+    /// `x_segments` by `z_segments` quads. The texture covers it once.
+    pub fn grid_plane(width: f64, depth: f64, x_segments: usize, z_segments: usize) -> Self {
+        let (x_segments, z_segments) = (x_segments.max(1), z_segments.max(1));
+        let mut mesh = Mesh::new(Vec::new(), Vec::new());
+
+        for k in 0..z_segments + 1 {
+            let t = k as f64 / z_segments as f64;
+            for i in 0..x_segments + 1 {
+                let s = i as f64 / x_segments as f64;
+                let position = Vec4::new((s - 0.5) * width, 0.0, (0.5 - t) * depth, 1.0);
+                add_vertex(&mut mesh, position, Vec4::new(0.0, 1.0, 0.0, 0.0), (s, t));
+            }
+        }
+
+        let index = |i: usize, k: usize| k * (x_segments + 1) + i;
+        for k in 0..z_segments {
+            for i in 0..x_segments {
+                add_quad(&mut mesh, index(i, k), index(i + 1, k), index(i + 1, k + 1), index(i, k + 1));
+            }
+        }
+
+        mesh
+    }
+
+    /// Circle in the xz plane facing upwards. The texture's inscribed
+    /// circle covers it.
+    pub fn disk(radius: f64, segments: usize) -> Self {
+        let mut mesh = Mesh::new(Vec::new(), Vec::new());
+        add_cap(&mut mesh, radius, 0.0, segments.max(3), true);
+        mesh
+    }
+}
+
+/// Direction in the xz plane after the given number of turns around the
+/// y axis, counter-clockwise when seen from above.
+fn radial(turns: f64) -> Vec4 {
+    let phi = 2.0 * PI * turns;
+    Vec4::new(phi.cos(), 0.0, -phi.sin(), 0.0)
+}
+
+fn add_vertex(mesh: &mut Mesh, position: Vec4, normal: Vec4, texture_coordinate: (f64, f64)) {
+    mesh.vertices.push(position);
+    mesh.normals.push(normal);
+    mesh.texture_coordinates.push(texture_coordinate);
+}
+
+fn add_triangle(mesh: &mut Mesh, a: usize, b: usize, c: usize) {
+    mesh.faces.push(Face { a, b, c });
+}
+
+/// Adds two triangles for a quad whose vertices are in counter-clockwise order.
+fn add_quad(mesh: &mut Mesh, a: usize, b: usize, c: usize, d: usize) {
+    add_triangle(mesh, a, b, c);
+    add_triangle(mesh, a, c, d);
+}
+
+/// Adds a horizontal circle at height `y` that faces either up or down.
+fn add_cap(mesh: &mut Mesh, radius: f64, y: f64, segments: usize, up: bool) {
+    let normal = Vec4::new(0.0, if up { 1.0 } else { -1.0 }, 0.0, 0.0);
+    // Seen from the front, the texture is not mirrored on either side.
+    let texture_coordinate = |direction: &Vec4| (0.5 + direction.x / 2.0, 0.5 + normal.y * -direction.z / 2.0);
+
+    let center = mesh.vertices.len();
+    add_vertex(mesh, Vec4::new(0.0, y, 0.0, 1.0), normal.clone(), (0.5, 0.5));
+    for j in 0..segments {
+        let direction = radial(j as f64 / segments as f64);
+        let position = (radius * direction.clone()).into_point() + Vec4::new(0.0, y, 0.0, 0.0);
+        add_vertex(mesh, position, normal.clone(), texture_coordinate(&direction));
+    }
+
+    for j in 0..segments {
+        let (a, b) = (center + 1 + j, center + 1 + (j + 1) % segments);
+        if up {
+            add_triangle(mesh, center, a, b);
+        }
+        else {
+            add_triangle(mesh, center, b, a);
+        }
+    }
+}
+
+/// Volume enclosed by a closed mesh, negative if its faces point inwards.
+#[cfg(test)]
+fn signed_volume(mesh: &Mesh) -> f64 {
+    mesh.faces.iter().map(|face| mesh.signed_volume(face)).sum()
+}
+
+/// Checks the attributes and that the faces agree with the vertex normals.
+#[cfg(test)]
+fn check_primitive(mesh: &Mesh) {
+    assert_eq!(mesh.normals.len(), mesh.vertices.len());
+    for face in &mesh.faces {
+        let face_normal = mesh.face_normal(face);
+        for &index in &[face.a, face.b, face.c] {
+            assert!(Vec4::dot(&face_normal, &mesh.normals[index]) > 0.0);
+            assert!((Vec4::dot(&mesh.normals[index], &mesh.normals[index]) - 1.0).abs() < 1e-9);
+        }
+    }
+}
+
+#[test]
+fn test_closed_primitives() {
+    let sphere = 4.0 / 3.0 * PI;
+    let primitives = [
+        (Mesh::uv_sphere(1.0, 64, 32), sphere, 64 * 32 * 2 - 2 * 64),
+        (Mesh::icosphere(1.0, 3), sphere, 20 * 4 * 4 * 4),
+        (Mesh::cube(2.0), 8.0, 12),
+        (Mesh::cylinder(1.0, 2.0, 64), 2.0 * PI, 64 * 4),
+        (Mesh::cone(1.0, 3.0, 64), PI, 64 * 2),
+        (Mesh::torus(2.0, 0.5, 64, 32), 2.0 * PI * PI * 2.0 * 0.25, 64 * 32 * 2)
+    ];
+
+    for &(ref mesh, volume, face_count) in &primitives {
+        check_primitive(mesh);
+        assert_eq!(mesh.faces.len(), face_count);
+        // Faces pointing inwards would make the volume negative.
+        let relative_error = (signed_volume(mesh) - volume).abs() / volume;
+        assert!(relative_error < 0.02, "{} instead of {}", signed_volume(mesh), volume);
+    }
+
+    // Subdivision keeps all vertices on the sphere and shares them between faces.
+    let icosphere = Mesh::icosphere(2.0, 2);
+    assert_eq!(icosphere.vertices.len(), 162);
+    for vertex in &icosphere.vertices {
+        let squared_distance = vertex.x * vertex.x + vertex.y * vertex.y + vertex.z * vertex.z;
+        assert!((squared_distance - 4.0).abs() < 1e-9);
+    }
+    assert!(icosphere.texture_coordinates.is_empty());
+}
+
+#[test]
+fn test_flat_primitives() {
+    let plane = Mesh::grid_plane(4.0, 2.0, 4, 2);
+    let disk = Mesh::disk(1.0, 256);
+    assert_eq!(plane.vertices.len(), 5 * 3);
+    assert_eq!(plane.faces.len(), 4 * 2 * 2);
+    assert_eq!(disk.faces.len(), 256);
+
+    for &(ref mesh, area) in &[(plane, 8.0), (disk, PI)] {
+        check_primitive(mesh);
+        let mut total = 0.0;
+        for face in &mesh.faces {
+            assert_eq!(mesh.face_normal(face), Vec4::new(0.0, 1.0, 0.0, 0.0));
+            let (a, b, c) = (&mesh.vertices[face.a], &mesh.vertices[face.b], &mesh.vertices[face.c]);
+            total += Vec4::cross(&(b.clone() - a.clone()), &(c.clone() - a.clone())).y / 2.0;
+        }
+        assert!((total - area).abs() < 1e-3);
+    }
+
+    // The texture is not mirrored when seen from above.
+    let plane = Mesh::grid_plane(2.0, 2.0, 1, 1);
+    let corner = plane.vertices.iter().position(|vertex| vertex.x > 0.0 && vertex.z < 0.0).unwrap();
+    assert_eq!(plane.texture_coordinates[corner], (1.0, 1.0));
+}
@@ -0,0 +1,63 @@
+/// Small pseudo random number generator based on SplitMix64.
+/// It is not suited for cryptography but fast and good enough for sampling.
+/// Renderers seed one generator per pixel so that the image does not depend
+/// on the order in which pixels are rendered.
+#[derive(Clone,Debug)]
+pub struct Random {
+    state: u64
+}
+
+#[allow(dead_code)]
+impl Random {
+    pub fn new(seed: u64) -> Self {
+        Random {
+            state: seed
+        }
+    }
+
+    /// Creates a generator whose sequence only depends on the pixel coordinates.
+    pub fn for_pixel(x: u32, y: u32) -> Self {
+        let mut random = Random::new(((x as u64) << 32) | y as u64);
+        // Decorrelate neighbouring pixels whose seeds only differ by a few bits.
+        let seed = random.next_u64();
+        Random::new(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniformly distributed number in [0, 1).
+    pub fn next_f64(&mut self) -> f64 {
+        // Use the upper 53 bits, which is the precision of a f64's mantissa.
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+#[test]
+fn test_random_range() {
+    let mut random = Random::new(42);
+    let mut sum = 0.0;
+    for _ in 0..10000 {
+        let x = random.next_f64();
+        assert!((0.0..1.0).contains(&x));
+        sum += x;
+    }
+    assert!((sum / 10000.0 - 0.5).abs() < 0.02);
+}
+
+#[test]
+fn test_random_deterministic() {
+    let mut a = Random::for_pixel(3, 7);
+    let mut b = Random::for_pixel(3, 7);
+    let mut c = Random::for_pixel(7, 3);
+    for _ in 0..10 {
+        let x = a.next_u64();
+        assert_eq!(x, b.next_u64());
+        assert!(x != c.next_u64());
+    }
+}
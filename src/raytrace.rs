@@ -1,186 +1,700 @@
 use std::vec::Vec;
+use std::borrow::Cow;
+use std::mem;
+use std::thread;
+use std::sync::atomic::{ AtomicUsize, Ordering };
 use scene::Scene;
 use pixmap::Pixmap;
-use color::Color;
+use color::Rgb;
+use film::Film;
+use filter::Filter;
+use sampling::{ concentric_disk, cosine_hemisphere, phong_lobe };
 use model::Model;
-use algebra::{ Angle, Vec4, Mat4 };
+use shape::ShapeHit;
+use material::{ Material, fresnel_dielectric };
+use random::Random;
+use algebra::{ Vec4, Mat4 };
+use camera::{ Eye, Projection, StereoLayout, StereoRig };
 
 pub struct Raytrace {
     scene: Scene,
     // TODO: is it a good idea that the Raytrace struct consumes the Pixmap?
-    pub pixmap: Pixmap
+    pub pixmap: Pixmap,
+    pub settings: RenderSettings
+}
+
+/// Determines how the color of a pixel is derived from what its ray hits.
+#[allow(dead_code)]
+#[derive(Clone,Copy,PartialEq,Debug)]
+pub enum RenderMode {
+    /// Paints every hit in the base color of its material without
+    /// considering any lights, useful for fast previews.
+    Flat,
+    /// Lambert diffuse and Phong specular lighting by the scene's lights,
+    /// plus perfect reflection and refraction by recursively traced rays.
+    Shaded,
+    /// Physically based global illumination by Monte Carlo path tracing.
+    /// Requires many samples per pixel to converge.
+    PathTraced
+}
+
+/// Determines where within a pixel its samples are placed.
+#[allow(dead_code)]
+#[derive(Clone,Copy,PartialEq,Debug)]
+pub enum PixelSampling {
+    /// All samples go through the pixel's center, which does not remove
+    /// aliasing but still averages the noise of path tracing.
+    Center,
+    /// Every sample is placed at a random position within the pixel.
+    Jittered,
+    /// The pixel is divided into a grid of n x n cells, with n being the
+    /// square root of the sample count rounded down, and every cell gets a
+    /// random sample. Remaining samples are jittered across the whole pixel.
+    Stratified
+}
+
+/// Number of bounces after which paths may be terminated by Russian roulette.
+const RUSSIAN_ROULETTE_DEPTH: u32 = 3;
+
+/// Distance by which rays starting on a surface are moved away from it along
+/// its normal so that they do not hit the surface they start on.
+const SURFACE_BIAS: f64 = 1e-4;
+
+/// Options that control how the image gets rendered.
+#[derive(Clone,Debug)]
+pub struct RenderSettings {
+    pub mode: RenderMode,
+    /// Maximum number of nested reflections and refractions that are traced,
+    /// or the maximum number of bounces of a path. 0 disables them entirely.
+    pub max_depth: u32,
+    /// Number of rays that are traced per pixel.
+    pub samples_per_pixel: u32,
+    pub pixel_sampling: PixelSampling,
+    /// Weights the samples into the pixels around them. Filters wider than
+    /// a pixel make the output depend on `tile_size` by rounding errors.
+    pub filter: Filter,
+    /// Number of worker threads, 0 uses one thread per available CPU core.
+    /// The output does not depend on this value.
+    pub threads: usize,
+    /// Edge length of the square tiles the image is split into.
+    pub tile_size: u32
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        RenderSettings {
+            mode: RenderMode::Shaded,
+            max_depth: 5,
+            samples_per_pixel: 1,
+            pixel_sampling: PixelSampling::Center,
+            filter: Filter::default(),
+            threads: 0,
+            tile_size: 16
+        }
+    }
+}
+
+/// Rectangular part of the image that is rendered by a single thread at a time.
+#[derive(Clone,Debug)]
+struct Tile {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32
+}
+
+struct RenderedTile {
+    index: usize,
+    film: Film
 }
 
 #[derive(Clone)]
 struct Ray {
-    start: Vec4,
-    end: Vec4,
-    pixmap_coords: (u32, u32)
+    origin: Vec4,
+    /// Always normalized.
+    direction: Vec4
 }
 
-struct RayTriangleIntersection {
+impl Ray {
+    fn new(origin: Vec4, direction: Vec4) -> Self {
+        Ray {
+            origin,
+            direction: direction.normalize()
+        }
+    }
+}
+
+/// The closest intersection of a ray with the scene.
+#[allow(dead_code)]
+struct RayIntersection {
     ray: Ray,
-    hit_position: Vec4
+    hit_position: Vec4,
+    distance: f64,
+    model_index: usize,
+    /// Intersection with the model's shape in object space.
+    hit: ShapeHit
 }
 
 impl Raytrace {
     pub fn new(scene: Scene, pixmap: Pixmap) -> Self {
         Raytrace {
             scene: scene,
-            pixmap: pixmap
+            pixmap: pixmap,
+            settings: RenderSettings::default()
         }
     }
 
     fn calc_projection_matrix(&self) -> Mat4 {
-        Mat4::perspective(Angle::Degrees(45.0),
-                          (self.pixmap.width / self.pixmap.height) as f64,
-                          self.scene.camera.position.z / 10.0,
-                          self.scene.camera.position.z * 10.0)
+        self.scene.camera.projection_matrix(self.pixmap.width, self.pixmap.height)
     }
 
     fn calc_view_matrix(&self) -> Mat4 {
-        Mat4::look_at(&self.scene.camera.position,
-                      &self.scene.camera.look_at,
-                      &self.scene.camera.up)
+        self.scene.camera.view_matrix()
     }
 
-    fn calc_model_matrix(&self, model: &Model) -> Mat4 {
-        Mat4::translate(&model.position)
+    /// Ray through the image position (x, y) given in pixels, where pixel
+    /// edges lie on integer coordinates. Returns `None` for positions that
+    /// the camera does not see, like the corners of a fisheye image.
+    fn generate_primary_ray(&self, x: f64, y: f64, view_matrix: &Mat4, projection_matrix: &Mat4) -> Option<Ray> {
+        let camera = &self.scene.camera;
+        if camera.is_panoramic() {
+            let (width, height) = (self.pixmap.width as f64, self.pixmap.height as f64);
+            let (x, y) = (x / width * 2.0 - 1.0, y / height * 2.0 - 1.0);
+            return camera.panoramic_direction(x, y, width / height)
+                .map(|direction| Ray::new(camera.panoramic_origin(x), direction));
+        }
+
+        let start = Vec4::unproject(Vec4::new(x, y, 0.0, 1.0),
+                                    view_matrix, projection_matrix,
+                                    self.pixmap.width, self.pixmap.height);
+        let end = Vec4::unproject(Vec4::new(x, y, 1.0, 1.0),
+                                  view_matrix, projection_matrix,
+                                  self.pixmap.width, self.pixmap.height);
+        Some(Ray::new(start.clone(), end - start))
     }
 
-    fn generate_primary_rays(&self, view_matrix: Mat4, projection_matrix: Mat4) -> Vec<Ray> {
-        let mut rays: Vec<Ray> = Vec::new();
+    /// Turns a ray of the pinhole camera into one starting at a random point
+    /// on the lens that passes through the same point on the focus plane.
+    fn apply_depth_of_field(&self, ray: Ray, random: &mut Random) -> Ray {
+        let camera = &self.scene.camera;
+        if camera.aperture_radius <= 0.0 {
+            return ray;
+        }
 
-        for x in 0..self.pixmap.width {
-            for y in 0..self.pixmap.height {
-                let ray = Ray {
-                    start: Vec4::unproject(Vec4::new(x as f64, y as f64, 0.0, 1.0),
-                                           &view_matrix, &projection_matrix,
-                                           self.pixmap.width, self.pixmap.height),
-                    end: Vec4::unproject(Vec4::new(x as f64, y as f64, 1.0, 1.0),
-                                         &view_matrix, &projection_matrix,
-                                         self.pixmap.width, self.pixmap.height),
-                    pixmap_coords: (x, y)
-                };
+        let (forward, right, up) = camera.basis();
+        let cos_theta = Vec4::dot(&ray.direction, &forward);
+        if cos_theta <= 0.0 {
+            // Panoramic rays pointing sideways or backwards never reach the focus plane.
+            return ray;
+        }
+        let origin_depth = Vec4::dot(&(ray.origin.clone() - camera.position.clone()), &forward);
+        let t = (camera.focus_distance - origin_depth) / cos_theta;
+        let focus_point = ray.origin.clone() + t * ray.direction.clone();
+
+        // Perspective rays start on the near plane, but the lens sits at the
+        // camera's position. Orthographic rays are parallel, so each one gets
+        // a lens around its own origin. Panoramic rays already start at the camera.
+        let lens_center = match camera.projection {
+            Projection::Perspective => camera.position.clone(),
+            _ => ray.origin
+        };
+        let (lens_x, lens_y) = concentric_disk(random.next_f64(), random.next_f64());
+        let lens_point = lens_center + (camera.aperture_radius * lens_x) * right
+                                    + (camera.aperture_radius * lens_y) * up;
+        Ray::new(lens_point.clone(), focus_point - lens_point)
+    }
 
-                rays.push(ray);
+    /// Splits the image into tiles of at most `tile_size` x `tile_size` pixels.
+    fn generate_tiles(&self) -> Vec<Tile> {
+        let tile_size = self.settings.tile_size.max(1);
+        let mut tiles: Vec<Tile> = Vec::new();
+
+        let mut y = 0;
+        while y < self.pixmap.height {
+            let mut x = 0;
+            while x < self.pixmap.width {
+                tiles.push(Tile {
+                    x,
+                    y,
+                    width: tile_size.min(self.pixmap.width - x),
+                    height: tile_size.min(self.pixmap.height - y)
+                });
+                x += tile_size;
             }
+            y += tile_size;
         }
 
-        rays
+        tiles
     }
 
-    fn calculate_triangle_intersections(&self, rays: Vec<Ray>) -> Vec<RayTriangleIntersection> {
-        let mut intersections: Vec<RayTriangleIntersection> = Vec::new();
+    fn thread_count(&self) -> usize {
+        match self.settings.threads {
+            0 => thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+            n => n
+        }
+    }
+
+    /// Number of pixels by which the filter reaches beyond the pixel a sample lies in.
+    fn filter_margin(&self) -> u32 {
+        (self.settings.filter.radius() - 0.5).ceil().max(0.0) as u32
+    }
 
-        for ref ray in &rays {
-            for ref model in &self.scene.models {
-                if let Some(intersection) = self.calculate_model_mesh_intersection(&model, &ray) {
-                    intersections.push(intersection);
+    /// Offset of the `index`th of `count` samples to the pixel's center.
+    fn sample_offset(&self, index: u32, count: u32, random: &mut Random) -> (f64, f64) {
+        match self.settings.pixel_sampling {
+            PixelSampling::Center => (0.0, 0.0),
+            PixelSampling::Jittered => (random.next_f64() - 0.5, random.next_f64() - 0.5),
+            PixelSampling::Stratified => {
+                let n = (count as f64).sqrt() as u32;
+                if index >= n * n {
+                    return (random.next_f64() - 0.5, random.next_f64() - 0.5);
                 }
+                let (i, j) = (index % n, index / n);
+                ((i as f64 + random.next_f64()) / n as f64 - 0.5,
+                 (j as f64 + random.next_f64()) / n as f64 - 0.5)
             }
         }
+    }
+
+    /// Traces all samples of a single pixel and splats them into the film.
+    fn render_pixel(&self, x: u32, y: u32, view_matrix: &Mat4, projection_matrix: &Mat4, film: &mut Film) {
+        let mut random = Random::for_pixel(x, y);
+        let count = self.settings.samples_per_pixel.max(1);
 
-        intersections
+        for index in 0..count {
+            let (dx, dy) = self.sample_offset(index, count, &mut random);
+            let (sample_x, sample_y) = (x as f64 + dx, y as f64 + dy);
+            // Samples spread across the pixel have to cover its area, which
+            // starts at its integer coordinates. Centered samples keep going
+            // through that corner, so that the reference renders stay the same.
+            let shift = match self.settings.pixel_sampling {
+                PixelSampling::Center => 0.0,
+                PixelSampling::Jittered | PixelSampling::Stratified => 0.5
+            };
+            let radiance = match self.generate_primary_ray(sample_x + shift, sample_y + shift, view_matrix, projection_matrix) {
+                Some(ray) => {
+                    let ray = self.apply_depth_of_field(ray, &mut random);
+                    self.radiance(&ray, &mut random)
+                }
+                None => Rgb::black()
+            };
+            self.splat(film, sample_x, sample_y, radiance);
+        }
     }
 
-    fn calculate_model_mesh_intersection(&self, model: &Model, ray: &Ray) -> Option<RayTriangleIntersection> {
-        let mm = self.calc_model_matrix(&model);
+    /// Adds a sample at the image position (x, y) to all pixels within the filter's radius.
+    fn splat(&self, film: &mut Film, x: f64, y: f64, radiance: Rgb) {
+        let filter = &self.settings.filter;
+        let radius = filter.radius();
+
+        let min_x = (x - radius).floor().max(0.0) as u32;
+        let min_y = (y - radius).floor().max(0.0) as u32;
+        let max_x = ((x + radius).ceil().max(0.0) as u32).min(self.pixmap.width.saturating_sub(1));
+        let max_y = ((y + radius).ceil().max(0.0) as u32).min(self.pixmap.height.saturating_sub(1));
 
-        // TODO: read about Box
-        // let closest_intersection;
+        for pixel_y in min_y..=max_y {
+            for pixel_x in min_x..=max_x {
+                let weight = filter.evaluate(x - pixel_x as f64, y - pixel_y as f64);
+                if weight != 0.0 {
+                    film.add_sample(pixel_x, pixel_y, radiance, weight);
+                }
+            }
+        }
+    }
 
-        for ref face in &model.mesh.faces {
-            let v0 = mm.clone() * Vec4::new(model.mesh.vertices[face.a].x, model.mesh.vertices[face.a].y, model.mesh.vertices[face.a].z, model.mesh.vertices[face.a].w);
-            let v1 = mm.clone() * Vec4::new(model.mesh.vertices[face.b].x, model.mesh.vertices[face.b].y, model.mesh.vertices[face.b].z, model.mesh.vertices[face.b].w);
-            let v2 = mm.clone() * Vec4::new(model.mesh.vertices[face.c].x, model.mesh.vertices[face.c].y, model.mesh.vertices[face.c].z, model.mesh.vertices[face.c].w);
+    /// Light arriving along a primary ray, using the configured render mode.
+    fn radiance(&self, ray: &Ray, random: &mut Random) -> Rgb {
+        match self.settings.mode {
+            RenderMode::Flat => match self.calculate_closest_intersection(ray) {
+                Some(intersection) => self.material(&intersection).base_color,
+                None => Rgb::black()
+            },
+            RenderMode::Shaded => self.trace(ray, 0, random),
+            RenderMode::PathTraced => self.path_trace(ray, random)
+        }
+    }
+
+    /// Color seen along a ray, black if it does not hit anything.
+    fn trace(&self, ray: &Ray, depth: u32, random: &mut Random) -> Rgb {
+        match self.calculate_closest_intersection(ray) {
+            Some(intersection) => self.shade(&intersection, depth, random),
+            None => Rgb::black()
+        }
+    }
+
+    /// Material at the intersection with its textures applied at the
+    /// interpolated texture coordinates.
+    fn material(&self, intersection: &RayIntersection) -> Cow<'_, Material> {
+        let model = &self.scene.models[intersection.model_index];
+        let material = model.material(intersection.hit.part);
+        if !material.has_textures() {
+            return Cow::Borrowed(material);
+        }
+
+        match model.shape.texture_coordinate(&intersection.hit) {
+            Some((s, t)) => Cow::Owned(material.at(s, t)),
+            None => Cow::Borrowed(material)
+        }
+    }
+
+    /// Normal at the intersection that points towards the ray's origin, as
+    /// surfaces are shaded from both sides, and whether the ray enters the
+    /// object, which depends on the surface's orientation. For meshes the
+    /// normal is interpolated between the vertex normals if there are any.
+    fn facing_normal(&self, intersection: &RayIntersection) -> (Vec4, bool) {
+        let shape = &self.scene.models[intersection.model_index].shape;
+
+        // Models are only translated, so normals in object space are also
+        // valid in world space.
+        let face_normal = shape.normal(&intersection.hit);
+        let mut normal = shape.shading_normal(&intersection.hit);
+        // Vertex normals may have been specified for the opposite winding.
+        if Vec4::dot(&normal, &face_normal) < 0.0 {
+            normal = -1.0 * normal;
+        }
+
+        let entering = Vec4::dot(&face_normal, &intersection.ray.direction) < 0.0;
+        if entering {
+            (normal, true)
+        }
+        else {
+            (-1.0 * normal, false)
+        }
+    }
+
+    /// Light leaving the surface towards the viewer. The opaque part of the
+    /// surface emits light and reflects the lights as well as the scene, the
+    /// transparent part reflects and refracts according to Fresnel's law.
+    /// `depth` is the number of reflections and refractions along the way
+    /// from the camera to this surface.
+    fn shade(&self, intersection: &RayIntersection, depth: u32, random: &mut Random) -> Rgb {
+        let material = self.material(intersection);
+        let position = &intersection.hit_position;
+        let direction = &intersection.ray.direction;
+        let (normal, entering) = self.facing_normal(intersection);
+        let to_viewer = -1.0 * direction.clone();
+        let cos_theta = Vec4::dot(&normal, &to_viewer);
+
+        let can_recurse = depth < self.settings.max_depth;
+        let reflected_ray = Ray::new(position.clone() + SURFACE_BIAS * normal.clone(),
+                                     Vec4::reflect(direction, &normal));
+
+        let mut color = Rgb::black();
+
+        if material.opacity > 0.0 {
+            let mut surface = material.emission + self.direct_lighting(position, &normal, &to_viewer, &material, random);
+
+            // Only smooth surfaces mirror their surroundings.
+            let smoothness = (1.0 - material.roughness).max(0.0).powi(2);
+            if can_recurse && smoothness > 0.0 {
+                let reflected = self.trace(&reflected_ray, depth + 1, random);
+                surface += smoothness * (material.reflectance(cos_theta) * reflected);
+            }
+
+            color += material.opacity * surface;
+        }
+
+        if material.opacity < 1.0 && can_recurse {
+            let (eta_i, eta_t) = if entering {
+                (1.0, material.index_of_refraction)
+            }
+            else {
+                (material.index_of_refraction, 1.0)
+            };
+
+            let fresnel = fresnel_dielectric(cos_theta, eta_i, eta_t);
+            let mut transmitted = fresnel * self.trace(&reflected_ray, depth + 1, random);
+
+            if let Some(refracted_direction) = Vec4::refract(direction, &normal, eta_i / eta_t) {
+                let refracted_ray = Ray::new(position.clone() + -SURFACE_BIAS * normal.clone(), refracted_direction);
+                let refracted = self.trace(&refracted_ray, depth + 1, random);
+                transmitted += (1.0 - fresnel) * (material.base_color * refracted);
+            }
+
+            color += (1.0 - material.opacity) * transmitted;
+        }
+
+        color
+    }
+
+    /// Estimates the light arriving along a ray by following a random path
+    /// through the scene. At every surface the lights are sampled directly
+    /// (next event estimation) and the path continues into a direction drawn
+    /// from the surface's BSDF, so emissive surfaces are only found by chance.
+    fn path_trace(&self, ray: &Ray, random: &mut Random) -> Rgb {
+        let mut radiance = Rgb::black();
+        let mut throughput = Rgb::white();
+        let mut ray = ray.clone();
+
+        for depth in 0..self.settings.max_depth + 1 {
+            let intersection = match self.calculate_closest_intersection(&ray) {
+                Some(intersection) => intersection,
+                None => break
+            };
+
+            let material = self.material(&intersection);
+            let position = &intersection.hit_position;
+            let (normal, entering) = self.facing_normal(&intersection);
+            let to_viewer = -1.0 * ray.direction.clone();
+            let is_last_bounce = depth == self.settings.max_depth;
+
+            // Choose between the opaque and transparent part of the surface
+            // with their respective probabilities, so their weights cancel out.
+            if random.next_f64() < material.opacity {
+                let direct = self.direct_lighting(position, &normal, &to_viewer, &material, random);
+                radiance += throughput * (material.emission + direct);
+
+                if is_last_bounce {
+                    break;
+                }
+
+                let diffuse_color = material.diffuse_color();
+                let specular_color = material.specular_color();
+                let total = diffuse_color.luminance() + specular_color.luminance();
+                if total <= 0.0 {
+                    break;
+                }
 
-            let ray_direction = (ray.end.clone() - ray.start.clone()).normalize();
+                // Pick one of the lobes proportional to its reflectance.
+                let specular_probability = specular_color.luminance() / total;
+                let direction = if random.next_f64() < specular_probability {
+                    let shininess = material.shininess();
+                    let mirrored = Vec4::reflect(&ray.direction, &normal);
+                    let direction = phong_lobe(&mirrored, shininess, random);
+                    let cos_theta = Vec4::dot(&normal, &direction);
+                    if cos_theta <= 0.0 {
+                        break;
+                    }
 
-            if let Some(t) = triangle_intersection(v0, v1, v2, ray.start.clone(), ray_direction.clone()) {
-                let intersection = RayTriangleIntersection {
-                    ray: ray.clone(),
-                    hit_position: ray.start.clone() + t * ray_direction.clone()
+                    // BRDF * cos(theta) / pdf of the normalized Phong lobe.
+                    let weight = (shininess + 2.0) / (shininess + 1.0) * cos_theta / specular_probability;
+                    throughput = throughput * (weight * specular_color);
+                    direction
+                }
+                else {
+                    throughput = throughput * ((1.0 / (1.0 - specular_probability)) * diffuse_color);
+                    cosine_hemisphere(&normal, random)
                 };
-                return Some(intersection);
+
+                ray = Ray::new(position.clone() + SURFACE_BIAS * normal.clone(), direction);
+            }
+            else {
+                if is_last_bounce {
+                    break;
+                }
+
+                let (eta_i, eta_t) = if entering {
+                    (1.0, material.index_of_refraction)
+                }
+                else {
+                    (material.index_of_refraction, 1.0)
+                };
+
+                let fresnel = fresnel_dielectric(Vec4::dot(&normal, &to_viewer), eta_i, eta_t);
+                let refracted = Vec4::refract(&ray.direction, &normal, eta_i / eta_t);
+
+                ray = match refracted {
+                    Some(direction) if random.next_f64() >= fresnel => {
+                        throughput = throughput * material.base_color;
+                        Ray::new(position.clone() + -SURFACE_BIAS * normal.clone(), direction)
+                    }
+                    _ => Ray::new(position.clone() + SURFACE_BIAS * normal.clone(),
+                                  Vec4::reflect(&ray.direction, &normal))
+                };
+            }
+
+            // Randomly terminate paths that do not carry much light anymore
+            // and boost the survivors to compensate for it.
+            if depth + 1 >= RUSSIAN_ROULETTE_DEPTH {
+                let survival_probability = throughput.max_component().clamp(0.05, 1.0);
+                if random.next_f64() >= survival_probability {
+                    break;
+                }
+                throughput = (1.0 / survival_probability) * throughput;
             }
         }
 
-        None
+        radiance
     }
 
-    pub fn run(&mut self) {
-        println!("Calculate matrices");
-        let projection_matrix = self.calc_projection_matrix();
-        let view_matrix = self.calc_view_matrix();
+    /// Light reflected towards the viewer at a surface point, taking into
+    /// account whether the lights are visible from that point. Applies the
+    /// Phong reflection model without an ambient term, summing up Lambertian
+    /// diffuse and energy-normalized specular reflection of each light.
+    fn direct_lighting(&self, position: &Vec4, normal: &Vec4, to_viewer: &Vec4, material: &Material, random: &mut Random) -> Rgb {
+        let diffuse_color = material.diffuse_color();
+        let specular_color = material.specular_color();
+        let shininess = material.shininess();
+        let mut color = Rgb::black();
+
+        // Only lights in front of the surface contribute, so moving the shadow
+        // rays' origin along the normal moves it towards the lights.
+        let shadow_origin = position.clone() + SURFACE_BIAS * normal.clone();
 
-        println!("Generate primary rays");
-        let rays = self.generate_primary_rays(view_matrix, projection_matrix);
+        for light in &self.scene.lights {
+            for sample in light.illuminate(position, random) {
+                let cos_theta = Vec4::dot(normal, &sample.direction);
+                if cos_theta <= 0.0 {
+                    continue;
+                }
+
+                if self.is_occluded(&shadow_origin, &sample.direction, sample.distance - SURFACE_BIAS) {
+                    continue;
+                }
 
-        println!("Calculate triangle intersections");
-        let intersections = self.calculate_triangle_intersections(rays);
+                let reflected = Vec4::reflect(&(-1.0 * sample.direction.clone()), normal);
+                let specular = (shininess + 2.0) / 2.0 * Vec4::dot(&reflected, to_viewer).max(0.0).powf(shininess);
 
-        println!("Render intersections");
-        for intersection in intersections {
-            let (x, y) = intersection.ray.pixmap_coords;
-            self.pixmap.draw(x, y, Color { r: 255, g: 0, b: 0 });
+                color += cos_theta * ((diffuse_color + specular * specular_color) * sample.radiance);
+            }
+        }
+
+        color
+    }
+
+    fn render_tile(&self, index: usize, tile: &Tile, view_matrix: &Mat4, projection_matrix: &Mat4) -> RenderedTile {
+        // Samples near the tile's border also contribute to pixels of neighbouring tiles.
+        let margin = self.filter_margin();
+        let min_x = tile.x.saturating_sub(margin);
+        let min_y = tile.y.saturating_sub(margin);
+        let max_x = (tile.x + tile.width + margin).min(self.pixmap.width);
+        let max_y = (tile.y + tile.height + margin).min(self.pixmap.height);
+        let mut film = Film::new(min_x, min_y, max_x - min_x, max_y - min_y);
+
+        for y in tile.y..tile.y + tile.height {
+            for x in tile.x..tile.x + tile.width {
+                self.render_pixel(x, y, view_matrix, projection_matrix, &mut film);
+            }
+        }
+
+        RenderedTile {
+            index,
+            film
         }
     }
-}
 
-/// Implementation of the Möller-Trumbore intersection algorithm
-/// Pseude code has been taken from Wikipedia and translated into Rust:
-/// https://en.wikipedia.org/wiki/M%C3%B6ller%E2%80%93Trumbore_intersection_algorithm
-fn triangle_intersection(v1: Vec4, v2: Vec4, v3: Vec4, o: Vec4, d: Vec4) -> Option<f64> {
-    // TODO: Use global epsilon?
-    let epsilon: f64 = 0.000001;
+    /// Renders all tiles using a pool of worker threads that pick the next
+    /// unrendered tile until none are left. As every pixel is calculated
+    /// independently, the result is the same for any number of threads.
+    /// The rendered tiles are returned in their original order.
+    fn render_tiles(&self, view_matrix: &Mat4, projection_matrix: &Mat4) -> Vec<RenderedTile> {
+        let tiles = self.generate_tiles();
+        let threads = self.thread_count().min(tiles.len()).max(1);
+        let next_tile = AtomicUsize::new(0);
 
-    // find vectors for two edges sharing v1
-    let e1 = v2.clone() - v1.clone();
-    let e2 = v3.clone() - v1.clone();
+        println!("Render {} tiles using {} threads", tiles.len(), threads);
 
-    // begin calculating determinant - also used to calculate u parameter
-    let p = Vec4::cross(&d, &e2);
+        thread::scope(|scope| {
+            let workers: Vec<_> = (0..threads).map(|_| scope.spawn(|| {
+                let mut rendered: Vec<RenderedTile> = Vec::new();
+                loop {
+                    let index = next_tile.fetch_add(1, Ordering::Relaxed);
+                    if index >= tiles.len() {
+                        break;
+                    }
+                    rendered.push(self.render_tile(index, &tiles[index], view_matrix, projection_matrix));
+                }
+                rendered
+            })).collect();
 
-    // if determinant is near zero, ray lies in plane of triangle or ray is parallel to plane of triangle
-    let det = Vec4::dot(&e1, &p);
-    if det > -epsilon && det < epsilon {
-        return None;
+            let mut rendered: Vec<RenderedTile> = workers.into_iter()
+                                                         .flat_map(|worker| worker.join().expect("Render thread panicked"))
+                                                         .collect();
+            rendered.sort_by_key(|rendered_tile| rendered_tile.index);
+            rendered
+        })
     }
 
-    // calculate invert determinant
-    let inv_det = 1.0 / det;
+    /// Finds the intersection closest to the ray's origin across all models
+    /// of the scene.
+    fn calculate_closest_intersection(&self, ray: &Ray) -> Option<RayIntersection> {
+        let mut closest: Option<RayIntersection> = None;
 
-    // calculate distance from v1 to ray origin
-    let t = o.clone() - v1.clone();
+        for (model_index, model) in self.scene.models.iter().enumerate() {
+            if let Some(intersection) = self.calculate_model_intersection(model_index, model, ray) {
+                let is_closer = match closest {
+                    Some(ref c) => intersection.distance < c.distance,
+                    None => true
+                };
+                if is_closer {
+                    closest = Some(intersection);
+                }
+            }
+        }
 
-    // calculate u parameter and test bound
-    // and abort if the intersection lies outside of the triangle
-    let u = Vec4::dot(&t, &p) * inv_det;
-    if u < 0.0 || u > 1.0 {
-        return None;
+        closest
     }
 
-    // prepare to test v parameter
-    let q = Vec4::cross(&t, &e1);
+    /// Finds the intersection of the ray with a single model closest to the ray's origin.
+    fn calculate_model_intersection(&self, model_index: usize, model: &Model, ray: &Ray) -> Option<RayIntersection> {
+        // Shapes live in object space. Models are only translated, so the
+        // direction and thus all distances are the same in both spaces.
+        let origin = model.to_object_space(&ray.origin);
 
-    // calculate V parameter and test bound
-    let v = Vec4::dot(&d, &q) * inv_det;
+        model.shape.intersect(&origin, &ray.direction, f64::INFINITY).map(|hit| RayIntersection {
+            ray: ray.clone(),
+            hit_position: ray.origin.clone() + hit.t * ray.direction.clone(),
+            distance: hit.t,
+            model_index,
+            hit
+        })
+    }
 
-    // the intersection lies outside of the triangle
-    if v < 0.0 || u + v > 1.0 {
-        return None;
+    /// Checks whether anything in the scene blocks the way from `start` along
+    /// `direction` within `max_distance`.
+    fn is_occluded(&self, start: &Vec4, direction: &Vec4, max_distance: f64) -> bool {
+        self.scene.models.iter().any(|model| {
+            model.shape.is_hit(&model.to_object_space(start), direction, max_distance)
+        })
     }
 
-    // now check again if we've found an intersection and calculate the result
-    let t = Vec4::dot(&e2, &q) * inv_det;
-    if t > epsilon {
-        // TODO: assign out
-        return Some(t)
+    pub fn run(&mut self) {
+        println!("Calculate matrices");
+        let projection_matrix = self.calc_projection_matrix();
+        let view_matrix = self.calc_view_matrix();
+
+        let rendered_tiles = self.render_tiles(&view_matrix, &projection_matrix);
+
+        println!("Develop film");
+        let mut film = Film::new(0, 0, self.pixmap.width, self.pixmap.height);
+        for rendered_tile in &rendered_tiles {
+            film.merge(&rendered_tile.film);
+        }
+        film.develop(&mut self.pixmap);
     }
 
-    // no hit, no win
-    None
+    /// Renders the left and right eye of the stereo rig, both using half of
+    /// the pixmap as arranged by the rig's layout.
+    #[allow(dead_code)]
+    pub fn run_stereo(&mut self, rig: &StereoRig) {
+        let (width, height) = (self.pixmap.width, self.pixmap.height);
+        let camera = self.scene.camera.clone();
+
+        for &eye in &[Eye::Left, Eye::Right] {
+            // Pixmap rows are stored bottom to top. For odd sizes the eye in
+            // the second half gets the extra column or row.
+            let (x, y, eye_width, eye_height) = match (rig.layout, eye) {
+                (StereoLayout::SideBySide, Eye::Left) => (0, 0, width / 2, height),
+                (StereoLayout::SideBySide, Eye::Right) => (width / 2, 0, width - width / 2, height),
+                (StereoLayout::TopBottom, Eye::Left) => (0, height / 2, width, height - height / 2),
+                (StereoLayout::TopBottom, Eye::Right) => (0, 0, width, height / 2)
+            };
+
+            println!("Render {:?} eye", eye);
+            self.scene.camera = rig.eye_camera(&camera, eye);
+            let pixmap = mem::replace(&mut self.pixmap, Pixmap::new(eye_width, eye_height));
+            self.run();
+            let eye_pixmap = mem::replace(&mut self.pixmap, pixmap);
+
+            for eye_y in 0..eye_height {
+                for eye_x in 0..eye_width {
+                    self.pixmap.draw(x + eye_x, y + eye_y, eye_pixmap.get_color(eye_x, eye_y));
+                }
+            }
+        }
+
+        self.scene.camera = camera;
+    }
 }
 
 #[test]
@@ -190,32 +704,687 @@ fn test_raytrace() {
 
     let mesh = mesh::Mesh::try_load_from_off("./meshes/teapot.off", mesh::PolygonWinding::Clockwise).unwrap();
 
-    let model = Model {
-        mesh: mesh,
-        position: Vec4 {
-            x: 0.0,
-            y: -1.0,
-            z: 0.0,
-            w: 1.0
-        }
-    };
+    let mut model = Model::new(mesh, Vec4 {
+        x: 0.0,
+        y: -1.0,
+        z: 0.0,
+        w: 1.0
+    });
+    model.material.base_color = Rgb::new(1.0, 0.0, 0.0);
 
-    let camera = Camera {
-        position: Vec4 { x: 0.0, y: 0.0, z: 10.0, w: 1.0 },
-        look_at: Vec4 { x: 0.0, y: 0.0, z: 0.0, w: 1.0 },
-        up: Vec4 { x: 0.0, y: 1.0, z: 0.0, w: 0.0 },
-    };
+    let camera = Camera::new(Vec4 { x: 0.0, y: 0.0, z: 10.0, w: 1.0 },
+                             Vec4 { x: 0.0, y: 0.0, z: 0.0, w: 1.0 },
+                             Vec4 { x: 0.0, y: 1.0, z: 0.0, w: 0.0 });
 
     let scene = Scene {
         models: vec![model],
+        lights: Vec::new(),
         camera: camera
     };
 
     let pixmap = Pixmap::new(32, 32);
 
     let mut raytrace = Raytrace::new(scene, pixmap);
+    raytrace.settings.mode = RenderMode::Flat;
     raytrace.run();
 
     let reference_pixmap = Pixmap::try_load_from_ppm("./testdata/raytrace.ppm".to_string()).unwrap();
     assert_eq!(raytrace.pixmap, reference_pixmap);
 }
+
+#[test]
+fn test_projection_aspect_ratio() {
+    use algebra::Angle;
+    use camera::{ Camera, FieldOfView };
+
+    let angle_to_forward = |ray: &Ray| Vec4::dot(&ray.direction, &Vec4::new(0.0, 0.0, -1.0, 0.0)).acos();
+
+    for &(width, height) in &[(640, 480), (1920, 1080)] {
+        let aspect_ratio = width as f64 / height as f64;
+        let mut camera = Camera::new(Vec4::new(3.0, 2.0, 10.0, 1.0),
+                                     Vec4::new(3.0, 2.0, 0.0, 1.0),
+                                     Vec4::new(0.0, 1.0, 0.0, 0.0));
+        camera.field_of_view = FieldOfView::Horizontal(Angle::Degrees(90.0));
+        let scene = Scene {
+            models: Vec::new(),
+            lights: Vec::new(),
+            camera
+        };
+        let raytrace = Raytrace::new(scene, Pixmap::new(width, height));
+        let view_matrix = raytrace.calc_view_matrix();
+        let projection_matrix = raytrace.calc_projection_matrix();
+        let (w, h) = (width as f64, height as f64);
+
+        // Rays through the centers of the image's edges span the field of view.
+        let right = raytrace.generate_primary_ray(w, h / 2.0, &view_matrix, &projection_matrix).unwrap();
+        let top = raytrace.generate_primary_ray(w / 2.0, h, &view_matrix, &projection_matrix).unwrap();
+        assert!((angle_to_forward(&right) - (45.0f64).to_radians()).abs() < 1e-9);
+        assert!((angle_to_forward(&top) - (1.0 / aspect_ratio).atan()).abs() < 1e-9);
+
+        // Pixels are square, so equal distances on the image correspond to equal angles at its center.
+        let dx = raytrace.generate_primary_ray(w / 2.0 + 1.0, h / 2.0, &view_matrix, &projection_matrix).unwrap();
+        let dy = raytrace.generate_primary_ray(w / 2.0, h / 2.0 + 1.0, &view_matrix, &projection_matrix).unwrap();
+        assert!((angle_to_forward(&dx) - angle_to_forward(&dy)).abs() < 1e-12);
+    }
+}
+
+#[test]
+fn test_orthographic_projection() {
+    use camera::{ Camera, Projection };
+
+    let mut camera = Camera::new(Vec4::new(1.0, 0.0, 10.0, 1.0),
+                                 Vec4::new(1.0, 0.0, 0.0, 1.0),
+                                 Vec4::new(0.0, 1.0, 0.0, 0.0));
+    camera.projection = Projection::Orthographic { height: 6.0 };
+    let scene = Scene {
+        models: Vec::new(),
+        lights: Vec::new(),
+        camera
+    };
+    let raytrace = Raytrace::new(scene, Pixmap::new(640, 480));
+    let view_matrix = raytrace.calc_view_matrix();
+    let projection_matrix = raytrace.calc_projection_matrix();
+
+    // All rays point along the viewing direction and start on the near plane,
+    // spread across the 8 x 6 view volume.
+    let center = raytrace.generate_primary_ray(320.0, 240.0, &view_matrix, &projection_matrix).unwrap();
+    let corner = raytrace.generate_primary_ray(640.0, 480.0, &view_matrix, &projection_matrix).unwrap();
+    for ray in &[&center, &corner] {
+        assert!(Vec4::epsilon_compare(ray.direction.clone(), Vec4::new(0.0, 0.0, -1.0, 0.0), 1e-9));
+        assert!((ray.origin.z - 9.9).abs() < 1e-9);
+    }
+    assert!((center.origin.x - 1.0).abs() < 1e-9 && center.origin.y.abs() < 1e-9);
+    assert!(((corner.origin.x - center.origin.x).abs() - 4.0).abs() < 1e-9);
+    assert!(((corner.origin.y - center.origin.y).abs() - 3.0).abs() < 1e-9);
+}
+
+#[test]
+fn test_supersampling_covers_pixels() {
+    use camera::{ Camera, Projection };
+    use color::Color;
+    use shape::Cuboid;
+
+    // A bar exactly as wide as the fifth pixel column of an orthographic
+    // view that maps one unit to one pixel.
+    let mut bar = Model::with_shape(Cuboid { size: Vec4::new(1.0, 20.0, 1.0, 0.0) }, Vec4::new(0.5, 0.0, 0.0, 1.0));
+    bar.material.base_color = Rgb::new(1.0, 0.0, 0.0);
+
+    let mut camera = Camera::new(Vec4::new(0.0, 0.0, 10.0, 1.0),
+                                 Vec4::new(0.0, 0.0, 0.0, 1.0),
+                                 Vec4::new(0.0, 1.0, 0.0, 0.0));
+    camera.projection = Projection::Orthographic { height: 8.0 };
+    let scene = Scene {
+        models: vec![bar],
+        lights: Vec::new(),
+        camera
+    };
+
+    let mut raytrace = Raytrace::new(scene, Pixmap::new(8, 8));
+    raytrace.settings.mode = RenderMode::Flat;
+    raytrace.settings.samples_per_pixel = 16;
+    raytrace.settings.pixel_sampling = PixelSampling::Stratified;
+    raytrace.run();
+
+    // All samples of the column hit the bar and none of its neighbors do.
+    for y in 0..8 {
+        for x in 0..8 {
+            let expected = if x == 4 { Color { r: 255, g: 0, b: 0 } } else { Color { r: 0, g: 0, b: 0 } };
+            assert_eq!(raytrace.pixmap.get_color(x, y), expected, "pixel ({}, {})", x, y);
+        }
+    }
+}
+
+#[test]
+fn test_panoramic_projection() {
+    use algebra::Angle;
+    use camera::{ Camera, Projection };
+
+    let mut camera = Camera::new(Vec4::new(1.0, 2.0, 3.0, 1.0),
+                                 Vec4::new(1.0, 2.0, 0.0, 1.0),
+                                 Vec4::new(0.0, 1.0, 0.0, 0.0));
+    camera.projection = Projection::Fisheye { field_of_view: Angle::Degrees(180.0) };
+    let scene = Scene {
+        models: Vec::new(),
+        lights: Vec::new(),
+        camera
+    };
+    let mut raytrace = Raytrace::new(scene, Pixmap::new(64, 32));
+    let view_matrix = raytrace.calc_view_matrix();
+    let projection_matrix = raytrace.calc_projection_matrix();
+
+    // Rays start at the camera and the corners lie outside of the image circle.
+    let center = raytrace.generate_primary_ray(32.0, 16.0, &view_matrix, &projection_matrix).unwrap();
+    assert_eq!(center.origin, Vec4::new(1.0, 2.0, 3.0, 1.0));
+    assert_eq!(center.direction, Vec4::new(0.0, 0.0, -1.0, 0.0));
+    assert!(raytrace.generate_primary_ray(0.0, 0.0, &view_matrix, &projection_matrix).is_none());
+
+    // The right border of an equirectangular image looks backwards.
+    raytrace.scene.camera.projection = Projection::Equirectangular;
+    let back = raytrace.generate_primary_ray(64.0, 16.0, &view_matrix, &projection_matrix).unwrap();
+    assert!(Vec4::epsilon_compare(back.direction, Vec4::new(0.0, 0.0, 1.0, 0.0), 1e-12));
+}
+
+#[test]
+fn test_stereo_rendering() {
+    use camera::Camera;
+    use mesh;
+
+    let render = |camera: &Camera, pixmap: Pixmap, rig: Option<&StereoRig>| {
+        let mesh = mesh::Mesh::try_load_from_off("./meshes/teapot.off", mesh::PolygonWinding::Clockwise).unwrap();
+        let scene = Scene {
+            models: vec![Model::new(mesh, Vec4::new(0.0, -1.0, 0.0, 1.0))],
+            lights: Vec::new(),
+            camera: camera.clone()
+        };
+        let mut raytrace = Raytrace::new(scene, pixmap);
+        raytrace.settings.mode = RenderMode::Flat;
+        match rig {
+            Some(rig) => raytrace.run_stereo(rig),
+            None => raytrace.run()
+        }
+        raytrace.pixmap
+    };
+
+    let camera = Camera::new(Vec4::new(0.0, 0.0, 10.0, 1.0),
+                             Vec4::new(0.0, 0.0, 0.0, 1.0),
+                             Vec4::new(0.0, 1.0, 0.0, 0.0));
+    let mut rig = StereoRig::new(StereoLayout::SideBySide);
+    rig.interocular_distance = 1.0;
+    rig.convergence_distance = 20.0;
+
+    let left = render(&rig.eye_camera(&camera, Eye::Left), Pixmap::new(16, 24), None);
+    let right = render(&rig.eye_camera(&camera, Eye::Right), Pixmap::new(16, 24), None);
+    assert!(left != right);
+
+    let stereo = render(&camera, Pixmap::new(32, 24), Some(&rig));
+    for y in 0..24 {
+        for x in 0..16 {
+            assert_eq!(stereo.get_color(x, y), left.get_color(x, y));
+            assert_eq!(stereo.get_color(x + 16, y), right.get_color(x, y));
+        }
+    }
+
+    rig.layout = StereoLayout::TopBottom;
+    let left = render(&rig.eye_camera(&camera, Eye::Left), Pixmap::new(32, 12), None);
+    let stereo = render(&camera, Pixmap::new(32, 24), Some(&rig));
+    for y in 0..12 {
+        for x in 0..32 {
+            assert_eq!(stereo.get_color(x, y + 12), left.get_color(x, y));
+        }
+    }
+
+    // With odd sizes the eye in the second half is one column or row larger,
+    // so nothing is left unrendered.
+    for &(layout, eye, (x0, y0), (eye_width, eye_height)) in &[(StereoLayout::SideBySide, Eye::Right, (16, 0), (17, 24)),
+                                                               (StereoLayout::TopBottom, Eye::Left, (0, 12), (32, 13))] {
+        rig.layout = layout;
+        let single = render(&rig.eye_camera(&camera, eye), Pixmap::new(eye_width, eye_height), None);
+        let stereo = render(&camera, Pixmap::new(x0 + eye_width, y0 + eye_height), Some(&rig));
+        for y in 0..eye_height {
+            for x in 0..eye_width {
+                assert_eq!(stereo.get_color(x0 + x, y0 + y), single.get_color(x, y));
+            }
+        }
+    }
+}
+
+#[test]
+fn test_depth_of_field() {
+    use camera::Camera;
+
+    let mut camera = Camera::new(Vec4::new(0.0, 1.0, 10.0, 1.0),
+                                 Vec4::new(0.0, 1.0, 0.0, 1.0),
+                                 Vec4::new(0.0, 1.0, 0.0, 0.0));
+    camera.aperture_radius = 0.5;
+    camera.focus_distance = 8.0;
+    let scene = Scene {
+        models: Vec::new(),
+        lights: Vec::new(),
+        camera
+    };
+    let raytrace = Raytrace::new(scene, Pixmap::new(64, 48));
+    let view_matrix = raytrace.calc_view_matrix();
+    let projection_matrix = raytrace.calc_projection_matrix();
+
+    // Rays through the same pixel start at different points on the lens
+    // but meet where the pinhole ray crosses the focus plane at z = 2.
+    let pinhole = raytrace.generate_primary_ray(10.0, 40.0, &view_matrix, &projection_matrix).unwrap();
+    let t = (2.0 - pinhole.origin.z) / pinhole.direction.z;
+    let focus_point = pinhole.origin.clone() + t * pinhole.direction.clone();
+
+    let mut random = Random::new(7);
+    let mut origins: Vec<Vec4> = Vec::new();
+    for _ in 0..16 {
+        let ray = raytrace.apply_depth_of_field(pinhole.clone(), &mut random);
+        let t = (2.0 - ray.origin.z) / ray.direction.z;
+        let point = ray.origin.clone() + t * ray.direction.clone();
+        assert!(Vec4::epsilon_compare(point, focus_point.clone(), 1e-9));
+
+        // The lens is centered at the camera, perpendicular to the viewing
+        // direction and within the aperture.
+        let offset = ray.origin.clone() - raytrace.scene.camera.position.clone();
+        assert!(offset.z.abs() < 1e-12);
+        assert!(Vec4::dot(&offset, &offset).sqrt() <= 0.5);
+        origins.push(ray.origin);
+    }
+    assert!(origins.iter().any(|origin| *origin != origins[0]));
+
+    // Orthographic rays are parallel, so their lens is centered at their own origin.
+    let mut raytrace = raytrace;
+    raytrace.scene.camera.projection = Projection::Orthographic { height: 6.0 };
+    let projection_matrix = raytrace.calc_projection_matrix();
+    let parallel = raytrace.generate_primary_ray(10.0, 40.0, &view_matrix, &projection_matrix).unwrap();
+    for _ in 0..16 {
+        let ray = raytrace.apply_depth_of_field(parallel.clone(), &mut random);
+        let offset = ray.origin.clone() - parallel.origin.clone();
+        assert!(offset.z.abs() < 1e-12);
+        assert!(Vec4::dot(&offset, &offset).sqrt() <= 0.5);
+    }
+}
+
+#[test]
+fn test_closest_intersection() {
+    use camera::Camera;
+    use mesh;
+
+    // Two parallel planes, the one further away from the camera comes first
+    // in the scene so that a first-found strategy would pick the wrong one.
+    let mesh = mesh::Mesh::try_load_from_off("./meshes/plane4x4.off", mesh::PolygonWinding::Clockwise).unwrap();
+    let far_plane = Model::new(mesh.clone(), Vec4::new(0.0, 0.0, 0.0, 1.0));
+    let near_plane = Model::new(mesh, Vec4::new(0.0, 0.0, 2.0, 1.0));
+
+    let scene = Scene {
+        models: vec![far_plane, near_plane],
+        lights: Vec::new(),
+        camera: Camera::new(Vec4::new(0.0, 0.0, 10.0, 1.0),
+                            Vec4::new(0.0, 0.0, 0.0, 1.0),
+                            Vec4::new(0.0, 1.0, 0.0, 0.0))
+    };
+
+    let raytrace = Raytrace::new(scene, Pixmap::new(8, 8));
+    let ray = Ray::new(Vec4::new(0.1, 0.1, 10.0, 1.0), Vec4::new(0.0, 0.0, -1.0, 0.0));
+
+    let intersection = raytrace.calculate_closest_intersection(&ray).unwrap();
+    assert_eq!(intersection.model_index, 1);
+    assert!((intersection.distance - 8.0).abs() < 1e-9);
+    assert!(Vec4::epsilon_compare(intersection.hit_position, Vec4::new(0.1, 0.1, 2.0, 1.0), 1e-9));
+
+    let (u, v) = intersection.hit.uv;
+    assert!(u >= 0.0 && v >= 0.0 && u + v <= 1.0);
+}
+
+#[test]
+fn test_analytic_shapes() {
+    use camera::Camera;
+    use shape::{ Plane, Sphere };
+
+    let mut sphere = Model::with_shape(Sphere { radius: 1.0 }, Vec4::new(0.0, 1.0, 0.0, 1.0));
+    sphere.material.base_color = Rgb::new(1.0, 0.0, 0.0);
+    let ground = Model::with_shape(Plane { normal: Vec4::new(0.0, 1.0, 0.0, 0.0) }, Vec4::new(0.0, 0.0, 0.0, 1.0));
+
+    let scene = Scene {
+        models: vec![ground, sphere],
+        lights: Vec::new(),
+        camera: Camera::new(Vec4::new(0.0, 1.0, 10.0, 1.0),
+                            Vec4::new(0.0, 1.0, 0.0, 1.0),
+                            Vec4::new(0.0, 1.0, 0.0, 0.0))
+    };
+    let raytrace = Raytrace::new(scene, Pixmap::new(8, 8));
+
+    let ray = Ray::new(Vec4::new(0.0, 1.0, 10.0, 1.0), Vec4::new(0.0, 0.0, -1.0, 0.0));
+    let intersection = raytrace.calculate_closest_intersection(&ray).unwrap();
+    assert_eq!(intersection.model_index, 1);
+    assert!((intersection.distance - 9.0).abs() < 1e-9);
+    assert_eq!(raytrace.facing_normal(&intersection), (Vec4::new(0.0, 0.0, 1.0, 0.0), true));
+    assert_eq!(raytrace.material(&intersection).base_color, Rgb::new(1.0, 0.0, 0.0));
+
+    let ray = Ray::new(Vec4::new(3.0, 1.0, 0.0, 1.0), Vec4::new(0.0, -1.0, 0.0, 0.0));
+    let intersection = raytrace.calculate_closest_intersection(&ray).unwrap();
+    assert_eq!(intersection.model_index, 0);
+    assert!((intersection.distance - 1.0).abs() < 1e-9);
+
+    // The sphere casts a shadow onto the ground right beneath it.
+    let up = Vec4::new(0.0, 1.0, 0.0, 0.0);
+    assert!(raytrace.is_occluded(&Vec4::new(0.0, 0.0, 0.0, 1.0), &up, f64::INFINITY));
+    assert!(!raytrace.is_occluded(&Vec4::new(3.0, 0.0, 0.0, 1.0), &up, f64::INFINITY));
+}
+
+#[test]
+fn test_textured_material() {
+    use std::sync::Arc;
+    use camera::Camera;
+    use color::Color;
+    use mesh::{ Face, Mesh };
+    use texture::{ Texture, TextureFilter };
+
+    let mut mesh = Mesh::new(vec![Vec4::new(-1.0, -1.0, 0.0, 1.0), Vec4::new(1.0, -1.0, 0.0, 1.0),
+                                  Vec4::new(1.0, 1.0, 0.0, 1.0), Vec4::new(-1.0, 1.0, 0.0, 1.0)],
+                             vec![Face { a: 0, b: 1, c: 2 }, Face { a: 0, b: 2, c: 3 }]);
+    mesh.texture_coordinates = vec![(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)];
+
+    // Red on the left half, green on the right one.
+    let mut pixmap = Pixmap::new(2, 1);
+    pixmap.draw(0, 0, Color { r: 255, g: 0, b: 0 });
+    pixmap.draw(1, 0, Color { r: 0, g: 255, b: 0 });
+    let mut texture = Texture::from_pixmap(&pixmap);
+    texture.filter = TextureFilter::Nearest;
+
+    let mut model = Model::new(mesh, Vec4::new(0.0, 0.0, 0.0, 1.0));
+    model.material.base_color = Rgb::white();
+    model.material.base_color_texture = Some(Arc::new(texture));
+
+    let scene = Scene {
+        models: vec![model],
+        lights: Vec::new(),
+        camera: Camera::new(Vec4::new(0.0, 0.0, 10.0, 1.0),
+                            Vec4::new(0.0, 0.0, 0.0, 1.0),
+                            Vec4::new(0.0, 1.0, 0.0, 0.0))
+    };
+    let raytrace = Raytrace::new(scene, Pixmap::new(8, 8));
+
+    for &(x, y, expected) in &[(-0.5, -0.5, Rgb::new(1.0, 0.0, 0.0)), (-0.5, 0.9, Rgb::new(1.0, 0.0, 0.0)),
+                               (0.5, -0.9, Rgb::new(0.0, 1.0, 0.0)), (0.5, 0.5, Rgb::new(0.0, 1.0, 0.0))] {
+        let ray = Ray::new(Vec4::new(x, y, 10.0, 1.0), Vec4::new(0.0, 0.0, -1.0, 0.0));
+        let intersection = raytrace.calculate_closest_intersection(&ray).unwrap();
+        assert_eq!(raytrace.material(&intersection).base_color, expected);
+    }
+}
+
+#[test]
+fn test_is_occluded() {
+    use camera::Camera;
+    use mesh;
+
+    let mesh = mesh::Mesh::try_load_from_off("./meshes/plane4x4.off", mesh::PolygonWinding::Clockwise).unwrap();
+    let scene = Scene {
+        models: vec![Model::new(mesh, Vec4::new(0.0, 0.0, 0.0, 1.0))],
+        lights: Vec::new(),
+        camera: Camera::new(Vec4::new(0.0, 0.0, 10.0, 1.0),
+                            Vec4::new(0.0, 0.0, 0.0, 1.0),
+                            Vec4::new(0.0, 1.0, 0.0, 0.0))
+    };
+    let raytrace = Raytrace::new(scene, Pixmap::new(8, 8));
+
+    let start = Vec4::new(0.2, 0.1, 5.0, 1.0);
+    let down = Vec4::new(0.0, 0.0, -1.0, 0.0);
+    assert!(raytrace.is_occluded(&start, &down, f64::INFINITY));
+    assert!(!raytrace.is_occluded(&start, &down, 4.0));
+    assert!(!raytrace.is_occluded(&start, &Vec4::new(0.0, 0.0, 1.0, 0.0), f64::INFINITY));
+}
+
+#[test]
+fn test_raytrace_thread_count_independent() {
+    use camera::Camera;
+    use mesh;
+
+    let render = |mode: RenderMode, threads: usize, tile_size: u32| {
+        let mesh = mesh::Mesh::try_load_from_off("./meshes/teapot.off", mesh::PolygonWinding::Clockwise).unwrap();
+        let scene = Scene {
+            models: vec![Model::new(mesh, Vec4::new(0.0, -1.0, 0.0, 1.0))],
+            lights: Vec::new(),
+            camera: Camera::new(Vec4::new(0.0, 0.0, 10.0, 1.0),
+                                Vec4::new(0.0, 0.0, 0.0, 1.0),
+                                Vec4::new(0.0, 1.0, 0.0, 0.0))
+        };
+
+        let mut raytrace = Raytrace::new(scene, Pixmap::new(40, 24));
+        raytrace.settings.mode = mode;
+        raytrace.settings.samples_per_pixel = 2;
+        raytrace.settings.threads = threads;
+        raytrace.settings.tile_size = tile_size;
+        raytrace.run();
+        raytrace.pixmap
+    };
+
+    for &mode in &[RenderMode::Shaded, RenderMode::PathTraced] {
+        let reference = render(mode, 1, 64);
+        assert_eq!(render(mode, 4, 7), reference);
+        assert_eq!(render(mode, 3, 16), reference);
+    }
+}
+
+#[test]
+fn test_antialiasing() {
+    use camera::Camera;
+    use color::Color;
+    use mesh;
+
+    let render = |pixel_sampling: PixelSampling, filter: Filter, threads: usize| {
+        let mesh = mesh::Mesh::try_load_from_off("./meshes/teapot.off", mesh::PolygonWinding::Clockwise).unwrap();
+        let mut model = Model::new(mesh, Vec4::new(0.0, -1.0, 0.0, 1.0));
+        model.material.base_color = Rgb::white();
+        let scene = Scene {
+            models: vec![model],
+            lights: Vec::new(),
+            camera: Camera::new(Vec4::new(0.0, 0.0, 10.0, 1.0),
+                                Vec4::new(0.0, 0.0, 0.0, 1.0),
+                                Vec4::new(0.0, 1.0, 0.0, 0.0))
+        };
+
+        let mut raytrace = Raytrace::new(scene, Pixmap::new(32, 24));
+        raytrace.settings.mode = RenderMode::Flat;
+        raytrace.settings.samples_per_pixel = 16;
+        raytrace.settings.pixel_sampling = pixel_sampling;
+        raytrace.settings.filter = filter;
+        raytrace.settings.threads = threads;
+        raytrace.settings.tile_size = 8;
+        raytrace.run();
+        raytrace.pixmap
+    };
+    let count_edge_pixels = |pixmap: &Pixmap| {
+        let mut count = 0;
+        for x in 0..pixmap.width {
+            for y in 0..pixmap.height {
+                let color = pixmap.get_color(x, y);
+                if color != (Color { r: 0, g: 0, b: 0 }) && color != (Color { r: 255, g: 255, b: 255 }) {
+                    count += 1;
+                }
+            }
+        }
+        count
+    };
+
+    // Without antialiasing every pixel is either fully covered or not at all.
+    assert_eq!(count_edge_pixels(&render(PixelSampling::Center, Filter::default(), 1)), 0);
+
+    // Partially covered pixels along the silhouette get blended colors.
+    assert!(count_edge_pixels(&render(PixelSampling::Stratified, Filter::default(), 1)) > 0);
+    assert!(count_edge_pixels(&render(PixelSampling::Jittered, Filter::Tent { radius: 1.0 }, 1)) > 0);
+
+    // Filters reaching into neighbouring tiles do not depend on the thread count.
+    let filter = Filter::mitchell_netravali();
+    assert_eq!(render(PixelSampling::Stratified, filter, 4), render(PixelSampling::Stratified, filter, 1));
+}
+
+#[test]
+fn test_shading() {
+    use camera::Camera;
+    use light::Light;
+    use mesh;
+
+    let mesh = mesh::Mesh::try_load_from_off("./meshes/plane4x4.off", mesh::PolygonWinding::Clockwise).unwrap();
+    let lights = vec![Light::Directional {
+        direction: Vec4::new(0.0, 0.0, -1.0, 0.0),
+        color: Rgb::white(),
+        intensity: 0.8
+    }];
+    let scene = Scene {
+        models: vec![Model::new(mesh, Vec4::new(0.0, 0.0, 0.0, 1.0))],
+        lights,
+        camera: Camera::new(Vec4::new(0.0, 0.0, 10.0, 1.0),
+                            Vec4::new(0.0, 0.0, 0.0, 1.0),
+                            Vec4::new(0.0, 1.0, 0.0, 0.0))
+    };
+    let raytrace = Raytrace::new(scene, Pixmap::new(8, 8));
+
+    // Light, normal and viewing direction coincide, so both diffuse and specular are at their maximum.
+    let ray = Ray::new(Vec4::new(0.0, 0.0, 10.0, 1.0), Vec4::new(0.0, 0.0, -1.0, 0.0));
+    let intersection = raytrace.calculate_closest_intersection(&ray).unwrap();
+    let color = raytrace.shade(&intersection, 0, &mut Random::new(0));
+    let material = Material::default();
+    let expected = 0.8 * (material.diffuse_color() + (material.shininess() + 2.0) / 2.0 * material.specular_color());
+    assert!((color.r - expected.r).abs() < 1e-9);
+    assert!((color.g - expected.g).abs() < 1e-9);
+
+    // Light coming from behind the plane does not illuminate the side facing the camera.
+    let mut raytrace = raytrace;
+    raytrace.scene.lights[0] = Light::Directional {
+        direction: Vec4::new(0.0, 0.0, 1.0, 0.0),
+        color: Rgb::white(),
+        intensity: 0.8
+    };
+    assert_eq!(raytrace.shade(&intersection, 0, &mut Random::new(0)), Rgb::black());
+
+    // Emissive surfaces are visible even without light.
+    raytrace.scene.models[0].material.emission = Rgb::new(0.0, 0.0, 0.25);
+    assert_eq!(raytrace.shade(&intersection, 0, &mut Random::new(0)), Rgb::new(0.0, 0.0, 0.25));
+}
+
+#[test]
+fn test_shadows() {
+    use camera::Camera;
+    use light::Light;
+    use mesh;
+
+    // An occluder hovers above the right half of the ground plane.
+    let mesh = mesh::Mesh::try_load_from_off("./meshes/plane4x4.off", mesh::PolygonWinding::Clockwise).unwrap();
+    let ground = Model::new(mesh.clone(), Vec4::new(0.0, 0.0, 0.0, 1.0));
+    let occluder = Model::new(mesh, Vec4::new(1.0, 0.0, 1.0, 1.0));
+
+    let scene = Scene {
+        models: vec![ground, occluder],
+        lights: vec![Light::Directional {
+            direction: Vec4::new(0.0, 0.0, -1.0, 0.0),
+            color: Rgb::white(),
+            intensity: 1.0
+        }],
+        camera: Camera::new(Vec4::new(0.0, 0.0, 10.0, 1.0),
+                            Vec4::new(0.0, 0.0, 0.0, 1.0),
+                            Vec4::new(0.0, 1.0, 0.0, 0.0))
+    };
+    let mut raytrace = Raytrace::new(scene, Pixmap::new(8, 8));
+
+    let normal = Vec4::new(0.0, 0.0, 1.0, 0.0);
+    let lit = Vec4::new(-0.5, 0.5, 0.0, 1.0);
+    let shadowed = Vec4::new(0.5, 0.5, 0.0, 1.0);
+    let boundary = Vec4::new(0.0, 0.5, 0.0, 1.0);
+    let material = Material { base_color: Rgb::white(), roughness: 1.0, ..Material::default() };
+    let mut random = Random::new(0);
+
+    // Hard shadows of a directional light.
+    assert!(raytrace.direct_lighting(&lit, &normal, &normal, &material, &mut random).r > 0.9);
+    assert_eq!(raytrace.direct_lighting(&shadowed, &normal, &normal, &material, &mut random), Rgb::black());
+
+    // The surface the shadow ray starts on must not shadow itself.
+    assert!(raytrace.direct_lighting(&Vec4::new(0.5, 0.5, 1.0, 1.0), &normal, &normal, &material, &mut random).r > 0.9);
+
+    // The boundary of an area light's shadow is only partially lit.
+    raytrace.scene.lights = vec![Light::Area {
+        position: Vec4::new(0.0, 0.0, 3.0, 1.0),
+        u: Vec4::new(0.0, 4.0, 0.0, 0.0),
+        v: Vec4::new(4.0, 0.0, 0.0, 0.0),
+        color: Rgb::white(),
+        intensity: 9.0,
+        samples: 8
+    }];
+    let lit = raytrace.direct_lighting(&Vec4::new(-0.9, 0.5, 0.0, 1.0), &normal, &normal, &material, &mut random).r;
+    let penumbra = raytrace.direct_lighting(&boundary, &normal, &normal, &material, &mut random).r;
+    assert!(penumbra > 0.1 * lit && penumbra < 0.9 * lit);
+}
+
+#[test]
+fn test_reflection_and_refraction() {
+    use camera::Camera;
+    use mesh;
+
+    // A green light panel hovers above the surface under test, a white one lies below it.
+    let mesh = mesh::Mesh::try_load_from_off("./meshes/plane4x4.off", mesh::PolygonWinding::Clockwise).unwrap();
+    let mut surface = Model::new(mesh.clone(), Vec4::new(0.0, 0.0, 0.0, 1.0));
+    let mut above = Model::new(mesh.clone(), Vec4::new(0.0, 0.0, 5.0, 1.0));
+    let mut below = Model::new(mesh, Vec4::new(0.0, 0.0, -2.0, 1.0));
+
+    let panel = Material { base_color: Rgb::black(), roughness: 1.0, ..Material::default() };
+    above.material = Material { emission: Rgb::new(0.0, 1.0, 0.0), ..panel.clone() };
+    below.material = Material { emission: Rgb::white(), ..panel };
+
+    // A perfect mirror.
+    surface.material = Material { base_color: Rgb::white(), roughness: 0.0, metallic: 1.0, ..Material::default() };
+
+    let scene = Scene {
+        models: vec![surface, above, below],
+        lights: Vec::new(),
+        camera: Camera::new(Vec4::new(0.0, 0.0, 10.0, 1.0),
+                            Vec4::new(0.0, 0.0, 0.0, 1.0),
+                            Vec4::new(0.0, 1.0, 0.0, 0.0))
+    };
+    let mut raytrace = Raytrace::new(scene, Pixmap::new(8, 8));
+    let ray = Ray::new(Vec4::new(0.1, 0.1, 3.0, 1.0), Vec4::new(0.0, 0.0, -1.0, 0.0));
+
+    assert_eq!(raytrace.trace(&ray, 0, &mut Random::new(0)), Rgb::new(0.0, 1.0, 0.0));
+
+    // Reflections are not traced beyond the maximum depth.
+    raytrace.settings.max_depth = 0;
+    assert_eq!(raytrace.trace(&ray, 0, &mut Random::new(0)), Rgb::black());
+    raytrace.settings.max_depth = 5;
+
+    // Clear glass lets through everything that is not reflected at its surface.
+    raytrace.scene.models[0].material = Material {
+        base_color: Rgb::white(),
+        roughness: 0.0,
+        opacity: 0.0,
+        index_of_refraction: 1.5,
+        ..Material::default()
+    };
+    let color = raytrace.trace(&ray, 0, &mut Random::new(0));
+    assert!((color.r - 0.96).abs() < 1e-9);
+    assert!((color.g - (0.96 + 0.04)).abs() < 1e-9);
+}
+
+#[test]
+fn test_path_tracing() {
+    use camera::Camera;
+    use light::Light;
+    use mesh;
+
+    // A diffuse plane is facing a light panel of the same size half a unit above it.
+    let mesh = mesh::Mesh::try_load_from_off("./meshes/plane4x4.off", mesh::PolygonWinding::Clockwise).unwrap();
+    let mut receiver = Model::new(mesh.clone(), Vec4::new(0.0, 0.0, 0.0, 1.0));
+    let mut emitter = Model::new(mesh, Vec4::new(0.0, 0.0, 0.5, 1.0));
+    receiver.material = Material { base_color: Rgb::new(0.8, 0.8, 0.8), roughness: 1.0, index_of_refraction: 1.0, ..Material::default() };
+    emitter.material = Material { base_color: Rgb::black(), metallic: 1.0, emission: Rgb::white(), ..Material::default() };
+
+    let scene = Scene {
+        models: vec![receiver, emitter],
+        lights: Vec::new(),
+        camera: Camera::new(Vec4::new(0.0, 0.0, 10.0, 1.0),
+                            Vec4::new(0.0, 0.0, 0.0, 1.0),
+                            Vec4::new(0.0, 1.0, 0.0, 0.0))
+    };
+    let mut raytrace = Raytrace::new(scene, Pixmap::new(8, 8));
+    let ray = Ray::new(Vec4::new(0.01, 0.02, 0.25, 1.0), Vec4::new(0.0, 0.0, -1.0, 0.0));
+
+    // The light reflected by the receiver is its albedo times the form factor
+    // of the panel, which is made up of four rectangles with a corner above the receiver.
+    let x: f64 = 1.00769 / 0.5;
+    let corner = (x / (1.0 + x * x).sqrt() * (x / (1.0 + x * x).sqrt()).atan()) / std::f64::consts::PI;
+    let expected = 0.8 * 4.0 * corner;
+
+    let mut random = Random::new(0);
+    let samples = 4000;
+    let mut estimate = 0.0;
+    for _ in 0..samples {
+        estimate += raytrace.path_trace(&ray, &mut random).r / samples as f64;
+    }
+    assert!((estimate - expected).abs() < 0.03);
+
+    // Without the panel, direct lighting matches the Whitted shading of a rough surface.
+    raytrace.scene.models.pop();
+    raytrace.scene.lights.push(Light::Directional {
+        direction: Vec4::new(0.3, 0.0, -1.0, 0.0),
+        color: Rgb::white(),
+        intensity: 1.0
+    });
+    let path_traced = raytrace.path_trace(&ray, &mut random);
+    let shaded = raytrace.trace(&ray, 0, &mut random);
+    assert!((path_traced.r - shaded.r).abs() < 1e-12);
+}
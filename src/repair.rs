@@ -0,0 +1,437 @@
+use std::collections::{ BTreeMap, HashMap };
+use std::fmt;
+use algebra::Vec4;
+use mesh::{ Face, Mesh, position_key };
+
+/// Problems found by `Mesh::validate`. A mesh without any of them can be
+/// rendered safely, see `is_valid`.
+#[allow(dead_code)]
+#[derive(Clone,PartialEq,Debug,Default)]
+pub struct MeshReport {
+    /// Faces referring to vertices that do not exist.
+    pub out_of_range_faces: Vec<usize>,
+    /// Faces without any area, e.g. because two of their corners coincide.
+    pub degenerate_faces: Vec<usize>,
+    /// Vertices that equal an earlier vertex in their position and all of
+    /// their attributes, together with that earlier vertex.
+    pub duplicate_vertices: Vec<(usize, usize)>,
+    /// Edges shared by more than two faces.
+    pub non_manifold_edges: Vec<(usize, usize)>,
+    /// Edges between two faces that traverse them in the same direction,
+    /// which means that one of the faces is flipped.
+    pub inconsistent_edges: Vec<(usize, usize)>,
+    /// Vertices that no face refers to.
+    pub unreferenced_vertices: Vec<usize>,
+    /// Names of the optional attributes that are neither empty nor have
+    /// one entry per vertex or face.
+    pub mismatched_attributes: Vec<&'static str>
+}
+
+/// Undirected edges with the faces they belong to, each together with
+/// whether the face traverses the edge from its lower to its higher vertex.
+type EdgeFaces = BTreeMap<(usize, usize), Vec<(usize, bool)>>;
+
+#[allow(dead_code)]
+impl MeshReport {
+    /// Whether the mesh does not have any problems at all.
+    pub fn is_valid(&self) -> bool {
+        *self == MeshReport::default()
+    }
+}
+
+#[allow(dead_code)]
+impl Mesh {
+    /// Checks the mesh for problems. Edges are identified by the positions
+    /// of their vertices, so that faces meeting at vertices which were
+    /// duplicated for different attributes, e.g. along a texture seam, are
+    /// still considered to be neighbours. Those edges are reported using
+    /// the first vertex at each position.
+    pub fn validate(&self) -> MeshReport {
+        let mut report = MeshReport {
+            mismatched_attributes: self.mismatched_attributes(),
+            ..MeshReport::default()
+        };
+
+        let mut referenced = vec![false; self.vertices.len()];
+        let mut valid_faces: Vec<usize> = Vec::new();
+        for (face_index, face) in self.faces.iter().enumerate() {
+            if !self.is_in_range(face) {
+                report.out_of_range_faces.push(face_index);
+                continue;
+            }
+
+            for &vertex in &[face.a, face.b, face.c] {
+                referenced[vertex] = true;
+            }
+            if self.is_degenerate(face) {
+                report.degenerate_faces.push(face_index);
+            }
+            else {
+                valid_faces.push(face_index);
+            }
+        }
+
+        report.unreferenced_vertices = (0..self.vertices.len()).filter(|&vertex| !referenced[vertex]).collect();
+
+        let mut seen: HashMap<Vec<u64>, usize> = HashMap::new();
+        for vertex in 0..self.vertices.len() {
+            let key = self.vertex_key(vertex);
+            match seen.get(&key) {
+                Some(&first) => report.duplicate_vertices.push((vertex, first)),
+                None => {
+                    seen.insert(key, vertex);
+                }
+            }
+        }
+
+        for (&edge, faces) in &self.edge_faces(&valid_faces) {
+            if faces.len() > 2 {
+                report.non_manifold_edges.push(edge);
+            }
+            else if faces.len() == 2 && faces[0].1 == faces[1].1 {
+                report.inconsistent_edges.push(edge);
+            }
+        }
+
+        report
+    }
+
+    /// Fixes the problems `validate` finds as far as possible. Attributes
+    /// that do not match the number of vertices or faces are removed, faces
+    /// referring to missing vertices are dropped and duplicate vertices are
+    /// merged. Afterwards degenerate faces are dropped along with vertices
+    /// that are not referenced anymore. Finally the faces of every connected
+    /// part are flipped to agree with their neighbours. Closed parts are
+    /// turned outwards, open ones keep the orientation of most of their faces.
+    /// Non-manifold edges cannot be fixed and remain.
+    pub fn repair(&mut self) {
+        for attribute in self.mismatched_attributes() {
+            match attribute {
+                "normals" => self.normals.clear(),
+                "colors" => self.colors.clear(),
+                "texture coordinates" => self.texture_coordinates.clear(),
+                _ => self.face_colors.clear()
+            }
+        }
+
+        let keep: Vec<bool> = self.faces.iter().map(|face| self.is_in_range(face)).collect();
+        self.retain_faces(&keep);
+
+        // Point every face at the first of all identical vertices.
+        let mut seen: HashMap<Vec<u64>, usize> = HashMap::new();
+        let welded: Vec<usize> = (0..self.vertices.len()).map(|vertex| {
+            *seen.entry(self.vertex_key(vertex)).or_insert(vertex)
+        }).collect();
+        for face in &mut self.faces {
+            *face = Face { a: welded[face.a], b: welded[face.b], c: welded[face.c] };
+        }
+
+        let keep: Vec<bool> = self.faces.iter().map(|face| !self.is_degenerate(face)).collect();
+        self.retain_faces(&keep);
+        self.remove_unreferenced_vertices();
+        self.orient_faces();
+    }
+
+    fn is_in_range(&self, face: &Face) -> bool {
+        face.a < self.vertices.len() && face.b < self.vertices.len() && face.c < self.vertices.len()
+    }
+
+    /// Whether the face has no area, relative to the length of its edges.
+    fn is_degenerate(&self, face: &Face) -> bool {
+        let (a, b, c) = (&self.vertices[face.a], &self.vertices[face.b], &self.vertices[face.c]);
+        let (ab, ac, bc) = (b.clone() - a.clone(), c.clone() - a.clone(), c.clone() - b.clone());
+        let normal = Vec4::cross(&ab, &ac);
+
+        let longest = Vec4::dot(&ab, &ab).max(Vec4::dot(&ac, &ac)).max(Vec4::dot(&bc, &bc));
+        Vec4::dot(&normal, &normal).sqrt() <= 1e-12 * longest || !longest.is_finite()
+    }
+
+    fn mismatched_attributes(&self) -> Vec<&'static str> {
+        let (vertices, faces) = (self.vertices.len(), self.faces.len());
+        [("normals", self.normals.len(), vertices),
+         ("colors", self.colors.len(), vertices),
+         ("texture coordinates", self.texture_coordinates.len(), vertices),
+         ("face colors", self.face_colors.len(), faces)].iter()
+            .filter(|&&(_, len, expected)| len != 0 && len != expected)
+            .map(|&(name, _, _)| name)
+            .collect()
+    }
+
+    /// Bits of the vertex' position and all of its valid attributes, see `position_key`.
+    fn vertex_key(&self, vertex: usize) -> Vec<u64> {
+        let count = self.vertices.len();
+        let mut key = position_key(&self.vertices[vertex]).to_vec();
+        if self.normals.len() == count {
+            key.extend_from_slice(&position_key(&self.normals[vertex]));
+        }
+        if self.colors.len() == count {
+            let color = self.colors[vertex];
+            key.extend_from_slice(&[(color.r + 0.0).to_bits(), (color.g + 0.0).to_bits(), (color.b + 0.0).to_bits()]);
+        }
+        if self.texture_coordinates.len() == count {
+            let (u, v) = self.texture_coordinates[vertex];
+            key.extend_from_slice(&[(u + 0.0).to_bits(), (v + 0.0).to_bits()]);
+        }
+        key
+    }
+
+    /// First vertex with the same position for every vertex.
+    fn position_representatives(&self) -> Vec<usize> {
+        let mut seen: HashMap<[u64; 3], usize> = HashMap::new();
+        (0..self.vertices.len()).map(|vertex| {
+            *seen.entry(position_key(&self.vertices[vertex])).or_insert(vertex)
+        }).collect()
+    }
+
+    /// Edges of the given faces between vertices identified by their position.
+    fn edge_faces(&self, faces: &[usize]) -> EdgeFaces {
+        let representatives = self.position_representatives();
+        let mut edges: EdgeFaces = BTreeMap::new();
+        for &face_index in faces {
+            let face = &self.faces[face_index];
+            let corners = [representatives[face.a], representatives[face.b], representatives[face.c]];
+            for i in 0..3 {
+                let (from, to) = (corners[i], corners[(i + 1) % 3]);
+                edges.entry((from.min(to), from.max(to))).or_default().push((face_index, from < to));
+            }
+        }
+        edges
+    }
+
+    /// Keeps only the faces for which `keep` is true, along with their colors
+    /// and group memberships.
+    fn retain_faces(&mut self, keep: &[bool]) {
+        let mut new_indices: Vec<Option<usize>> = Vec::with_capacity(keep.len());
+        let mut count = 0;
+        for &kept in keep {
+            new_indices.push(if kept { count += 1; Some(count - 1) } else { None });
+        }
+
+        let mut index = 0;
+        self.faces.retain(|_| { index += 1; keep[index - 1] });
+        if !self.face_colors.is_empty() {
+            let mut index = 0;
+            self.face_colors.retain(|_| { index += 1; keep[index - 1] });
+        }
+        for group in &mut self.groups {
+            group.faces = group.faces.iter().filter_map(|&face| new_indices.get(face).cloned().unwrap_or(None)).collect();
+        }
+    }
+
+    fn remove_unreferenced_vertices(&mut self) {
+        let mut referenced = vec![false; self.vertices.len()];
+        for face in &self.faces {
+            for &vertex in &[face.a, face.b, face.c] {
+                referenced[vertex] = true;
+            }
+        }
+
+        // Old index of every remaining vertex and new index of every old one.
+        let mut sources: Vec<usize> = Vec::new();
+        let mut new_indices = vec![0; self.vertices.len()];
+        for vertex in 0..self.vertices.len() {
+            if referenced[vertex] {
+                new_indices[vertex] = sources.len();
+                sources.push(vertex);
+            }
+        }
+
+        for face in &mut self.faces {
+            *face = Face { a: new_indices[face.a], b: new_indices[face.b], c: new_indices[face.c] };
+        }
+        self.vertices = sources.iter().map(|&vertex| self.vertices[vertex].clone()).collect();
+        if !self.normals.is_empty() {
+            self.normals = sources.iter().map(|&vertex| self.normals[vertex].clone()).collect();
+        }
+        if !self.colors.is_empty() {
+            self.colors = sources.iter().map(|&vertex| self.colors[vertex]).collect();
+        }
+        if !self.texture_coordinates.is_empty() {
+            self.texture_coordinates = sources.iter().map(|&vertex| self.texture_coordinates[vertex]).collect();
+        }
+    }
+
+    /// Flips faces so that neighbours traverse their shared edge in opposite
+    /// directions, which makes their normals point to the same side.
+    fn orient_faces(&mut self) {
+        let all_faces: Vec<usize> = (0..self.faces.len()).collect();
+        let edges = self.edge_faces(&all_faces);
+
+        // Neighbours across manifold edges and whether both faces currently
+        // traverse that edge in the same direction.
+        let mut neighbours: Vec<Vec<(usize, bool)>> = vec![Vec::new(); self.faces.len()];
+        let mut on_border = vec![false; self.faces.len()];
+        for faces in edges.values() {
+            if faces.len() == 2 {
+                let ((first, first_forward), (second, second_forward)) = (faces[0], faces[1]);
+                neighbours[first].push((second, first_forward == second_forward));
+                neighbours[second].push((first, first_forward == second_forward));
+            }
+            else {
+                for &(face_index, _) in faces {
+                    on_border[face_index] = true;
+                }
+            }
+        }
+
+        let mut flipped: Vec<Option<bool>> = vec![None; self.faces.len()];
+        for seed in 0..self.faces.len() {
+            if flipped[seed].is_some() {
+                continue;
+            }
+
+            flipped[seed] = Some(false);
+            let mut component = vec![seed];
+            let mut stack = vec![seed];
+            while let Some(face_index) = stack.pop() {
+                let is_flipped = flipped[face_index] == Some(true);
+                for &(neighbour, same_direction) in &neighbours[face_index] {
+                    // Non-orientable surfaces cannot satisfy every edge, the
+                    // first orientation found for a face is kept then.
+                    if flipped[neighbour].is_none() {
+                        flipped[neighbour] = Some(is_flipped != same_direction);
+                        component.push(neighbour);
+                        stack.push(neighbour);
+                    }
+                }
+            }
+
+            let is_closed = component.iter().all(|&face_index| !on_border[face_index]);
+            let reverse = if is_closed {
+                let volume: f64 = component.iter().map(|&face_index| {
+                    let volume = self.signed_volume(&self.faces[face_index]);
+                    if flipped[face_index] == Some(true) { -volume } else { volume }
+                }).sum();
+                volume < 0.0
+            }
+            else {
+                let flip_count = component.iter().filter(|&&face_index| flipped[face_index] == Some(true)).count();
+                2 * flip_count > component.len()
+            };
+
+            for &face_index in &component {
+                if (flipped[face_index] == Some(true)) != reverse {
+                    let face = &mut self.faces[face_index];
+                    *face = Face { a: face.a, b: face.c, c: face.b };
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for MeshReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_valid() {
+            return write!(f, "No problems");
+        }
+
+        let counts = [
+            ("Out-of-range faces", self.out_of_range_faces.len()),
+            ("degenerate faces", self.degenerate_faces.len()),
+            ("duplicate vertices", self.duplicate_vertices.len()),
+            ("non-manifold edges", self.non_manifold_edges.len()),
+            ("inconsistently wound edges", self.inconsistent_edges.len()),
+            ("unreferenced vertices", self.unreferenced_vertices.len())
+        ];
+        let mut problems: Vec<String> = counts.iter()
+                                              .map(|&(problem, count)| format!("{}: {}", problem, count))
+                                              .collect();
+        if !self.mismatched_attributes.is_empty() {
+            problems.push(format!("mismatched attributes: {}", self.mismatched_attributes.join(", ")));
+        }
+        write!(f, "{}", problems.join(", "))
+    }
+}
+
+#[test]
+fn test_validate_primitives() {
+    for mesh in &[Mesh::cube(1.0), Mesh::uv_sphere(1.0, 16, 8), Mesh::icosphere(1.0, 2),
+                  Mesh::cylinder(1.0, 2.0, 12), Mesh::cone(1.0, 2.0, 12), Mesh::disk(1.0, 12), Mesh::torus(2.0, 0.5, 16, 8), Mesh::grid_plane(2.0, 2.0, 4, 4)] {
+        let report = mesh.validate();
+        assert!(report.is_valid(), "{}", report);
+    }
+}
+
+#[test]
+fn test_validate() {
+    use color::Rgb;
+
+    let mut mesh = Mesh::grid_plane(2.0, 2.0, 2, 1);
+    let count = mesh.faces.len();
+    // Flip the second face, duplicate a vertex and add an unused one.
+    let face = mesh.faces[1].clone();
+    mesh.faces[1] = Face { a: face.a, b: face.c, c: face.b };
+    mesh.vertices.push(mesh.vertices[0].clone());
+    mesh.normals.push(mesh.normals[0].clone());
+    mesh.texture_coordinates.push(mesh.texture_coordinates[0]);
+    mesh.faces.push(Face { a: 0, b: 1, c: 1 });
+    mesh.faces.push(Face { a: 0, b: 1, c: 42 });
+    mesh.colors = vec![Rgb::white()];
+
+    let report = mesh.validate();
+    assert!(!report.is_valid());
+    assert_eq!(report.out_of_range_faces, vec![count + 1]);
+    assert_eq!(report.degenerate_faces, vec![count]);
+    assert_eq!(report.duplicate_vertices, vec![(mesh.vertices.len() - 1, 0)]);
+    assert!(report.non_manifold_edges.is_empty());
+    assert_eq!(report.inconsistent_edges.len(), 1);
+    assert_eq!(report.unreferenced_vertices, vec![mesh.vertices.len() - 1]);
+    assert_eq!(report.mismatched_attributes, vec!["colors"]);
+    assert_eq!(report.to_string(), "Out-of-range faces: 1, degenerate faces: 1, duplicate vertices: 1, \
+                                    non-manifold edges: 0, inconsistently wound edges: 1, unreferenced vertices: 1, \
+                                    mismatched attributes: colors");
+
+    // A fin sticking out of a plane.
+    let mut fin = Mesh::grid_plane(2.0, 2.0, 1, 1);
+    fin.vertices.push(Vec4::new(0.0, 1.0, 0.0, 1.0));
+    fin.normals.clear();
+    fin.texture_coordinates.clear();
+    let face = fin.faces[0].clone();
+    fin.faces.push(Face { a: face.a, b: face.c, c: 4 });
+    assert_eq!(fin.validate().non_manifold_edges.len(), 1);
+}
+
+#[test]
+fn test_repair() {
+    use mesh::FaceGroup;
+
+    let original = Mesh::icosphere(1.0, 1);
+    let mut mesh = original.clone();
+    for face_index in (0..mesh.faces.len()).filter(|face_index| face_index % 3 == 0) {
+        let face = mesh.faces[face_index].clone();
+        mesh.faces[face_index] = Face { a: face.b, b: face.a, c: face.c };
+    }
+    // Let a face use a duplicate of one of its vertices.
+    mesh.vertices.push(mesh.vertices[mesh.faces[5].a].clone());
+    mesh.normals.push(mesh.normals[mesh.faces[5].a].clone());
+    mesh.faces[5].a = mesh.vertices.len() - 1;
+    mesh.vertices.insert(0, Vec4::new(5.0, 5.0, 5.0, 1.0));
+    mesh.normals.insert(0, Vec4::new(0.0, 1.0, 0.0, 0.0));
+    for face in &mut mesh.faces {
+        *face = Face { a: face.a + 1, b: face.b + 1, c: face.c + 1 };
+    }
+    mesh.faces.insert(0, Face { a: 1, b: 2, c: 2 });
+    mesh.faces.push(Face { a: 1, b: 2, c: 100 });
+    mesh.groups.push(FaceGroup { name: "some".to_string(), faces: vec![0, 1, 2, mesh.faces.len() - 1] });
+    assert!(!mesh.validate().is_valid());
+
+    mesh.repair();
+    let report = mesh.validate();
+    assert!(report.is_valid(), "{}", report);
+    assert_eq!(mesh.vertices.len(), original.vertices.len());
+    assert_eq!(mesh.faces.len(), original.faces.len());
+    assert_eq!(mesh.groups[0].faces, vec![0, 1]);
+    // All faces point outwards again.
+    for face in &mesh.faces {
+        assert!(mesh.signed_volume(face) > 0.0);
+        assert!(Vec4::dot(&mesh.face_normal(face), &mesh.normals[face.a]) > 0.0);
+    }
+
+    // Open surfaces keep the orientation of most of their faces.
+    let mut plane = Mesh::grid_plane(2.0, 2.0, 4, 4);
+    let face = plane.faces[3].clone();
+    plane.faces[3] = Face { a: face.a, b: face.c, c: face.b };
+    plane.repair();
+    assert!(plane.validate().is_valid());
+    assert!(plane.faces.iter().all(|face| plane.face_normal(face).y > 0.0));
+}
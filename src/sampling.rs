@@ -0,0 +1,84 @@
+use std::f64::consts::PI;
+use algebra::Vec4;
+use random::Random;
+
+/// Maps a point from the unit square onto the unit disk using Shirley's
+/// concentric mapping, which preserves the stratification of the samples.
+pub fn concentric_disk(u1: f64, u2: f64) -> (f64, f64) {
+    let x = 2.0 * u1 - 1.0;
+    let y = 2.0 * u2 - 1.0;
+
+    if x == 0.0 && y == 0.0 {
+        return (0.0, 0.0);
+    }
+
+    let (r, theta) = if x.abs() > y.abs() {
+        (x, PI / 4.0 * (y / x))
+    }
+    else {
+        (y, PI / 2.0 - PI / 4.0 * (x / y))
+    };
+
+    (r * theta.cos(), r * theta.sin())
+}
+
+/// Random direction in the hemisphere around the normalized `normal`,
+/// distributed with a probability density of cos(theta) / pi.
+pub fn cosine_hemisphere(normal: &Vec4, random: &mut Random) -> Vec4 {
+    let (x, y) = concentric_disk(random.next_f64(), random.next_f64());
+    let z = (1.0 - x * x - y * y).max(0.0).sqrt();
+    to_world(x, y, z, normal)
+}
+
+/// Random direction around the normalized `axis`, distributed with a
+/// probability density of (exponent + 1) / (2 pi) * cos(alpha)^exponent
+/// where alpha is the angle between the direction and the axis.
+pub fn phong_lobe(axis: &Vec4, exponent: f64, random: &mut Random) -> Vec4 {
+    let cos_alpha = random.next_f64().powf(1.0 / (exponent + 1.0));
+    let sin_alpha = (1.0 - cos_alpha * cos_alpha).max(0.0).sqrt();
+    let phi = 2.0 * PI * random.next_f64();
+    to_world(sin_alpha * phi.cos(), sin_alpha * phi.sin(), cos_alpha, axis)
+}
+
+/// Transforms a direction given relative to `axis` as z-axis into world space.
+fn to_world(x: f64, y: f64, z: f64, axis: &Vec4) -> Vec4 {
+    let (tangent, bitangent) = Vec4::orthonormal_basis(axis);
+    x * tangent + y * bitangent + z * axis.clone()
+}
+
+#[test]
+fn test_concentric_disk() {
+    let mut random = Random::new(1);
+    for _ in 0..1000 {
+        let (x, y) = concentric_disk(random.next_f64(), random.next_f64());
+        assert!(x * x + y * y <= 1.0 + 1e-12);
+    }
+    let (x, y) = concentric_disk(1.0, 0.5);
+    assert!((x - 1.0).abs() < 1e-12 && y.abs() < 1e-12);
+}
+
+#[test]
+fn test_cosine_hemisphere() {
+    let normal = Vec4::new(0.0, 1.0, 0.0, 0.0);
+    let mut random = Random::new(7);
+    let mut mean_cos = 0.0;
+    for _ in 0..10000 {
+        let d = cosine_hemisphere(&normal, &mut random);
+        assert!((Vec4::dot(&d, &d) - 1.0).abs() < 1e-9);
+        assert!(d.y >= 0.0);
+        mean_cos += d.y / 10000.0;
+    }
+    // E[cos(theta)] = 2/3 for a cosine weighted distribution.
+    assert!((mean_cos - 2.0 / 3.0).abs() < 0.01);
+}
+
+#[test]
+fn test_phong_lobe() {
+    let axis = Vec4::new(1.0, 0.0, 0.0, 0.0);
+    let mut random = Random::new(3);
+    for _ in 0..1000 {
+        let d = phong_lobe(&axis, 1000.0, &mut random);
+        assert!((Vec4::dot(&d, &d) - 1.0).abs() < 1e-9);
+        assert!(d.x > 0.9);
+    }
+}
@@ -1,9 +1,10 @@
 use camera::Camera;
 use model::Model;
+use light::Light;
 
 #[allow(dead_code)]
 pub struct Scene {
     pub models: Vec<Model>,
+    pub lights: Vec<Light>,
     pub camera: Camera
 }
-
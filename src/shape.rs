@@ -0,0 +1,825 @@
+use std::f64::consts::PI;
+use algebra::{ Aabb, Vec4 };
+use bvh::Bvh;
+use mesh::Mesh;
+use sampling::concentric_disk;
+
+/// Hits closer than this are ignored so that rays starting on a surface do
+/// not hit it again due to rounding errors.
+const EPSILON: f64 = 0.000001;
+
+/// Intersection of a ray with a shape in the shape's object space.
+#[derive(Clone,Debug)]
+pub struct ShapeHit {
+    /// Distance along the ray's direction.
+    pub t: f64,
+    pub position: Vec4,
+    /// Face of a mesh or side of an analytic shape that was hit, which
+    /// models may assign their own materials to.
+    pub part: usize,
+    /// Barycentric coordinates within the face for meshes, texture
+    /// coordinates for analytic shapes.
+    pub uv: (f64, f64)
+}
+
+/// Point on the surface of a shape together with its outward normal.
+#[allow(dead_code)]
+#[derive(Clone,Debug)]
+pub struct SurfaceSample {
+    pub position: Vec4,
+    pub normal: Vec4
+}
+
+/// Geometry that rays can be intersected with. Everything is given in the
+/// shape's object space, positions are points and normals are normalized
+/// directions that point outwards, or upwards for flat shapes.
+#[allow(dead_code)]
+pub trait Shape: Send + Sync {
+    /// Closest hit in front of the ray's origin and closer than `max_distance`.
+    fn intersect(&self, origin: &Vec4, direction: &Vec4, max_distance: f64) -> Option<ShapeHit>;
+
+    /// Whether the ray hits the shape at all within `max_distance`, which
+    /// may be answered faster than finding the closest hit.
+    fn is_hit(&self, origin: &Vec4, direction: &Vec4, max_distance: f64) -> bool {
+        self.intersect(origin, direction, max_distance).is_some()
+    }
+
+    /// Box containing the whole shape.
+    fn bounds(&self) -> Aabb;
+
+    /// Geometric normal at the hit, which determines the surface's orientation.
+    fn normal(&self, hit: &ShapeHit) -> Vec4;
+
+    /// Normal used for shading, which may differ from the geometric one to
+    /// make tessellated surfaces look smooth.
+    fn shading_normal(&self, hit: &ShapeHit) -> Vec4 {
+        self.normal(hit)
+    }
+
+    /// Texture coordinates at the hit, if the shape has any.
+    fn texture_coordinate(&self, hit: &ShapeHit) -> Option<(f64, f64)> {
+        Some(hit.uv)
+    }
+
+    /// Total surface area, infinite for unbounded shapes.
+    fn area(&self) -> f64;
+
+    /// Maps `u` and `v` from [0, 1) onto a point of the surface so that
+    /// uniformly distributed values yield uniformly distributed points.
+    /// Unbounded shapes cannot be sampled.
+    fn sample_point(&self, u: f64, v: f64) -> Option<SurfaceSample>;
+
+    /// The triangle mesh if the shape is one.
+    fn as_mesh(&self) -> Option<&Mesh> {
+        None
+    }
+}
+
+/// Triangle mesh together with its acceleration structure. The mesh cannot
+/// be modified anymore as the hierarchy would not reflect those changes.
+#[allow(dead_code)]
+#[derive(Clone)]
+pub struct TriangleMesh {
+    mesh: Mesh,
+    bvh: Bvh,
+    /// Summed up areas of all faces up to and including the face at each index.
+    cumulative_areas: Vec<f64>
+}
+
+/// Sphere centered at the origin.
+#[allow(dead_code)]
+#[derive(Clone,Debug)]
+pub struct Sphere {
+    pub radius: f64
+}
+
+/// Infinite plane through the origin.
+#[allow(dead_code)]
+#[derive(Clone,Debug)]
+pub struct Plane {
+    /// Normalized direction the plane faces.
+    pub normal: Vec4
+}
+
+/// Axis-aligned box centered at the origin. Its sides are the parts
+/// facing +x, -x, +y, -y, +z and -z in that order.
+#[allow(dead_code)]
+#[derive(Clone,Debug)]
+pub struct Cuboid {
+    /// Edge lengths along the x, y and z axis.
+    pub size: Vec4
+}
+
+/// Closed cylinder around the y axis centered at the origin. Its parts
+/// are the side, the top and the bottom.
+#[allow(dead_code)]
+#[derive(Clone,Debug)]
+pub struct Cylinder {
+    pub radius: f64,
+    pub height: f64
+}
+
+/// Circle in the xz plane facing upwards.
+#[allow(dead_code)]
+#[derive(Clone,Debug)]
+pub struct Disk {
+    pub radius: f64
+}
+
+/// Result of a successful ray/triangle test. `t` is the distance along the
+/// ray direction, `u` and `v` are the barycentric coordinates of the hit
+/// point with respect to the triangle's second and third vertex.
+#[derive(Clone,Debug)]
+pub struct TriangleHit {
+    pub t: f64,
+    pub u: f64,
+    pub v: f64
+}
+
+impl TriangleMesh {
+    /// Builds the acceleration structure for the mesh.
+    pub fn new(mesh: Mesh) -> Self {
+        let bvh = Bvh::build(&mesh);
+        let mut area = 0.0;
+        let cumulative_areas = mesh.faces.iter().map(|face| {
+            let a = &mesh.vertices[face.a];
+            let normal = Vec4::cross(&(mesh.vertices[face.b].clone() - a.clone()),
+                                     &(mesh.vertices[face.c].clone() - a.clone()));
+            area += Vec4::dot(&normal, &normal).sqrt() / 2.0;
+            area
+        }).collect();
+
+        TriangleMesh {
+            mesh,
+            bvh,
+            cumulative_areas
+        }
+    }
+
+    fn intersect_face(&self, face_index: usize, origin: &Vec4, direction: &Vec4) -> Option<TriangleHit> {
+        let face = &self.mesh.faces[face_index];
+        let vertices = &self.mesh.vertices;
+        triangle_intersection(vertices[face.a].clone(), vertices[face.b].clone(), vertices[face.c].clone(),
+                              origin.clone(), direction.clone())
+    }
+}
+
+impl Shape for TriangleMesh {
+    fn intersect(&self, origin: &Vec4, direction: &Vec4, max_distance: f64) -> Option<ShapeHit> {
+        let closest = self.bvh.closest_hit(origin, direction, max_distance, |face_index| {
+            self.intersect_face(face_index, origin, direction).map(|hit| (hit.t, hit))
+        });
+
+        closest.map(|(face_index, hit)| ShapeHit {
+            t: hit.t,
+            position: origin.clone() + hit.t * direction.clone(),
+            part: face_index,
+            uv: (hit.u, hit.v)
+        })
+    }
+
+    fn is_hit(&self, origin: &Vec4, direction: &Vec4, max_distance: f64) -> bool {
+        self.bvh.any_hit(origin, direction, max_distance, |face_index| {
+            match self.intersect_face(face_index, origin, direction) {
+                Some(hit) => hit.t < max_distance,
+                None => false
+            }
+        })
+    }
+
+    fn bounds(&self) -> Aabb {
+        Aabb::from_points(&self.mesh.vertices.iter().collect::<Vec<&Vec4>>())
+    }
+
+    fn normal(&self, hit: &ShapeHit) -> Vec4 {
+        self.mesh.face_normal(&self.mesh.faces[hit.part])
+    }
+
+    fn shading_normal(&self, hit: &ShapeHit) -> Vec4 {
+        let (u, v) = hit.uv;
+        self.mesh.shading_normal(&self.mesh.faces[hit.part], u, v)
+    }
+
+    fn texture_coordinate(&self, hit: &ShapeHit) -> Option<(f64, f64)> {
+        let (u, v) = hit.uv;
+        self.mesh.texture_coordinate(&self.mesh.faces[hit.part], u, v)
+    }
+
+    fn area(&self) -> f64 {
+        self.cumulative_areas.last().cloned().unwrap_or(0.0)
+    }
+
+    fn sample_point(&self, u: f64, v: f64) -> Option<SurfaceSample> {
+        let area = self.area();
+        if area <= 0.0 {
+            return None;
+        }
+
+        // Pick a face proportional to its area and reuse what is left of
+        // `u` within that face's share as a new random number.
+        let target = u * area;
+        let face_index = self.cumulative_areas.iter()
+                                              .position(|&cumulative| target < cumulative)
+                                              .unwrap_or(self.cumulative_areas.len() - 1);
+        let start = if face_index == 0 { 0.0 } else { self.cumulative_areas[face_index - 1] };
+        let u = ((target - start) / (self.cumulative_areas[face_index] - start)).clamp(0.0, 1.0);
+
+        let face = &self.mesh.faces[face_index];
+        let (a, b, c) = (&self.mesh.vertices[face.a], &self.mesh.vertices[face.b], &self.mesh.vertices[face.c]);
+        let s = u.sqrt();
+        let position = a.clone() + (s * (1.0 - v)) * (b.clone() - a.clone()) + (s * v) * (c.clone() - a.clone());
+
+        Some(SurfaceSample {
+            position,
+            normal: self.mesh.face_normal(face)
+        })
+    }
+
+    fn as_mesh(&self) -> Option<&Mesh> {
+        Some(&self.mesh)
+    }
+}
+
+impl Shape for Sphere {
+    fn intersect(&self, origin: &Vec4, direction: &Vec4, max_distance: f64) -> Option<ShapeHit> {
+        let o = origin.to_direction();
+        let a = Vec4::dot(direction, direction);
+        let b = Vec4::dot(&o, direction);
+        let c = Vec4::dot(&o, &o) - self.radius * self.radius;
+
+        let discriminant = b * b - a * c;
+        if discriminant < 0.0 {
+            return None;
+        }
+
+        // Rays starting inside the sphere hit its far side.
+        let root = discriminant.sqrt();
+        let t = [(-b - root) / a, (-b + root) / a].iter().cloned().find(|&t| t > EPSILON)?;
+        if t >= max_distance {
+            return None;
+        }
+
+        let position = origin.clone() + t * direction.clone();
+        let normal = position.to_direction().normalize();
+        let u = (-normal.z).atan2(normal.x) / (2.0 * PI);
+        let v = 1.0 - normal.y.clamp(-1.0, 1.0).acos() / PI;
+        Some(ShapeHit { t, position, part: 0, uv: (u.rem_euclid(1.0), v) })
+    }
+
+    fn bounds(&self) -> Aabb {
+        let r = self.radius;
+        Aabb::from_points(&[&Vec4::new(-r, -r, -r, 1.0), &Vec4::new(r, r, r, 1.0)])
+    }
+
+    fn normal(&self, hit: &ShapeHit) -> Vec4 {
+        hit.position.to_direction().normalize()
+    }
+
+    fn area(&self) -> f64 {
+        4.0 * PI * self.radius * self.radius
+    }
+
+    fn sample_point(&self, u: f64, v: f64) -> Option<SurfaceSample> {
+        let y = 1.0 - 2.0 * u;
+        let r = (1.0 - y * y).max(0.0).sqrt();
+        let phi = 2.0 * PI * v;
+        let normal = Vec4::new(r * phi.cos(), y, -r * phi.sin(), 0.0);
+
+        Some(SurfaceSample {
+            position: (self.radius * normal.clone()).into_point(),
+            normal
+        })
+    }
+}
+
+impl Shape for Plane {
+    fn intersect(&self, origin: &Vec4, direction: &Vec4, max_distance: f64) -> Option<ShapeHit> {
+        let denominator = Vec4::dot(&self.normal, direction);
+        if denominator.abs() < EPSILON {
+            return None;
+        }
+
+        let t = -Vec4::dot(&self.normal, &origin.to_direction()) / denominator;
+        if t <= EPSILON || t >= max_distance {
+            return None;
+        }
+
+        // The texture repeats every unit along two directions within the plane.
+        let position = origin.clone() + t * direction.clone();
+        let (tangent, bitangent) = Vec4::orthonormal_basis(&self.normal);
+        let uv = (Vec4::dot(&tangent, &position.to_direction()), Vec4::dot(&bitangent, &position.to_direction()));
+        Some(ShapeHit { t, position, part: 0, uv })
+    }
+
+    fn bounds(&self) -> Aabb {
+        // Planes perpendicular to an axis are flat along that axis.
+        let extent = |axis: usize| {
+            if self.normal.component(axis).abs() == 1.0 { 0.0 } else { f64::INFINITY }
+        };
+        let (x, y, z) = (extent(0), extent(1), extent(2));
+        Aabb::from_points(&[&Vec4::new(-x, -y, -z, 1.0), &Vec4::new(x, y, z, 1.0)])
+    }
+
+    fn normal(&self, _hit: &ShapeHit) -> Vec4 {
+        self.normal.clone()
+    }
+
+    fn area(&self) -> f64 {
+        f64::INFINITY
+    }
+
+    fn sample_point(&self, _u: f64, _v: f64) -> Option<SurfaceSample> {
+        None
+    }
+}
+
+/// Normal and the directions of increasing texture coordinates for every
+/// side of a cuboid, the same as for `Mesh::cube`.
+const CUBOID_SIDES: [([f64; 3], [f64; 3], [f64; 3]); 6] = [
+    ([1.0, 0.0, 0.0], [0.0, 0.0, -1.0], [0.0, 1.0, 0.0]),
+    ([-1.0, 0.0, 0.0], [0.0, 0.0, 1.0], [0.0, 1.0, 0.0]),
+    ([0.0, 1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, -1.0]),
+    ([0.0, -1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, 1.0]),
+    ([0.0, 0.0, 1.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+    ([0.0, 0.0, -1.0], [-1.0, 0.0, 0.0], [0.0, 1.0, 0.0])
+];
+
+#[allow(dead_code)]
+impl Cuboid {
+    /// Half of the edge length along the given axis.
+    fn half_size(&self, axis: usize) -> f64 {
+        self.size.component(axis) / 2.0
+    }
+
+    /// Area of the side with the given index.
+    fn side_area(&self, side: usize) -> f64 {
+        let axis = side / 2;
+        self.size.component((axis + 1) % 3) * self.size.component((axis + 2) % 3)
+    }
+
+    /// Texture coordinates of a point on the given side, which is covered
+    /// by the whole texture.
+    fn side_texture_coordinate(&self, side: usize, position: &Vec4) -> (f64, f64) {
+        let (_, u, v) = CUBOID_SIDES[side];
+        let coordinate = |direction: [f64; 3]| {
+            let axis = direction.iter().position(|&d| d != 0.0).unwrap();
+            0.5 + direction[axis] * position.component(axis) / (2.0 * self.half_size(axis))
+        };
+        (coordinate(u), coordinate(v))
+    }
+}
+
+impl Shape for Cuboid {
+    fn intersect(&self, origin: &Vec4, direction: &Vec4, max_distance: f64) -> Option<ShapeHit> {
+        // Slab test that remembers the sides through which the ray enters and leaves.
+        let (mut t_near, mut near_side) = (f64::NEG_INFINITY, 0);
+        let (mut t_far, mut far_side) = (f64::INFINITY, 0);
+
+        for axis in 0..3 {
+            let o = origin.component(axis);
+            let d = direction.component(axis);
+            let h = self.half_size(axis);
+            if d == 0.0 {
+                if o.abs() > h {
+                    return None;
+                }
+                continue;
+            }
+
+            // The positive side of each axis comes first.
+            let to_positive = ((h - o) / d, 2 * axis);
+            let to_negative = ((-h - o) / d, 2 * axis + 1);
+            let (near, far) = if to_positive.0 < to_negative.0 { (to_positive, to_negative) } else { (to_negative, to_positive) };
+
+            if near.0 > t_near {
+                t_near = near.0;
+                near_side = near.1;
+            }
+            if far.0 < t_far {
+                t_far = far.0;
+                far_side = far.1;
+            }
+        }
+
+        if t_near > t_far {
+            return None;
+        }
+
+        // Rays starting inside the box hit the side they leave through.
+        let (t, side) = if t_near > EPSILON { (t_near, near_side) } else { (t_far, far_side) };
+        if t <= EPSILON || t >= max_distance {
+            return None;
+        }
+
+        let position = origin.clone() + t * direction.clone();
+        let uv = self.side_texture_coordinate(side, &position);
+        Some(ShapeHit { t, position, part: side, uv })
+    }
+
+    fn bounds(&self) -> Aabb {
+        let (x, y, z) = (self.half_size(0), self.half_size(1), self.half_size(2));
+        Aabb::from_points(&[&Vec4::new(-x, -y, -z, 1.0), &Vec4::new(x, y, z, 1.0)])
+    }
+
+    fn normal(&self, hit: &ShapeHit) -> Vec4 {
+        let (n, _, _) = CUBOID_SIDES[hit.part];
+        Vec4::new(n[0], n[1], n[2], 0.0)
+    }
+
+    fn area(&self) -> f64 {
+        (0..6).map(|side| self.side_area(side)).sum()
+    }
+
+    fn sample_point(&self, u: f64, v: f64) -> Option<SurfaceSample> {
+        let areas: Vec<f64> = (0..6).map(|side| self.side_area(side)).collect();
+        let (side, u) = pick_part(&areas, u)?;
+
+        let (n, t, b) = CUBOID_SIDES[side];
+        let (s, r) = (2.0 * u - 1.0, 2.0 * v - 1.0);
+        let coordinate = |axis: usize| (n[axis] + s * t[axis] + r * b[axis]) * self.half_size(axis);
+
+        Some(SurfaceSample {
+            position: Vec4::new(coordinate(0), coordinate(1), coordinate(2), 1.0),
+            normal: Vec4::new(n[0], n[1], n[2], 0.0)
+        })
+    }
+}
+
+#[allow(dead_code)]
+impl Cylinder {
+    /// Areas of the side, the top and the bottom.
+    fn part_areas(&self) -> [f64; 3] {
+        let cap = PI * self.radius * self.radius;
+        [2.0 * PI * self.radius * self.height, cap, cap]
+    }
+}
+
+impl Shape for Cylinder {
+    fn intersect(&self, origin: &Vec4, direction: &Vec4, max_distance: f64) -> Option<ShapeHit> {
+        let h = self.height / 2.0;
+        let mut closest: Option<(f64, usize)> = None;
+        let mut consider = |t: f64, part: usize| {
+            if t > EPSILON && t < max_distance && closest.is_none_or(|(closest_t, _)| t < closest_t) {
+                closest = Some((t, part));
+            }
+        };
+
+        // The side is an infinite cylinder in the xz plane cut off at both caps.
+        let a = direction.x * direction.x + direction.z * direction.z;
+        let b = origin.x * direction.x + origin.z * direction.z;
+        let c = origin.x * origin.x + origin.z * origin.z - self.radius * self.radius;
+        let discriminant = b * b - a * c;
+        if a > 0.0 && discriminant >= 0.0 {
+            for &t in &[(-b - discriminant.sqrt()) / a, (-b + discriminant.sqrt()) / a] {
+                if (origin.y + t * direction.y).abs() <= h {
+                    consider(t, 0);
+                }
+            }
+        }
+
+        if direction.y != 0.0 {
+            for &(y, part) in &[(h, 1), (-h, 2)] {
+                let t = (y - origin.y) / direction.y;
+                let (x, z) = (origin.x + t * direction.x, origin.z + t * direction.z);
+                if x * x + z * z <= self.radius * self.radius {
+                    consider(t, part);
+                }
+            }
+        }
+
+        let (t, part) = closest?;
+        let position = origin.clone() + t * direction.clone();
+        let uv = if part == 0 {
+            let u = (-position.z).atan2(position.x) / (2.0 * PI);
+            (u.rem_euclid(1.0), (position.y + h) / self.height)
+        }
+        else {
+            cap_texture_coordinate(&position, self.radius, part == 1)
+        };
+        Some(ShapeHit { t, position, part, uv })
+    }
+
+    fn bounds(&self) -> Aabb {
+        let (r, h) = (self.radius, self.height / 2.0);
+        Aabb::from_points(&[&Vec4::new(-r, -h, -r, 1.0), &Vec4::new(r, h, r, 1.0)])
+    }
+
+    fn normal(&self, hit: &ShapeHit) -> Vec4 {
+        match hit.part {
+            0 => Vec4::new(hit.position.x, 0.0, hit.position.z, 0.0).normalize(),
+            1 => Vec4::new(0.0, 1.0, 0.0, 0.0),
+            _ => Vec4::new(0.0, -1.0, 0.0, 0.0)
+        }
+    }
+
+    fn area(&self) -> f64 {
+        self.part_areas().iter().sum()
+    }
+
+    fn sample_point(&self, u: f64, v: f64) -> Option<SurfaceSample> {
+        let h = self.height / 2.0;
+        let (part, u) = pick_part(&self.part_areas(), u)?;
+
+        if part == 0 {
+            let phi = 2.0 * PI * u;
+            let normal = Vec4::new(phi.cos(), 0.0, -phi.sin(), 0.0);
+            let position = (self.radius * normal.clone()).into_point() + Vec4::new(0.0, -h + v * self.height, 0.0, 0.0);
+            return Some(SurfaceSample { position, normal });
+        }
+
+        let (x, z) = concentric_disk(u, v);
+        let y = if part == 1 { h } else { -h };
+        Some(SurfaceSample {
+            position: Vec4::new(self.radius * x, y, self.radius * z, 1.0),
+            normal: Vec4::new(0.0, y.signum(), 0.0, 0.0)
+        })
+    }
+}
+
+impl Shape for Disk {
+    fn intersect(&self, origin: &Vec4, direction: &Vec4, max_distance: f64) -> Option<ShapeHit> {
+        if direction.y == 0.0 {
+            return None;
+        }
+
+        let t = -origin.y / direction.y;
+        if t <= EPSILON || t >= max_distance {
+            return None;
+        }
+
+        let position = origin.clone() + t * direction.clone();
+        if position.x * position.x + position.z * position.z > self.radius * self.radius {
+            return None;
+        }
+
+        let uv = cap_texture_coordinate(&position, self.radius, true);
+        Some(ShapeHit { t, position, part: 0, uv })
+    }
+
+    fn bounds(&self) -> Aabb {
+        let r = self.radius;
+        Aabb::from_points(&[&Vec4::new(-r, 0.0, -r, 1.0), &Vec4::new(r, 0.0, r, 1.0)])
+    }
+
+    fn normal(&self, _hit: &ShapeHit) -> Vec4 {
+        Vec4::new(0.0, 1.0, 0.0, 0.0)
+    }
+
+    fn area(&self) -> f64 {
+        PI * self.radius * self.radius
+    }
+
+    fn sample_point(&self, u: f64, v: f64) -> Option<SurfaceSample> {
+        let (x, z) = concentric_disk(u, v);
+        Some(SurfaceSample {
+            position: Vec4::new(self.radius * x, 0.0, self.radius * z, 1.0),
+            normal: Vec4::new(0.0, 1.0, 0.0, 0.0)
+        })
+    }
+}
+
+/// Texture coordinates on a horizontal circle facing up or down, whose
+/// inscribed circle is covered by the texture like the caps of `Mesh::cylinder`.
+fn cap_texture_coordinate(position: &Vec4, radius: f64, up: bool) -> (f64, f64) {
+    let facing = if up { 1.0 } else { -1.0 };
+    (0.5 + position.x / (2.0 * radius), 0.5 - facing * position.z / (2.0 * radius))
+}
+
+/// Picks one of the parts with a probability proportional to its area and
+/// rescales `u` to [0, 1) within that part's share.
+#[allow(dead_code)]
+fn pick_part(areas: &[f64], u: f64) -> Option<(usize, f64)> {
+    let total: f64 = areas.iter().sum();
+    if total <= 0.0 {
+        return None;
+    }
+
+    let mut start = 0.0;
+    for (part, &area) in areas.iter().enumerate() {
+        let end = start + area / total;
+        if u < end || part == areas.len() - 1 {
+            return Some((part, ((u - start) / (end - start)).clamp(0.0, 1.0)));
+        }
+        start = end;
+    }
+    None
+}
+
+/// Implementation of the Möller-Trumbore intersection algorithm
+/// Pseude code has been taken from Wikipedia and translated into Rust:
+/// https://en.wikipedia.org/wiki/M%C3%B6ller%E2%80%93Trumbore_intersection_algorithm
+pub fn triangle_intersection(v1: Vec4, v2: Vec4, v3: Vec4, o: Vec4, d: Vec4) -> Option<TriangleHit> {
+    // TODO: Use global epsilon?
+    let epsilon: f64 = 0.000001;
+
+    // find vectors for two edges sharing v1
+    let e1 = v2.clone() - v1.clone();
+    let e2 = v3.clone() - v1.clone();
+
+    // begin calculating determinant - also used to calculate u parameter
+    let p = Vec4::cross(&d, &e2);
+
+    // if determinant is near zero, ray lies in plane of triangle or ray is parallel to plane of triangle
+    let det = Vec4::dot(&e1, &p);
+    if det > -epsilon && det < epsilon {
+        return None;
+    }
+
+    // calculate invert determinant
+    let inv_det = 1.0 / det;
+
+    // calculate distance from v1 to ray origin
+    let t = o.clone() - v1.clone();
+
+    // calculate u parameter and test bound
+    // and abort if the intersection lies outside of the triangle
+    let u = Vec4::dot(&t, &p) * inv_det;
+    if u < 0.0 || u > 1.0 {
+        return None;
+    }
+
+    // prepare to test v parameter
+    let q = Vec4::cross(&t, &e1);
+
+    // calculate V parameter and test bound
+    let v = Vec4::dot(&d, &q) * inv_det;
+
+    // the intersection lies outside of the triangle
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+
+    // now check again if we've found an intersection and calculate the result
+    let t = Vec4::dot(&e2, &q) * inv_det;
+    if t > epsilon {
+        return Some(TriangleHit { t, u, v })
+    }
+
+    // no hit, no win
+    None
+}
+
+/// Checks that sampled points lie on the shape's surface by intersecting
+/// it from just outside of each point.
+#[cfg(test)]
+fn check_sample_points(shape: &dyn Shape) {
+    for i in 1..10 {
+        for j in 1..10 {
+            let sample = shape.sample_point(i as f64 / 10.0, j as f64 / 10.0).unwrap();
+            let origin = sample.position.clone() + 0.01 * sample.normal.clone();
+            let hit = shape.intersect(&origin, &(-1.0 * sample.normal.clone()), f64::INFINITY).unwrap();
+            assert!((hit.t - 0.01).abs() < 1e-9);
+            assert!(Vec4::epsilon_compare(shape.normal(&hit), sample.normal, 1e-9));
+        }
+    }
+}
+
+#[test]
+fn test_sphere() {
+    let sphere = Sphere { radius: 2.0 };
+    let origin = Vec4::new(0.0, 0.0, 10.0, 1.0);
+    let direction = Vec4::new(0.0, 0.0, -1.0, 0.0);
+
+    let hit = sphere.intersect(&origin, &direction, f64::INFINITY).unwrap();
+    assert_eq!(hit.t, 8.0);
+    assert_eq!(sphere.normal(&hit), Vec4::new(0.0, 0.0, 1.0, 0.0));
+    assert!(sphere.intersect(&origin, &direction, 8.0).is_none());
+    assert!(sphere.intersect(&origin, &Vec4::new(0.0, 1.0, 0.0, 0.0), f64::INFINITY).is_none());
+
+    // Rays starting inside hit the far side.
+    let hit = sphere.intersect(&Vec4::new(0.0, 0.0, 0.0, 1.0), &direction, f64::INFINITY).unwrap();
+    assert_eq!(hit.t, 2.0);
+    assert_eq!(sphere.normal(&hit), direction);
+
+    // The texture wraps around it like around `Mesh::uv_sphere`.
+    let hit = sphere.intersect(&Vec4::new(10.0, 0.0, 0.0, 1.0), &Vec4::new(-1.0, 0.0, 0.0, 0.0), f64::INFINITY).unwrap();
+    assert_eq!(hit.uv, (0.0, 0.5));
+
+    // A finely tessellated sphere is nearly hit at the same distance.
+    let tessellated = TriangleMesh::new(Mesh::uv_sphere(2.0, 64, 32));
+    let hit = tessellated.intersect(&origin, &Vec4::new(0.01, 0.02, -1.0, 0.0), f64::INFINITY).unwrap();
+    let exact = sphere.intersect(&origin, &Vec4::new(0.01, 0.02, -1.0, 0.0), f64::INFINITY).unwrap();
+    assert!((hit.t - exact.t).abs() < 0.01);
+    assert!((tessellated.area() - sphere.area()).abs() < 0.01 * sphere.area());
+
+    check_sample_points(&sphere);
+    check_sample_points(&tessellated);
+}
+
+#[test]
+fn test_plane() {
+    let plane = Plane { normal: Vec4::new(0.0, 1.0, 0.0, 0.0) };
+    let origin = Vec4::new(1.0, 3.0, 0.0, 1.0);
+
+    let hit = plane.intersect(&origin, &Vec4::new(0.0, -1.0, 0.0, 0.0), f64::INFINITY).unwrap();
+    assert_eq!(hit.t, 3.0);
+    assert_eq!(hit.position, Vec4::new(1.0, 0.0, 0.0, 1.0));
+    assert!(plane.intersect(&origin, &Vec4::new(1.0, 0.0, 0.0, 0.0), f64::INFINITY).is_none());
+    assert!(plane.intersect(&origin, &Vec4::new(0.0, 1.0, 0.0, 0.0), f64::INFINITY).is_none());
+
+    let bounds = plane.bounds();
+    assert_eq!((bounds.min.y, bounds.max.y), (0.0, 0.0));
+    assert!(bounds.max.x.is_infinite() && bounds.min.z.is_infinite());
+    assert!(plane.area().is_infinite());
+    assert!(plane.sample_point(0.5, 0.5).is_none());
+}
+
+#[test]
+fn test_cuboid() {
+    let cuboid = Cuboid { size: Vec4::new(2.0, 4.0, 6.0, 0.0) };
+
+    let hit = cuboid.intersect(&Vec4::new(0.0, 10.0, 0.0, 1.0), &Vec4::new(0.0, -1.0, 0.0, 0.0), f64::INFINITY).unwrap();
+    assert_eq!((hit.t, hit.part), (8.0, 2));
+    assert_eq!(cuboid.normal(&hit), Vec4::new(0.0, 1.0, 0.0, 0.0));
+    assert_eq!(hit.uv, (0.5, 0.5));
+
+    let hit = cuboid.intersect(&Vec4::new(-5.0, 0.5, 0.5, 1.0), &Vec4::new(1.0, 0.0, 0.0, 0.0), f64::INFINITY).unwrap();
+    assert_eq!((hit.t, hit.part), (4.0, 1));
+
+    // Rays starting inside hit the side they leave through.
+    let hit = cuboid.intersect(&Vec4::new(0.0, 0.0, 0.0, 1.0), &Vec4::new(0.0, 0.0, -1.0, 0.0), f64::INFINITY).unwrap();
+    assert_eq!((hit.t, hit.part), (3.0, 5));
+
+    assert!(cuboid.intersect(&Vec4::new(0.0, 10.0, 0.0, 1.0), &Vec4::new(0.0, 1.0, 0.0, 0.0), f64::INFINITY).is_none());
+    assert!(cuboid.intersect(&Vec4::new(2.0, 10.0, 0.0, 1.0), &Vec4::new(0.0, -1.0, 0.0, 0.0), f64::INFINITY).is_none());
+
+    assert_eq!(cuboid.area(), 2.0 * (8.0 + 24.0 + 12.0));
+    assert_eq!(cuboid.bounds().max, Vec4::new(1.0, 2.0, 3.0, 1.0));
+    check_sample_points(&cuboid);
+}
+
+#[test]
+fn test_cylinder() {
+    let cylinder = Cylinder { radius: 1.0, height: 2.0 };
+
+    let hit = cylinder.intersect(&Vec4::new(5.0, 0.5, 0.0, 1.0), &Vec4::new(-1.0, 0.0, 0.0, 0.0), f64::INFINITY).unwrap();
+    assert_eq!((hit.t, hit.part), (4.0, 0));
+    assert_eq!(cylinder.normal(&hit), Vec4::new(1.0, 0.0, 0.0, 0.0));
+    assert_eq!(hit.uv, (0.0, 0.75));
+
+    let hit = cylinder.intersect(&Vec4::new(0.5, 5.0, 0.0, 1.0), &Vec4::new(0.0, -1.0, 0.0, 0.0), f64::INFINITY).unwrap();
+    assert_eq!((hit.t, hit.part), (4.0, 1));
+    let hit = cylinder.intersect(&Vec4::new(0.5, 0.0, 0.0, 1.0), &Vec4::new(0.0, -1.0, 0.0, 0.0), f64::INFINITY).unwrap();
+    assert_eq!((hit.t, hit.part), (1.0, 2));
+    assert_eq!(cylinder.normal(&hit), Vec4::new(0.0, -1.0, 0.0, 0.0));
+
+    // Passing above the top cap or beside the side.
+    assert!(cylinder.intersect(&Vec4::new(5.0, 1.5, 0.0, 1.0), &Vec4::new(-1.0, 0.0, 0.0, 0.0), f64::INFINITY).is_none());
+    assert!(cylinder.intersect(&Vec4::new(1.5, 5.0, 0.0, 1.0), &Vec4::new(0.0, -1.0, 0.0, 0.0), f64::INFINITY).is_none());
+
+    assert!((cylinder.area() - 6.0 * PI).abs() < 1e-12);
+    check_sample_points(&cylinder);
+}
+
+#[test]
+fn test_disk() {
+    let disk = Disk { radius: 2.0 };
+
+    let hit = disk.intersect(&Vec4::new(1.0, 3.0, -1.0, 1.0), &Vec4::new(0.0, -1.0, 0.0, 0.0), f64::INFINITY).unwrap();
+    assert_eq!(hit.t, 3.0);
+    assert_eq!(hit.uv, (0.75, 0.75));
+    // Disks can be hit from below as well.
+    assert!(disk.intersect(&Vec4::new(1.0, -3.0, 0.0, 1.0), &Vec4::new(0.0, 1.0, 0.0, 0.0), f64::INFINITY).is_some());
+    assert!(disk.intersect(&Vec4::new(2.0, 3.0, 1.0, 1.0), &Vec4::new(0.0, -1.0, 0.0, 0.0), f64::INFINITY).is_none());
+
+    assert!((disk.area() - 4.0 * PI).abs() < 1e-12);
+    check_sample_points(&disk);
+}
+
+#[test]
+fn test_bvh_matches_brute_force() {
+    use mesh;
+
+    let mesh = mesh::Mesh::try_load_from_off("./meshes/teapot.off", mesh::PolygonWinding::Clockwise).unwrap();
+    let shape = TriangleMesh::new(mesh);
+    let origin = Vec4::new(0.3, 0.7, 10.0, 1.0);
+
+    for i in 0..48 {
+        for j in 0..48 {
+            let target = Vec4::new(-4.0 + i as f64 / 6.0, -2.0 + j as f64 / 8.0, 0.0, 1.0);
+            let direction = (target - origin.clone()).normalize();
+
+            let mut brute_force: Option<f64> = None;
+            for face in &shape.mesh.faces {
+                let v = &shape.mesh.vertices;
+                if let Some(hit) = triangle_intersection(v[face.a].clone(), v[face.b].clone(), v[face.c].clone(),
+                                                         origin.clone(), direction.clone()) {
+                    if brute_force.is_none_or(|t| hit.t < t) {
+                        brute_force = Some(hit.t);
+                    }
+                }
+            }
+
+            let bvh = shape.intersect(&origin, &direction, f64::INFINITY).map(|hit| hit.t);
+
+            assert_eq!(brute_force.is_some(), bvh.is_some());
+            if let (Some(a), Some(b)) = (brute_force, bvh) {
+                assert!((a - b).abs() < 1e-9);
+            }
+
+            let any = shape.is_hit(&origin, &direction, f64::INFINITY);
+            assert_eq!(any, brute_force.is_some());
+        }
+    }
+}
+
@@ -0,0 +1,230 @@
+use std::collections::HashMap;
+use std::io::BufWriter;
+use std::io::prelude::*;
+use std::fs::File;
+use algebra::Vec4;
+use mesh::{ Face, Mesh, MeshError, PolygonWinding, Token, position_key, tokenize };
+
+/// Size of the header of binary STL files, followed by the number of triangles.
+const BINARY_HEADER_SIZE: usize = 80;
+/// Normal, three vertices and a 16 bit attribute.
+const BINARY_TRIANGLE_SIZE: usize = 50;
+
+/// Merges vertices at identical positions, as STL files store the
+/// vertices of every triangle separately.
+struct Welder {
+    vertices: Vec<Vec4>,
+    indices: HashMap<[u64; 3], usize>
+}
+
+impl Welder {
+    fn new() -> Self {
+        Welder {
+            vertices: Vec::new(),
+            indices: HashMap::new()
+        }
+    }
+
+    fn add(&mut self, vertex: Vec4) -> usize {
+        let key = position_key(&vertex);
+        let vertices = &mut self.vertices;
+        *self.indices.entry(key).or_insert_with(|| {
+            vertices.push(vertex);
+            vertices.len() - 1
+        })
+    }
+}
+
+#[allow(dead_code)]
+impl Mesh {
+    /// Loads a mesh from an ASCII or binary STL file. Vertices at identical
+    /// positions are merged and the facet normals are ignored.
+    /// See: https://en.wikipedia.org/wiki/STL_(file_format)
+    pub fn try_load_from_stl(path: &str, polygon_winding: PolygonWinding) -> Result<Self, MeshError> {
+        let mut f = File::open(path)?;
+        let mut data: Vec<u8> = Vec::new();
+        f.read_to_end(&mut data)?;
+
+        // Binary files may start with "solid" as well, so check whether the
+        // size matches the number of triangles first.
+        let triangles = if is_binary_stl(&data) {
+            parse_binary_stl(&data)
+        }
+        else if data.starts_with(b"solid") {
+            parse_ascii_stl(&String::from_utf8_lossy(&data))?
+        }
+        else {
+            return Err(MeshError::Parse { line: 1, column: 1, message: "Unknown STL format".to_string() });
+        };
+
+        if triangles.is_empty() {
+            return Err(MeshError::UnsupportedFeature("Meshes without faces are not supported".to_string()));
+        }
+
+        let mut welder = Welder::new();
+        let mut faces: Vec<Face> = Vec::with_capacity(triangles.len());
+        for [v1, v2, v3] in triangles {
+            let (a, b, c) = (welder.add(v1), welder.add(v2), welder.add(v3));
+            faces.push(match polygon_winding {
+                PolygonWinding::Clockwise => Face { a, b, c },
+                PolygonWinding::CounterClockwise => Face { a, b: c, c: b },
+            });
+        }
+
+        Ok(Mesh::new(welder.vertices, faces))
+    }
+
+    /// Saves the mesh as binary STL file. The facet normals are calculated
+    /// from the vertices, degenerate faces get a zero normal.
+    pub fn save_as_stl(&self, path: &str) -> Result<(), MeshError> {
+        let f = File::create(path)?;
+        let mut f = BufWriter::new(f);
+
+        let mut header = [0u8; BINARY_HEADER_SIZE];
+        let description = b"Binary STL written by raytracer";
+        header[..description.len()].copy_from_slice(description);
+
+        let mut data: Vec<u8> = Vec::with_capacity(BINARY_HEADER_SIZE + 4 + self.faces.len() * BINARY_TRIANGLE_SIZE);
+        data.extend_from_slice(&header);
+        data.extend_from_slice(&(self.faces.len() as u32).to_le_bytes());
+
+        for face in &self.faces {
+            let normal = self.face_normal(face);
+            let normal = if normal.x.is_finite() && normal.y.is_finite() && normal.z.is_finite() {
+                normal
+            }
+            else {
+                Vec4::new(0.0, 0.0, 0.0, 0.0)
+            };
+
+            for vector in &[&normal, &self.vertices[face.a], &self.vertices[face.b], &self.vertices[face.c]] {
+                for &value in &[vector.x, vector.y, vector.z] {
+                    data.extend_from_slice(&(value as f32).to_le_bytes());
+                }
+            }
+            data.extend_from_slice(&[0, 0]);
+        }
+
+        f.write_all(&data)?;
+        f.flush()?;
+        Ok(())
+    }
+}
+
+fn is_binary_stl(data: &[u8]) -> bool {
+    if data.len() < BINARY_HEADER_SIZE + 4 {
+        return false;
+    }
+    let count = &data[BINARY_HEADER_SIZE..BINARY_HEADER_SIZE + 4];
+    let count = u32::from_le_bytes([count[0], count[1], count[2], count[3]]) as usize;
+    data.len() == BINARY_HEADER_SIZE + 4 + count * BINARY_TRIANGLE_SIZE
+}
+
+fn parse_binary_stl(data: &[u8]) -> Vec<[Vec4; 3]> {
+    let read_f32 = |offset: usize| {
+        f32::from_le_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]]) as f64
+    };
+    let read_vertex = |offset: usize| Vec4::new(read_f32(offset), read_f32(offset + 4), read_f32(offset + 8), 1.0);
+
+    let mut triangles: Vec<[Vec4; 3]> = Vec::new();
+    let mut offset = BINARY_HEADER_SIZE + 4;
+    while offset + BINARY_TRIANGLE_SIZE <= data.len() {
+        // Skip the normal.
+        triangles.push([read_vertex(offset + 12), read_vertex(offset + 24), read_vertex(offset + 36)]);
+        offset += BINARY_TRIANGLE_SIZE;
+    }
+    triangles
+}
+
+fn parse_ascii_stl(text: &str) -> Result<Vec<[Vec4; 3]>, MeshError> {
+    let mut tokens = text.lines().enumerate().flat_map(|(index, line)| tokenize(line, index + 1));
+    let mut next = |expected: &str| -> Result<Token, MeshError> {
+        match tokens.next() {
+            Some(token) if expected.is_empty() || token.text == expected => Ok(token),
+            Some(token) => Err(token.error(&format!("Expected {} but found {}", expected, token.text))),
+            None => Err(MeshError::UnexpectedEof)
+        }
+    };
+
+    let mut triangles: Vec<[Vec4; 3]> = Vec::new();
+
+    let solid = next("solid")?;
+    loop {
+        let token = next("")?;
+        match token.text {
+            "facet" => {}
+            "endsolid" => return Ok(triangles),
+            // The solid's name is optional and may consist of multiple words.
+            _ if token.line == solid.line => continue,
+            text => return Err(token.error(&format!("Expected facet or endsolid but found {}", text)))
+        }
+
+        next("normal")?;
+        for _ in 0..3 {
+            next("")?;
+        }
+        next("outer")?;
+        next("loop")?;
+
+        let mut triangle = [Vec4::new(0.0, 0.0, 0.0, 1.0), Vec4::new(0.0, 0.0, 0.0, 1.0), Vec4::new(0.0, 0.0, 0.0, 1.0)];
+        for vertex in triangle.iter_mut() {
+            next("vertex")?;
+            vertex.x = next("")?.parse::<f64>()?;
+            vertex.y = next("")?.parse::<f64>()?;
+            vertex.z = next("")?.parse::<f64>()?;
+        }
+        triangles.push(triangle);
+
+        next("endloop")?;
+        next("endfacet")?;
+    }
+}
+
+#[test]
+fn test_stl() {
+    let ascii = Mesh::try_load_from_stl("testdata/meshes/tetrahedron_ascii.stl", PolygonWinding::Clockwise).unwrap();
+    let binary = Mesh::try_load_from_stl("testdata/meshes/tetrahedron_binary.stl", PolygonWinding::Clockwise).unwrap();
+
+    // The 12 vertices of the 4 triangles are welded into the 4 corners.
+    for mesh in &[&ascii, &binary] {
+        assert_eq!(mesh.vertices.len(), 4);
+        assert_eq!(mesh.faces.len(), 4);
+    }
+    assert_eq!(ascii.vertices, binary.vertices);
+
+    // All normals point outwards.
+    for face in &ascii.faces {
+        let outwards = ascii.vertices[face.a].clone() - Vec4::new(0.25, 0.25, 0.25, 1.0);
+        assert!(Vec4::dot(&ascii.face_normal(face), &outwards) > 0.0);
+    }
+}
+
+#[test]
+fn test_stl_round_trip() {
+    let mesh = Mesh::try_load_from_off("testdata/meshes/polygons.off", PolygonWinding::Clockwise).unwrap();
+    let path = ::std::env::temp_dir().join("raytracer_stl_round_trip.stl");
+    let path = path.to_str().unwrap();
+    mesh.save_as_stl(path).unwrap();
+
+    // Welding restores the shared vertices, but in the order of their first use.
+    let loaded = Mesh::try_load_from_stl(path, PolygonWinding::Clockwise).unwrap();
+    assert_eq!(loaded.vertices.len(), mesh.vertices.len());
+    assert_eq!(loaded.faces.len(), mesh.faces.len());
+    for (a, b) in loaded.faces.iter().zip(&mesh.faces) {
+        assert_eq!(loaded.vertices[a.a], mesh.vertices[b.a]);
+        assert_eq!(loaded.vertices[a.b], mesh.vertices[b.b]);
+        assert_eq!(loaded.vertices[a.c], mesh.vertices[b.c]);
+    }
+}
+
+#[test]
+fn test_bad_stl() {
+    assert!(Mesh::try_load_from_stl("testdata/meshes/bad_1.stl", PolygonWinding::Clockwise).is_err());
+    // The misspelled second facet must not be skipped.
+    match Mesh::try_load_from_stl("testdata/meshes/bad_2.stl", PolygonWinding::Clockwise) {
+        Err(MeshError::Parse { line: 9, column: 3, .. }) => {}
+        result => panic!("Unexpected result {:?}", result.map(|_| ()))
+    }
+    assert!(Mesh::try_load_from_stl("testdata/meshes/good.off", PolygonWinding::Clockwise).is_err());
+    assert!(Mesh::try_load_from_stl("testdata/non_existing_mesh.stl", PolygonWinding::Clockwise).is_err());
+}
@@ -0,0 +1,183 @@
+use std::path::Path;
+use color::Rgb;
+use pixmap::Pixmap;
+
+/// How a texture is sampled between the centers of its texels.
+#[allow(dead_code)]
+#[derive(Clone,Copy,PartialEq,Debug)]
+pub enum TextureFilter {
+    Nearest,
+    Bilinear
+}
+
+/// How texture coordinates outside of [0, 1] are mapped onto the texture.
+#[allow(dead_code)]
+#[derive(Clone,Copy,PartialEq,Debug)]
+pub enum TextureWrap {
+    /// Tiles the texture.
+    Repeat,
+    /// Repeats the texels at the border.
+    Clamp,
+    /// Tiles the texture, flipping every other tile.
+    Mirror
+}
+
+/// Image that is mapped onto surfaces using texture coordinates. (0, 0) is
+/// the lower left corner of the image and (1, 1) the upper right one.
+#[allow(dead_code)]
+#[derive(Clone,PartialEq,Debug)]
+pub struct Texture {
+    pub width: u32,
+    pub height: u32,
+    /// Row by row, starting at the bottom.
+    texels: Vec<Rgb>,
+    pub filter: TextureFilter,
+    pub wrap: TextureWrap
+}
+
+#[allow(dead_code)]
+impl Texture {
+    /// Creates a bilinearly filtered, repeating texture from a pixmap.
+    pub fn from_pixmap(pixmap: &Pixmap) -> Self {
+        let mut texels = Vec::with_capacity((pixmap.width * pixmap.height) as usize);
+        for y in 0..pixmap.height {
+            for x in 0..pixmap.width {
+                texels.push(Rgb::from_color(&pixmap.get_color(x, y)));
+            }
+        }
+
+        Texture {
+            width: pixmap.width,
+            height: pixmap.height,
+            texels,
+            filter: TextureFilter::Bilinear,
+            wrap: TextureWrap::Repeat
+        }
+    }
+
+    /// Loads a texture from an image in any format `Pixmap` supports, which
+    /// is chosen by the file's extension.
+    pub fn try_load(path: &str) -> Result<Self, String> {
+        let extension = Path::new(path).extension().and_then(|extension| extension.to_str());
+        let pixmap = match extension.map(|extension| extension.to_lowercase()) {
+            Some(ref extension) if extension == "ppm" => Pixmap::try_load_from_ppm(path.to_string())?,
+            _ => return Err(format!("Unsupported image format of {}", path))
+        };
+        if pixmap.width == 0 || pixmap.height == 0 {
+            return Err(format!("{} does not contain any pixels", path));
+        }
+        Ok(Texture::from_pixmap(&pixmap))
+    }
+
+    /// Color at the given texture coordinates.
+    pub fn sample(&self, u: f64, v: f64) -> Rgb {
+        // Texel centers lie at half-integer coordinates.
+        let x = u * self.width as f64;
+        let y = v * self.height as f64;
+
+        match self.filter {
+            TextureFilter::Nearest => self.texel(x.floor() as i64, y.floor() as i64),
+            TextureFilter::Bilinear => {
+                let (x, y) = (x - 0.5, y - 0.5);
+                let (x0, y0) = (x.floor() as i64, y.floor() as i64);
+                let (fx, fy) = (x - x.floor(), y - y.floor());
+
+                let bottom = (1.0 - fx) * self.texel(x0, y0) + fx * self.texel(x0 + 1, y0);
+                let top = (1.0 - fx) * self.texel(x0, y0 + 1) + fx * self.texel(x0 + 1, y0 + 1);
+                (1.0 - fy) * bottom + fy * top
+            }
+        }
+    }
+
+    /// Texel at integer coordinates, which may lie outside of the texture.
+    fn texel(&self, x: i64, y: i64) -> Rgb {
+        let x = self.wrap.apply(x, self.width as i64);
+        let y = self.wrap.apply(y, self.height as i64);
+        self.texels[(y * self.width as i64 + x) as usize]
+    }
+}
+
+impl TextureWrap {
+    /// Maps an index onto the range from 0 to `size` - 1.
+    fn apply(self, index: i64, size: i64) -> i64 {
+        match self {
+            TextureWrap::Repeat => index.rem_euclid(size),
+            TextureWrap::Clamp => index.clamp(0, size - 1),
+            TextureWrap::Mirror => {
+                let index = index.rem_euclid(2 * size);
+                if index < size { index } else { 2 * size - 1 - index }
+            }
+        }
+    }
+}
+
+/// 2x2 texture with black and white texels on the bottom row and red and
+/// green texels on the top row.
+#[cfg(test)]
+fn test_texture() -> Texture {
+    use color::Color;
+
+    let mut pixmap = Pixmap::new(2, 2);
+    pixmap.draw(0, 0, Color { r: 0, g: 0, b: 0 });
+    pixmap.draw(1, 0, Color { r: 255, g: 255, b: 255 });
+    pixmap.draw(0, 1, Color { r: 255, g: 0, b: 0 });
+    pixmap.draw(1, 1, Color { r: 0, g: 255, b: 0 });
+    Texture::from_pixmap(&pixmap)
+}
+
+#[test]
+fn test_texture_nearest() {
+    let mut texture = test_texture();
+    texture.filter = TextureFilter::Nearest;
+    assert_eq!(texture.sample(0.25, 0.25), Rgb::black());
+    assert_eq!(texture.sample(0.75, 0.25), Rgb::white());
+    assert_eq!(texture.sample(0.25, 0.75), Rgb::new(1.0, 0.0, 0.0));
+    assert_eq!(texture.sample(0.99, 0.99), Rgb::new(0.0, 1.0, 0.0));
+}
+
+#[test]
+fn test_texture_bilinear() {
+    let texture = test_texture();
+    // Texel centers return the texels' colors.
+    assert_eq!(texture.sample(0.25, 0.25), Rgb::black());
+    assert_eq!(texture.sample(0.75, 0.75), Rgb::new(0.0, 1.0, 0.0));
+    // The center of the texture averages all four texels.
+    assert_eq!(texture.sample(0.5, 0.5), Rgb::new(0.5, 0.5, 0.25));
+    assert_eq!(texture.sample(0.5, 0.25), Rgb::new(0.5, 0.5, 0.5));
+}
+
+#[test]
+fn test_texture_wrap() {
+    let mut texture = test_texture();
+    texture.filter = TextureFilter::Nearest;
+
+    texture.wrap = TextureWrap::Repeat;
+    assert_eq!(texture.sample(1.25, 0.25), Rgb::black());
+    assert_eq!(texture.sample(-0.25, 0.25), Rgb::white());
+
+    texture.wrap = TextureWrap::Clamp;
+    assert_eq!(texture.sample(1.25, 0.25), Rgb::white());
+    assert_eq!(texture.sample(-3.0, 0.25), Rgb::black());
+
+    texture.wrap = TextureWrap::Mirror;
+    assert_eq!(texture.sample(1.25, 0.25), Rgb::white());
+    assert_eq!(texture.sample(1.75, 0.25), Rgb::black());
+    assert_eq!(texture.sample(-0.25, 0.25), Rgb::black());
+
+    // Repeating textures blend across the border, clamped ones do not.
+    texture.filter = TextureFilter::Bilinear;
+    texture.wrap = TextureWrap::Repeat;
+    assert_eq!(texture.sample(0.0, 0.25), Rgb::new(0.5, 0.5, 0.5));
+    texture.wrap = TextureWrap::Clamp;
+    assert_eq!(texture.sample(0.0, 0.25), Rgb::black());
+}
+
+#[test]
+fn test_texture_load() {
+    let texture = Texture::try_load("testdata/meshes/red.ppm").unwrap();
+    assert_eq!((texture.width, texture.height), (2, 2));
+    assert_eq!(texture.sample(0.25, 0.25), Rgb::new(1.0, 0.0, 0.0));
+
+    assert!(Texture::try_load("testdata/meshes/good.off").is_err());
+    assert!(Texture::try_load("testdata/non_existing_texture.ppm").is_err());
+}